@@ -0,0 +1,437 @@
+//! Static analysis of a ROM: walks its control flow from the program start
+//! without running it, to find what it needs from an interpreter.
+
+use alloc::{
+    collections::{BTreeMap, BTreeSet},
+    vec,
+    vec::Vec,
+};
+use core::{fmt, ops::RangeInclusive};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{chip_8::PROGRAM_START_LOCATION, Instruction, Quirks};
+
+/// Chip8 dialects, each a superset of the last
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The platform and description of an extension opcode, which this interpreter
+/// doesn't run
+pub(crate) fn extension(opcode: u16) -> Option<(Platform, &'static str)> {
+    let nn = opcode & 0x00FF;
+    let extension = match opcode >> 12 {
+        0x0 if opcode & 0xFFF0 == 0x00C0 && opcode != 0x00C0 => {
+            (Platform::SuperChip, "scroll down")
+        }
+        0x0 if opcode & 0xFFF0 == 0x00D0 => (Platform::XoChip, "scroll up"),
+        0x0 if opcode == 0x00FB => (Platform::SuperChip, "scroll right"),
+        0x0 if opcode == 0x00FC => (Platform::SuperChip, "scroll left"),
+        0x0 if opcode == 0x00FD => (Platform::SuperChip, "exit"),
+        0x0 if opcode == 0x00FE => (Platform::SuperChip, "low resolution"),
+        0x0 if opcode == 0x00FF => (Platform::SuperChip, "high resolution"),
+        0x5 if opcode & 0xF == 0x2 => (Platform::XoChip, "save register range"),
+        0x5 if opcode & 0xF == 0x3 => (Platform::XoChip, "load register range"),
+        0xD if opcode & 0xF == 0x0 => (Platform::SuperChip, "16x16 sprite"),
+        0xF if opcode == 0xF000 => (Platform::XoChip, "long load of I"),
+        0xF if opcode == 0xF002 => (Platform::XoChip, "load audio pattern"),
+        0xF if nn == 0x01 => (Platform::XoChip, "select drawing planes"),
+        0xF if nn == 0x30 => (Platform::SuperChip, "large font character"),
+        0xF if nn == 0x3A => (Platform::XoChip, "set pitch"),
+        0xF if nn == 0x75 => (Platform::SuperChip, "save flags"),
+        0xF if nn == 0x85 => (Platform::SuperChip, "load flags"),
+        _ => return None,
+    };
+    Some(extension)
+}
+
+/// What a static walk of a ROM found
+#[derive(Debug, Default)]
+pub struct Analysis {
+    /// ROM length in bytes
+    pub rom_len: usize,
+    /// Addresses of instructions reachable from the program start
+    pub reachable: BTreeSet<u16>,
+    /// Reachable instructions per opcode pattern, per category
+    pub categories: BTreeMap<&'static str, BTreeMap<&'static str, usize>>,
+    /// Extension opcodes, by address
+    pub extensions: BTreeMap<u16, (Platform, &'static str)>,
+    /// Opcodes whose meaning depends on the quirks, by address
+    pub ambiguous: BTreeMap<u16, Instruction>,
+    /// BNNN jumps, whose targets depend on registers
+    pub indirect_jumps: BTreeSet<u16>,
+    /// Instructions that write over reachable code, and the addresses they write
+    pub self_modifying: BTreeMap<u16, RangeInclusive<u16>>,
+    /// ROM ranges never reached as code
+    pub unreachable: Vec<RangeInclusive<u16>>,
+    /// Addresses that `LD I` points at
+    pub data_references: BTreeSet<u16>,
+    /// Sprites that DXYN draws from an address I is known to hold there, with the
+    /// most rows drawn from each
+    pub sprites: BTreeMap<u16, u8>,
+}
+
+impl Analysis {
+    /// Analyze a ROM loaded at the program start
+    pub fn analyze(rom: &[u8]) -> Analysis {
+        let start = PROGRAM_START_LOCATION as u16;
+        let end = start as usize + rom.len();
+        let opcode_at = |address: u16| {
+            let offset = address as usize - PROGRAM_START_LOCATION;
+            BigEndian::read_u16(&rom[offset..offset + 2])
+        };
+
+        let mut analysis = Analysis {
+            rom_len: rom.len(),
+            ..Analysis::default()
+        };
+        // Bytes of reachable instructions, including F000 NNNN's operand
+        let mut code = BTreeSet::new();
+        // Writes to known addresses: the writing instruction and what it writes
+        let mut writes = Vec::new();
+
+        // Addresses to visit, with I if it's known there
+        let mut to_visit = vec![(start, None)];
+        while let Some((address, i)) = to_visit.pop() {
+            if address < start || address as usize + 2 > end {
+                continue;
+            }
+            if !analysis.reachable.insert(address) {
+                continue;
+            }
+            code.extend([address, address + 1]);
+
+            let opcode = opcode_at(address);
+            let instruction = Instruction::decode(opcode);
+            let mut next = address + 2;
+            let mut i: Option<u16> = i;
+
+            let extension = extension(opcode);
+            if let Some(extension) = extension {
+                analysis.extensions.insert(address, extension);
+                *analysis
+                    .categories
+                    .entry("extension")
+                    .or_default()
+                    .entry(instruction.pattern())
+                    .or_default() += 1;
+            } else {
+                *analysis
+                    .categories
+                    .entry(instruction.category())
+                    .or_default()
+                    .entry(instruction.pattern())
+                    .or_default() += 1;
+            }
+
+            if opcode == 0xF000 {
+                // The next two bytes are the address to load into I
+                if next as usize + 2 <= end {
+                    i = Some(opcode_at(next));
+                    code.extend([next, next + 1]);
+                }
+                next += 2;
+            }
+
+            // A skipped instruction could be a 4 byte F000 NNNN
+            let skip_target = |next: u16| {
+                let long = next as usize + 2 <= end && opcode_at(next) == 0xF000;
+                next + if long { 4 } else { 2 }
+            };
+
+            match instruction {
+                Instruction::Ret => {}
+                _ if opcode == 0x00FD => {}
+                Instruction::Jp { nnn } => to_visit.push((nnn, i)),
+                Instruction::Call { nnn } => {
+                    to_visit.push((nnn, i));
+                    // I is unknown after the subroutine returns
+                    to_visit.push((next, None));
+                }
+                Instruction::JpV0 { .. } => {
+                    analysis.indirect_jumps.insert(address);
+                    analysis.ambiguous.insert(address, instruction);
+                }
+                Instruction::SeByte { .. }
+                | Instruction::SneByte { .. }
+                | Instruction::SeReg { .. }
+                | Instruction::SneReg { .. }
+                | Instruction::Skp { .. }
+                | Instruction::Sknp { .. } => {
+                    to_visit.push((next, i));
+                    to_visit.push((skip_target(next), i));
+                }
+                _ => {
+                    match instruction {
+                        Instruction::LdI { nnn } => {
+                            analysis.data_references.insert(nnn);
+                            i = Some(nnn);
+                        }
+                        Instruction::Shr { x, y } | Instruction::Shl { x, y } if x != y => {
+                            analysis.ambiguous.insert(address, instruction);
+                        }
+                        Instruction::Or { .. }
+                        | Instruction::And { .. }
+                        | Instruction::Xor { .. } => {
+                            analysis.ambiguous.insert(address, instruction);
+                        }
+                        Instruction::LdBVx { .. } => {
+                            if let Some(i) = i {
+                                if let Some(end) = i.checked_add(2) {
+                                    writes.push((address, i..=end));
+                                }
+                            }
+                        }
+                        Instruction::LdIVx { x } => {
+                            analysis.ambiguous.insert(address, instruction);
+                            if let Some(i) = i {
+                                if let Some(end) = i.checked_add(x as u16) {
+                                    writes.push((address, i..=end));
+                                }
+                            }
+                            i = None;
+                        }
+                        Instruction::LdVxI { .. } => {
+                            analysis.ambiguous.insert(address, instruction);
+                            i = None;
+                        }
+                        Instruction::AddIVx { .. } | Instruction::LdFVx { .. } => i = None,
+                        // DXY0 is a SUPER-CHIP 16x16 sprite, which isn't rows of a byte
+                        Instruction::Drw { n, .. } if n > 0 => {
+                            if let Some(i) = i {
+                                let rows = analysis.sprites.entry(i).or_default();
+                                *rows = (*rows).max(n);
+                            }
+                        }
+                        _ => {}
+                    }
+                    to_visit.push((next, i));
+                }
+            }
+        }
+
+        for (address, range) in writes {
+            if range.clone().any(|written| code.contains(&written)) {
+                analysis.self_modifying.insert(address, range);
+            }
+        }
+
+        let mut unreachable_start = None;
+        for address in start..=end as u16 {
+            let in_code = address as usize == end || code.contains(&address);
+            match (unreachable_start, in_code) {
+                (None, false) => unreachable_start = Some(address),
+                (Some(first), true) => {
+                    analysis.unreachable.push(first..=address - 1);
+                    unreachable_start = None;
+                }
+                _ => {}
+            }
+        }
+
+        analysis
+    }
+
+    /// The newest platform whose extensions the ROM uses
+    pub fn platform(&self) -> Platform {
+        self.extensions
+            .values()
+            .map(|&(platform, _)| platform)
+            .max()
+            .unwrap_or(Platform::Chip8)
+    }
+
+    /// The name of the quirks preset the ROM most likely wants, and the preset
+    pub fn suggested_quirks(&self) -> (&'static str, Quirks) {
+        match self.platform() {
+            Platform::Chip8 => ("vip", Quirks::VIP),
+            Platform::SuperChip => ("schip", Quirks::SCHIP),
+            Platform::XoChip => ("xochip", Quirks::XO_CHIP),
+        }
+    }
+}
+
+/// A text report
+impl fmt::Display for Analysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} bytes, {} reachable instructions",
+            self.rom_len,
+            self.reachable.len()
+        )?;
+        writeln!(f, "Platform: {}", self.platform())?;
+
+        writeln!(f, "\nOpcodes by category:")?;
+        for (category, patterns) in &self.categories {
+            write!(f, "  {:<11}", category)?;
+            for (pattern, count) in patterns {
+                write!(f, " {} x{}", pattern, count)?;
+            }
+            writeln!(f)?;
+        }
+
+        if !self.extensions.is_empty() {
+            writeln!(f, "\nExtension opcodes:")?;
+            for (address, (platform, name)) in &self.extensions {
+                writeln!(f, "  {:04X} {} ({})", address, name, platform)?;
+            }
+        }
+        if !self.ambiguous.is_empty() {
+            writeln!(f, "\nOpcodes that depend on quirks:")?;
+            for (address, instruction) in &self.ambiguous {
+                writeln!(f, "  {:04X} {}", address, instruction)?;
+            }
+        }
+        if !self.indirect_jumps.is_empty() {
+            writeln!(f, "\nIndirect jumps (targets not followed):")?;
+            for address in &self.indirect_jumps {
+                writeln!(f, "  {:04X}", address)?;
+            }
+        }
+        if !self.self_modifying.is_empty() {
+            writeln!(f, "\nSelf-modifying code:")?;
+            for (address, range) in &self.self_modifying {
+                writeln!(
+                    f,
+                    "  {:04X} writes {:04X}-{:04X}",
+                    address,
+                    range.start(),
+                    range.end()
+                )?;
+            }
+        }
+        if !self.unreachable.is_empty() {
+            writeln!(f, "\nUnreachable regions:")?;
+            for range in &self.unreachable {
+                let referenced = self.data_references.range(range.clone()).next().is_some();
+                writeln!(
+                    f,
+                    "  {:04X}-{:04X} ({} bytes{})",
+                    range.start(),
+                    range.end(),
+                    range.end() - range.start() + 1,
+                    if referenced { ", data for LD I" } else { "" }
+                )?;
+            }
+        }
+
+        let (preset, _) = self.suggested_quirks();
+        write!(f, "\nSuggested quirks: --quirks {}", preset)?;
+        if self.platform() == Platform::Chip8 && !self.ambiguous.is_empty() {
+            write!(f, " (if it misbehaves, it may have been written for schip)")?;
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+
+    use super::{Analysis, Platform};
+
+    #[test]
+    fn walks_control_flow() {
+        #[rustfmt::skip]
+        let rom = [
+            0xA2, 0x10, // 200: LD I, 0x210
+            0x22, 0x0C, // 202: CALL 0x20C
+            0x30, 0x00, // 204: SE V0, 0
+            0x12, 0x04, // 206: JP 0x204
+            0x12, 0x08, // 208: JP 0x208
+            0x00, 0x00, // 20A: unreachable
+            0x81, 0x26, // 20C: SHR V1, V2
+            0x00, 0xEE, // 20E: RET
+            0xFF, 0x81, // 210: sprite
+        ];
+        let analysis = Analysis::analyze(&rom);
+        assert_eq!(
+            analysis
+                .reachable
+                .iter()
+                .copied()
+                .collect::<alloc::vec::Vec<_>>(),
+            [0x200, 0x202, 0x204, 0x206, 0x208, 0x20C, 0x20E]
+        );
+        assert_eq!(analysis.categories["flow"]["1NNN"], 2);
+        assert_eq!(analysis.ambiguous.keys().next(), Some(&0x20C));
+        assert_eq!(analysis.unreachable, [0x20A..=0x20B, 0x210..=0x211]);
+        assert_eq!(analysis.platform(), Platform::Chip8);
+
+        let report = analysis.to_string();
+        assert!(report.contains("  0210-0211 (2 bytes, data for LD I)\n"));
+        assert!(report.contains("Suggested quirks: --quirks vip"));
+    }
+
+    #[test]
+    fn finds_extensions_and_self_modifying_code() {
+        #[rustfmt::skip]
+        let rom = [
+            0x00, 0xFF, // 200: high resolution
+            0xA2, 0x00, // 202: LD I, 0x200
+            0xF1, 0x55, // 204: LD [I], V1
+            0xB2, 0x00, // 206: JP V0, 0x200
+        ];
+        let analysis = Analysis::analyze(&rom);
+        assert_eq!(analysis.platform(), Platform::SuperChip);
+        assert_eq!(analysis.suggested_quirks().0, "schip");
+        assert_eq!(analysis.self_modifying[&0x204], 0x200..=0x201);
+        assert!(analysis.indirect_jumps.contains(&0x206));
+        assert!(analysis.unreachable.is_empty());
+    }
+
+    #[test]
+    fn finds_logic_that_resets_vf() {
+        #[rustfmt::skip]
+        let rom = [
+            0x81, 0x22, // 200: AND V1, V2
+            0x81, 0x24, // 202: ADD V1, V2
+            0x81, 0x23, // 204: XOR V1, V2
+            0x12, 0x06, // 206: JP 0x206
+        ];
+        let analysis = Analysis::analyze(&rom);
+        assert_eq!(
+            analysis
+                .ambiguous
+                .keys()
+                .copied()
+                .collect::<alloc::vec::Vec<_>>(),
+            [0x200, 0x204]
+        );
+    }
+
+    #[test]
+    fn finds_sprites() {
+        #[rustfmt::skip]
+        let rom = [
+            0xA2, 0x0C, // 200: LD I, 0x20C
+            0xD0, 0x13, // 202: DRW V0, V1, 3
+            0xD0, 0x15, // 204: DRW V0, V1, 5
+            0xF0, 0x29, // 206: LD F, V0
+            0xD0, 0x15, // 208: DRW V0, V1, 5
+            0x12, 0x00, // 20A: JP 0x200
+            0xF0, 0x90, 0xF0, 0x90, 0x90, // 20C: sprite
+        ];
+        let analysis = Analysis::analyze(&rom);
+        assert_eq!(
+            analysis.sprites.into_iter().collect::<alloc::vec::Vec<_>>(),
+            [(0x20C, 5)]
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Cheats: searching memory for the bytes that hold lives or a score, and patches
+//! that hold bytes of data or code fixed every frame.
+//!
+//! Cheat files keep cheats for any number of ROMs, each under a `rom <length>
+//! <FNV-1a hash>` line like coverage data, then a line per cheat with whether it's
+//! on, its address, its bytes in hex and a name:
+//!
+//! ```text
+//! chip8-cheats 1
+//! rom 246 0123456789abcdef
+//! on 0300 05 infinite lives
+//! off 0234 1238 skip the title screen
+//! ```
+
+use alloc::{
+    collections::BTreeMap,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::{fmt::Write, str::FromStr};
+
+use crate::{coverage::fnv1a, globals::Err, Chip8};
+
+const HEADER: &str = "chip8-cheats 1";
+
+/// How a memory search narrows down its candidates, comparing each byte to its
+/// value at the last search
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SearchFilter {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+impl SearchFilter {
+    fn matches(self, previous: u8, now: u8) -> bool {
+        match self {
+            SearchFilter::Equal(value) => now == value,
+            SearchFilter::Changed => now != previous,
+            SearchFilter::Unchanged => now == previous,
+            SearchFilter::Increased => now > previous,
+            SearchFilter::Decreased => now < previous,
+        }
+    }
+}
+
+impl FromStr for SearchFilter {
+    type Err = String;
+
+    /// Parse `changed`, `unchanged`, `increased`, `decreased`, or a value to look for
+    /// in decimal or `0x` hex
+    fn from_str(s: &str) -> Result<SearchFilter, String> {
+        let filter = match s {
+            "changed" => SearchFilter::Changed,
+            "unchanged" => SearchFilter::Unchanged,
+            "increased" => SearchFilter::Increased,
+            "decreased" => SearchFilter::Decreased,
+            _ => {
+                let value = match s.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => s.parse(),
+                };
+                SearchFilter::Equal(value.map_err(|_| {
+                    format!(
+                        "`{}` isn't a byte value or changed, unchanged, increased or decreased",
+                        s
+                    )
+                })?)
+            }
+        };
+        Ok(filter)
+    }
+}
+
+/// A memory search, narrowed down one filter at a time
+pub struct Search {
+    /// Addresses still in the running, with their values at the last search
+    candidates: BTreeMap<u16, u8>,
+}
+
+impl Search {
+    /// Start with every address as a candidate
+    pub fn new(memory: &[u8]) -> Self {
+        Search {
+            candidates: (0..memory.len() as u16)
+                .zip(memory.iter().copied())
+                .collect(),
+        }
+    }
+
+    /// Keep the candidates that match, and remember their values for next time
+    pub fn filter(&mut self, memory: &[u8], filter: SearchFilter) {
+        self.candidates.retain(|&address, previous| {
+            let now = memory[address as usize];
+            let keep = filter.matches(*previous, now);
+            *previous = now;
+            keep
+        });
+    }
+
+    /// Addresses that matched every filter so far, with their values
+    pub fn candidates(&self) -> &BTreeMap<u16, u8> {
+        &self.candidates
+    }
+}
+
+/// Bytes written into memory every frame while the cheat is on
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub enabled: bool,
+    pub name: String,
+}
+
+impl Cheat {
+    /// Write the cheat's bytes, if it's on
+    pub fn apply(&self, chip_8: &mut Chip8) {
+        if !self.enabled {
+            return;
+        }
+        let start = self.address as usize;
+        if let Some(memory) = chip_8.memory.get_mut(start..start + self.bytes.len()) {
+            memory.copy_from_slice(&self.bytes);
+        }
+    }
+}
+
+/// The cheats in a cheat file, by ROM
+#[derive(Default)]
+pub struct CheatFile {
+    roms: BTreeMap<String, Vec<Cheat>>,
+}
+
+impl CheatFile {
+    pub fn parse(text: &str) -> Result<CheatFile, Err> {
+        let mut lines = text.lines().enumerate();
+        if lines.next().map(|(_, line)| line) != Some(HEADER) {
+            return Err(format!("not a cheat file: it should start with `{}`", HEADER).into());
+        }
+        let mut file = CheatFile::default();
+        let mut rom = None;
+        for (index, line) in lines {
+            let invalid = || format!("line {}: invalid cheat `{}`", index + 1, line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line.starts_with("rom ") {
+                rom = Some(line.to_string());
+                file.roms.entry(line.to_string()).or_default();
+                continue;
+            }
+            let rom = rom
+                .as_ref()
+                .ok_or_else(|| format!("line {}: cheat before any `rom` line", index + 1))?;
+            let mut fields = line.splitn(4, ' ');
+            let enabled = match fields.next() {
+                Some("on") => true,
+                Some("off") => false,
+                _ => return Err(invalid().into()),
+            };
+            let address = fields
+                .next()
+                .and_then(|address| u16::from_str_radix(address, 16).ok())
+                .ok_or_else(invalid)?;
+            let bytes = fields.next().and_then(parse_bytes).ok_or_else(invalid)?;
+            let name = fields.next().unwrap_or_default().to_string();
+            file.roms.get_mut(rom).unwrap().push(Cheat {
+                address,
+                bytes,
+                enabled,
+                name,
+            });
+        }
+        Ok(file)
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "{}", HEADER);
+        for (rom, cheats) in self.roms.iter().filter(|(_, cheats)| !cheats.is_empty()) {
+            let _ = writeln!(text, "{}", rom);
+            for cheat in cheats {
+                let bytes: String = cheat
+                    .bytes
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                let state = if cheat.enabled { "on" } else { "off" };
+                let _ = writeln!(
+                    text,
+                    "{} {:04X} {} {}",
+                    state, cheat.address, bytes, cheat.name
+                );
+            }
+        }
+        text
+    }
+
+    /// The cheats for a ROM
+    pub fn cheats(&self, rom: &[u8]) -> &[Cheat] {
+        self.roms.get(&rom_line(rom)).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn cheats_mut(&mut self, rom: &[u8]) -> &mut Vec<Cheat> {
+        self.roms.entry(rom_line(rom)).or_default()
+    }
+}
+
+fn rom_line(rom: &[u8]) -> String {
+    format!("rom {} {:016x}", rom.len(), fnv1a(rom))
+}
+
+/// Bytes in hex, e.g. `12A4`
+pub fn parse_bytes(hex: &str) -> Option<Vec<u8>> {
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{string::ToString, vec, vec::Vec};
+
+    use super::{Cheat, CheatFile, Search, SearchFilter};
+    use crate::Chip8;
+
+    #[test]
+    fn searches_and_cheats() {
+        let mut chip_8 = Chip8::new();
+        chip_8.memory[0x300] = 3;
+        chip_8.memory[0x301] = 3;
+        let mut search = Search::new(&chip_8.memory);
+        search.filter(&chip_8.memory, "3".parse().unwrap());
+        assert_eq!(search.candidates().len(), 2);
+
+        // Lose a life
+        chip_8.memory[0x300] = 2;
+        search.filter(&chip_8.memory, SearchFilter::Decreased);
+        assert_eq!(search.candidates().keys().collect::<Vec<_>>(), [&0x300]);
+
+        let rom = [0x12, 0x00];
+        let mut file = CheatFile::default();
+        file.cheats_mut(&rom).push(Cheat {
+            address: 0x300,
+            bytes: vec![9],
+            enabled: true,
+            name: "infinite lives".to_string(),
+        });
+        let file = CheatFile::parse(&file.to_text()).unwrap();
+        for cheat in file.cheats(&rom) {
+            cheat.apply(&mut chip_8);
+        }
+        assert_eq!(chip_8.memory[0x300], 9);
+        assert!(file.cheats(&[0x00, 0xE0]).is_empty());
+        assert!(CheatFile::parse("chip8-cheats 1\non 0300 9").is_err());
+    }
+}
@@ -1,12 +1,17 @@
-use std::{collections::HashSet, num::Wrapping};
+use alloc::{collections::BTreeSet, format, vec::Vec};
+use core::num::Wrapping;
 
 use byteorder::{BigEndian, ByteOrder};
-use rand::random;
 
-use crate::globals;
+use crate::{
+    globals::{self, Err},
+    rng::Rng,
+    Instruction, Observer, Quirks,
+};
 
-const FONT_START_LOCATION: usize = 0x50;
-const FONT: [u8; 80] = [
+pub const PROGRAM_START_LOCATION: usize = 0x200;
+pub const FONT_START_LOCATION: usize = 0x50;
+pub const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -25,7 +30,7 @@ const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
-pub type Display = HashSet<(i32, i32)>;
+pub type Display = BTreeSet<(i32, i32)>;
 
 #[derive(Clone)]
 pub struct Chip8 {
@@ -44,24 +49,58 @@ pub struct Chip8 {
     // Delay timer, sound timer
     pub dt: u8,
     pub st: u8,
+    // Which interpreter's behavior to copy where they disagree
+    pub quirks: Quirks,
+    // Random number generator for CXNN
+    pub(crate) rng: Rng,
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Chip8 {
+    /// With the `std` feature the random number generator is seeded from the OS,
+    /// otherwise from a fixed seed; use `with_seed` to choose one
     pub fn new() -> Chip8 {
+        #[cfg(feature = "std")]
+        let seed = rand::random();
+        #[cfg(not(feature = "std"))]
+        let seed = 0;
+
+        Chip8::with_seed(seed)
+    }
+
+    /// Create a Chip8 whose random number generator (CXNN) is seeded with `seed`
+    pub fn with_seed(seed: u64) -> Chip8 {
         // Read the sprite font into memory
         let mut memory = [0u8; 4096];
         memory[FONT_START_LOCATION..FONT_START_LOCATION + FONT.len()].clone_from_slice(&FONT[..]);
 
-        return Chip8 {
+        Chip8 {
             memory,
-            display: HashSet::default(),
+            display: BTreeSet::default(),
             v: [0; 16],
-            pc: 0x200, // Programs start at 0x200
+            pc: PROGRAM_START_LOCATION as u16,
             i: 0,
             stack: Vec::new(),
             dt: 0,
             st: 0,
-        };
+            quirks: Quirks::default(),
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Copy a ROM into memory at the program start address, 0x200
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), Err> {
+        let end = PROGRAM_START_LOCATION + rom.len();
+        if end > self.memory.len() {
+            return Err(format!("ROM is too large to fit in memory ({} bytes)", rom.len()).into());
+        }
+        self.memory[PROGRAM_START_LOCATION..end].clone_from_slice(rom);
+        Ok(())
     }
 
     pub fn decrement_counters(&mut self) {
@@ -69,250 +108,234 @@ impl Chip8 {
         self.st = self.st.saturating_sub(1);
     }
 
-    pub fn run_cycle(&mut self, held_keys: &HashSet<u8>) {
+    /// Why running the next instruction would stop the emulator, if it would: the
+    /// PC is past the end of memory, it's a RET with nothing on the stack, or it
+    /// reads or writes memory at I past the end
+    pub fn fault(&self) -> Option<&'static str> {
+        let pc = self.pc as usize;
+        let Some(&[high, low]) = self.memory.get(pc..pc + 2) else {
+            return Some("the PC is past the end of memory");
+        };
+        let instruction = Instruction::decode(u16::from_be_bytes([high, low]));
+        if instruction == Instruction::Ret && self.stack.is_empty() {
+            return Some("RET with an empty stack");
+        }
+        if let Some((_, len)) = instruction.memory_access() {
+            if self.i as usize + len as usize > self.memory.len() {
+                return Some("I is too close to the end of memory");
+            }
+        }
+        None
+    }
+
+    /// Run one 60 Hz frame: tick the timers, then run INSTRUCTIONS_PER_FRAME cycles
+    pub fn run_frame(&mut self, held_keys: &BTreeSet<u8>) {
+        self.run_frame_observed(held_keys, &mut ());
+    }
+
+    /// Run one frame like `run_frame`, telling `observer` about each instruction
+    pub fn run_frame_observed(&mut self, held_keys: &BTreeSet<u8>, observer: &mut dyn Observer) {
+        observer.frame_start(self);
+        self.decrement_counters();
+        for _ in 0..globals::INSTRUCTIONS_PER_FRAME {
+            observer.before_cycle(self);
+            self.run_cycle(held_keys);
+            observer.after_cycle(self);
+        }
+        observer.frame_end(self);
+    }
+
+    pub fn run_cycle(&mut self, held_keys: &BTreeSet<u8>) {
         // Read instruction
         let pc_idx = self.pc as usize;
-        let instruction = BigEndian::read_u16(&self.memory[pc_idx..pc_idx + 2]);
+        let opcode = BigEndian::read_u16(&self.memory[pc_idx..pc_idx + 2]);
 
         // Increment pc
         self.pc += 2;
 
-        // Get instruction nibble values
-        let x_nibble = (instruction & 0x0F00) >> 8;
-        let y_nibble = (instruction & 0x00F0) >> 4;
-        let n_nibble = instruction & 0x000F;
-        let nn_nibble = instruction & 0x00FF; // Technically not a nibble.
-        let nnn_nibble = instruction & 0x0FFF;
-
-        // Check 1st nibble
-        match instruction & 0xF000 {
+        match Instruction::decode(opcode) {
             // 00E0 -- Clear screen
-            _ if instruction == 0x00E0 => self.display.clear(),
+            Instruction::Cls => self.display.clear(),
             // 00EE -- End subroutine
-            _ if instruction == 0x00EE => self.pc = self.stack.pop().unwrap(),
-            // 1XXX -- JMP to XXX
-            a if a == 0x1000 => self.pc = instruction & 0x0FFF,
-            // 2XXX -- Subroutine: push PC to stack, JMP to XXX
-            a if a == 0x2000 => {
+            Instruction::Ret => self.pc = self.stack.pop().unwrap(),
+            // 1NNN -- JMP to NNN
+            Instruction::Jp { nnn } => self.pc = nnn,
+            // 2NNN -- Subroutine: push PC to stack, JMP to NNN
+            Instruction::Call { nnn } => {
                 self.stack.push(self.pc);
-                self.pc = instruction & 0x0FFF;
+                self.pc = nnn;
             }
             // 3XNN -- Skip the following instruction if vX == NN
-            a if a == 0x3000 => {
-                if self.v[x_nibble as usize] == nn_nibble as u8 {
-                    self.pc += 2;
-                };
-            }
+            Instruction::SeByte { x, nn } if self.v[x as usize] == nn => self.pc += 2,
             // 4XNN -- Skip the following instruction if vX != NN
-            a if a == 0x4000 => {
-                if self.v[x_nibble as usize] != nn_nibble as u8 {
-                    self.pc += 2;
-                };
-            }
+            Instruction::SneByte { x, nn } if self.v[x as usize] != nn => self.pc += 2,
             // 5XY0 -- Skip the following instruction if vX == vY
-            a if a == 0x5000 && instruction & 0x000F == 0 => {
-                if self.v[x_nibble as usize] == self.v[y_nibble as usize] {
-                    self.pc += 2;
-                };
-            }
+            Instruction::SeReg { x, y } if self.v[x as usize] == self.v[y as usize] => self.pc += 2,
             // 6XNN -- Store NN in register vX
-            a if a == 0x6000 => {
-                let value = instruction as u8;
-                self.v[x_nibble as usize] = value;
-            }
+            Instruction::LdByte { x, nn } => self.v[x as usize] = nn,
             // 7XNN -- Add the value NN to register vX -- Use wrapping overflow
-            a if a == 0x7000 => {
-                self.v[x_nibble as usize] =
-                    (Wrapping(self.v[x_nibble as usize]) + Wrapping(nn_nibble as u8)).0
+            Instruction::AddByte { x, nn } => {
+                self.v[x as usize] = (Wrapping(self.v[x as usize]) + Wrapping(nn)).0
             }
             // 8XY0 -- Store the value of register vY in register vX
-            _ if instruction & 0xF00F == 0x8000 => {
-                self.v[x_nibble as usize] = self.v[y_nibble as usize]
-            }
+            Instruction::LdReg { x, y } => self.v[x as usize] = self.v[y as usize],
             // 8XY1 -- Set vX to vX OR vY
-            _ if instruction & 0xF00F == 0x8001 => {
-                self.v[x_nibble as usize] = self.v[x_nibble as usize] | self.v[y_nibble as usize];
+            Instruction::Or { x, y } => {
+                self.v[x as usize] |= self.v[y as usize];
+                self.reset_vf_after_logic();
             }
             // 8XY2 -- Set vX to vX AND vY
-            _ if instruction & 0xF00F == 0x8002 => {
-                self.v[x_nibble as usize] = self.v[x_nibble as usize] & self.v[y_nibble as usize];
+            Instruction::And { x, y } => {
+                self.v[x as usize] &= self.v[y as usize];
+                self.reset_vf_after_logic();
             }
             // 8XY3 -- Set vX to vX XOR vY
-            _ if instruction & 0xF00F == 0x8003 => {
-                self.v[x_nibble as usize] = self.v[x_nibble as usize] ^ self.v[y_nibble as usize];
+            Instruction::Xor { x, y } => {
+                self.v[x as usize] ^= self.v[y as usize];
+                self.reset_vf_after_logic();
             }
             // 8XY4 -- Add the value of register vY to register vX
             //         Set vF to 01 if a carry occurs
             //         Set vF to 00 if a carry does not occur
-            _ if instruction & 0xF00F == 0x8004 => {
-                let x = x_nibble as usize;
-                let y = y_nibble as usize;
-                let x_val = self.v[x];
-                let y_val = self.v[y];
-                let (sum, carry) = u8::overflowing_add(x_val, y_val);
-                self.v[x] = sum;
+            Instruction::AddReg { x, y } => {
+                let (sum, carry) = u8::overflowing_add(self.v[x as usize], self.v[y as usize]);
+                self.v[x as usize] = sum;
                 self.v[0xF] = carry as u8;
             }
             // 8XY5 -- Set vX to the difference vX - vY
             //         Set vF to 00 if a borrow occurs
             //         Set vF to 01 if a borrow does not occur
-            _ if instruction & 0xF00F == 0x8005 => {
-                let x = x_nibble as usize;
-                let y = y_nibble as usize;
-                let x_val = self.v[x];
-                let y_val = self.v[y];
-                let (difference, carry) = u8::overflowing_sub(x_val, y_val);
-                self.v[x] = difference;
+            Instruction::Sub { x, y } => {
+                let (difference, carry) =
+                    u8::overflowing_sub(self.v[x as usize], self.v[y as usize]);
+                self.v[x as usize] = difference;
                 self.v[0xF] = !carry as u8;
             }
             // 8XY6 -- Store the value of register vY shifted right one bit in register vX
+            //         (or vX itself, with the shift_uses_vy quirk off)
             //         Set register vF to the least significant bit prior to the shift
             //         vY is unchanged
-            _ if instruction & 0xF00F == 0x8006 => {
-                let x = x_nibble as usize;
-                let y = y_nibble as usize;
-                let y_val = self.v[y];
-                let lsb = y_val & 1;
-                let y_shifted = y_val >> 1;
-                self.v[x] = y_shifted;
-                self.v[0xF] = lsb;
+            Instruction::Shr { x, y } => {
+                let value = self.v[if self.quirks.shift_uses_vy { y } else { x } as usize];
+                self.v[x as usize] = value >> 1;
+                self.v[0xF] = value & 1;
             }
             // 8XY7 -- Set vX to the difference vY - vX
             //         Set vF to 00 if a borrow occurs
             //         Set vF to 01 if a borrow does not occur
-            _ if instruction & 0xF00F == 0x8007 => {
-                let x = x_nibble as usize;
-                let y = y_nibble as usize;
-                let x_val = self.v[x];
-                let y_val = self.v[y];
-                let (difference, carry) = u8::overflowing_sub(y_val, x_val);
-                self.v[x] = difference;
+            Instruction::Subn { x, y } => {
+                let (difference, carry) =
+                    u8::overflowing_sub(self.v[y as usize], self.v[x as usize]);
+                self.v[x as usize] = difference;
                 self.v[0xF] = !carry as u8;
             }
             // 8XYE -- Store the value of register vY shifted left one bit in register vX
+            //         (or vX itself, with the shift_uses_vy quirk off)
             //         Set register vF to the most significant bit prior to the shift
             //         vY is unchanged
-            _ if instruction & 0xF00F == 0x800E => {
-                let x = x_nibble as usize;
-                let y = y_nibble as usize;
-                let y_val = self.v[y];
-                let msb = (y_val >> 7) & 1;
-                let y_shifted = y_val << 1;
-                self.v[x] = y_shifted;
-                self.v[0xF] = msb;
+            Instruction::Shl { x, y } => {
+                let value = self.v[if self.quirks.shift_uses_vy { y } else { x } as usize];
+                self.v[x as usize] = value << 1;
+                self.v[0xF] = (value >> 7) & 1;
             }
             // 9XY0 -- Skip the following instruction if vX != vY
-            _ if instruction & 0xF00F == 0x9000 => {
-                if self.v[x_nibble as usize] != self.v[y_nibble as usize] {
-                    self.pc += 2;
-                };
+            Instruction::SneReg { x, y } if self.v[x as usize] != self.v[y as usize] => {
+                self.pc += 2
             }
             // ANNN -- Store memory address NNN in register I
-            a if a == 0xA000 => self.i = nnn_nibble,
-            // BNNN -- Jump to NNN + v0
-            a if a == 0xB000 => self.pc = nnn_nibble + self.v[0] as u16,
+            Instruction::LdI { nnn } => self.i = nnn,
+            // BNNN -- Jump to NNN + v0 (or + vX, the high nibble of NNN, with the
+            //         jump_uses_vx quirk)
+            Instruction::JpV0 { nnn } => {
+                let x = if self.quirks.jump_uses_vx {
+                    (nnn >> 8) as usize
+                } else {
+                    0
+                };
+                self.pc = nnn + self.v[x] as u16;
+            }
             // CXNN -- Generate a random number, AND-mask it with NN, and set vX to it
-            a if a == 0xC000 => {
-                let n: u8 = random();
-                self.v[x_nibble as usize] = n & nn_nibble as u8;
+            Instruction::Rnd { x, nn } => {
+                self.v[x as usize] = self.rng.byte() & nn;
             }
             // DXYN -- Draw a sprite at vX, vY with N bytes of sprite data starting at the address stored in I
-            a if a == 0xD000 => {
+            Instruction::Drw { x, y, n } => {
                 self.v[0xf] = self
                     .draw_sprite(
-                        (self.v[x_nibble as usize] % 64) as i32,
-                        (self.v[y_nibble as usize] % 32) as i32,
-                        n_nibble as usize,
+                        (self.v[x as usize] % 64) as i32,
+                        (self.v[y as usize] % 32) as i32,
+                        n as usize,
                         self.i as usize,
                     )
                     .into();
             }
             // EX9E -- Skip the next instruction if the key vX is pressed
-            _ if instruction & 0xF0FF == 0xE09E => {
-                let v_x = self.v[x_nibble as usize];
-                if held_keys.contains(&v_x) {
-                    self.pc += 2;
-                }
-            }
+            Instruction::Skp { x } if held_keys.contains(&self.v[x as usize]) => self.pc += 2,
             // EXA1 -- Skip the next instruction if the key vX is not pressed
-            _ if instruction & 0xF0FF == 0xE0A1 => {
-                let x = x_nibble as usize;
-                if !held_keys.contains(&self.v[x]) {
-                    self.pc += 2;
-                }
-            }
+            Instruction::Sknp { x } if !held_keys.contains(&self.v[x as usize]) => self.pc += 2,
             // FX0A -- Wait for a keypress and store the result in vX
-            _ if instruction & 0xF0FF == 0xF00A => {
-                let x = x_nibble as usize;
-                match held_keys.iter().next() {
-                    Some(&key) => self.v[x] = key,
-                    None => self.pc -= 2,
-                }
-            }
+            Instruction::LdVxK { x } => match held_keys.iter().next() {
+                Some(&key) => self.v[x as usize] = key,
+                None => self.pc -= 2,
+            },
             // FX07 -- Set vX to the value of dt
-            _ if instruction & 0xF0FF == 0xF007 => {
-                self.v[x_nibble as usize] = self.dt;
-            }
+            Instruction::LdVxDt { x } => self.v[x as usize] = self.dt,
             // FX15 -- Set dt to the value of vX
-            _ if instruction & 0xF0FF == 0xF015 => {
-                self.dt = self.v[x_nibble as usize];
-            }
+            Instruction::LdDtVx { x } => self.dt = self.v[x as usize],
             // FX18 -- Set st to the value of vX
-            _ if instruction & 0xF0FF == 0xF018 => {
-                self.st = self.v[x_nibble as usize];
-            }
+            Instruction::LdStVx { x } => self.st = self.v[x as usize],
             // FX1E -- Add the value stored in vX to I
-            _ if instruction & 0xF0FF == 0xF01E => {
-                let x = x_nibble as usize;
-                let i = self.i;
-                let x_val = self.v[x] as u16;
-                let (sum, carry) = u16::overflowing_add(i, x_val);
+            Instruction::AddIVx { x } => {
+                let (sum, carry) = u16::overflowing_add(self.i, self.v[x as usize] as u16);
                 self.i = sum;
                 self.v[0xF] = carry as u8;
             }
             // FX29 -- Set I to the memory address of the sprite for the digit stored in vX
-            _ if instruction & 0xF0FF == 0xF029 => {
-                self.i = (FONT_START_LOCATION + (5 * self.v[x_nibble as usize]) as usize) as u16
+            Instruction::LdFVx { x } => {
+                self.i = (FONT_START_LOCATION + (5 * self.v[x as usize]) as usize) as u16
             }
             // FX33 -- Store the binary-coded decimal equivalent of the value stored in register vX
             //         at addresses I, I + 1, and I + 2
-            _ if instruction & 0xF0FF == 0xF033 => {
-                let x = x_nibble as usize;
+            Instruction::LdBVx { x } => {
                 let i = self.i as usize;
-                let val = self.v[x];
-                let first_digit = val / 100;
-                let second_digit = (val % 100) / 10;
-                let third_digit = val % 10;
-                self.memory[i] = first_digit;
-                self.memory[i + 1] = second_digit;
-                self.memory[i + 2] = third_digit;
+                let val = self.v[x as usize];
+                self.memory[i] = val / 100;
+                self.memory[i + 1] = (val % 100) / 10;
+                self.memory[i + 2] = val % 10;
             }
             // FX55 -- Store the values v0 through vX in memory starting at address I
-            //         I is set to I + X + 1 after operation
-            _ if instruction & 0xF0FF == 0xF055 => {
-                let mut addr = self.i;
-                for register in 0..x_nibble + 1 {
-                    let value = self.v[register as usize];
-                    self.memory[addr as usize] = value;
-                    addr += 1;
+            //         I is set to I + X + 1 after operation, with the load_store_increments_i quirk
+            Instruction::LdIVx { x } => {
+                let addr = self.i as usize;
+                for register in 0..=x as usize {
+                    self.memory[addr + register] = self.v[register];
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i += x as u16 + 1;
                 }
-                self.i = self.i + x_nibble + 1;
             }
             // FX65 -- Fill registers v0 to vX inclusive with the values stored in memory starting at address I
-            //         I is set to I + X + 1 after operation
-            _ if instruction & 0xF0FF == 0xF065 => {
-                let mut addr = self.i;
-                for register in 0..x_nibble + 1 {
-                    let value = self.memory[addr as usize];
-                    self.v[register as usize] = value;
-                    addr += 1;
+            //         I is set to I + X + 1 after operation, with the load_store_increments_i quirk
+            Instruction::LdVxI { x } => {
+                let addr = self.i as usize;
+                for register in 0..=x as usize {
+                    self.v[register] = self.memory[addr + register];
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i += x as u16 + 1;
                 }
-                self.i = self.i + x_nibble + 1;
             }
             _ => {}
         }
     }
 
+    // With the VIP logic quirk, 8XY1-8XY3 leave vF at 0
+    fn reset_vf_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.v[0xF] = 0;
+        }
+    }
+
     // Draw a sprite at x and y on the display, using sprite data of the given length,
     // drawn from the given memory location
     // Returns whether any cells were turned off
@@ -323,7 +346,7 @@ impl Chip8 {
         length: usize,
         sprite_location: usize,
     ) -> bool {
-        let sprite_data = self.memory[sprite_location..sprite_location + length as usize].to_vec();
+        let sprite_data = self.memory[sprite_location..sprite_location + length].to_vec();
 
         let mut cells_turned_off = false;
         for (y_offset, byte) in sprite_data.iter().enumerate() {
@@ -345,15 +368,16 @@ impl Chip8 {
                 }
             }
         }
-        return cells_turned_off;
+        cells_turned_off
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashSet;
+    use alloc::{collections::BTreeSet, string::ToString};
 
     use super::Chip8;
+    use crate::Quirks;
 
     #[test]
     fn jump() {
@@ -363,7 +387,7 @@ mod test {
         chip_8.memory[0x200] = 0x1E;
         chip_8.memory[0x201] = 0xEE;
 
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0xEEE);
     }
 
@@ -375,7 +399,7 @@ mod test {
         chip_8.memory[0x200] = 0xA0;
         chip_8.memory[0x201] = 0xEF;
 
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
 
         assert_eq!(chip_8.i, 0x0EF);
 
@@ -385,8 +409,8 @@ mod test {
         chip_8.memory[0x204] = 0x6F;
         chip_8.memory[0x205] = 0x01;
 
-        chip_8.run_cycle(&HashSet::new());
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
 
         assert_eq!(chip_8.v[0], 0xEF);
         assert_eq!(chip_8.v[0xF], 0x01);
@@ -422,7 +446,7 @@ mod test {
         chip_8.memory[0x50E] = 0x10;
         chip_8.memory[0x50F] = 0x01;
 
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
 
         // Check all registers are correct.
         assert_eq!(chip_8.v[0], 0xFE);
@@ -463,12 +487,12 @@ mod test {
         chip_8.memory[0x206] = 0xA2; // Not skipped, sets I to 0x222
         chip_8.memory[0x207] = 0x22;
 
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x204);
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x206);
         assert_eq!(chip_8.i, 0x100);
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x208);
         assert_eq!(chip_8.i, 0x222);
 
@@ -486,12 +510,12 @@ mod test {
         chip_8.memory[0x306] = 0xA3; // Not skipped, sets I to 0x333
         chip_8.memory[0x307] = 0x33;
 
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x304);
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x306);
         assert_eq!(chip_8.i, 0x100);
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x308);
         assert_eq!(chip_8.i, 0x333);
 
@@ -511,12 +535,12 @@ mod test {
         chip_8.memory[0x406] = 0xA3; // Not skipped, sets I to 0x333
         chip_8.memory[0x407] = 0x33;
 
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x404);
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x406);
         assert_eq!(chip_8.i, 0x100);
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x408);
         assert_eq!(chip_8.i, 0x333);
 
@@ -536,16 +560,41 @@ mod test {
         chip_8.memory[0x506] = 0xA3; // Not skipped, sets I to 0x333
         chip_8.memory[0x507] = 0x33;
 
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x504);
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x506);
         assert_eq!(chip_8.i, 0x100);
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
         assert_eq!(chip_8.pc, 0x508);
         assert_eq!(chip_8.i, 0x333);
     }
 
+    #[test]
+    fn seeded_random() {
+        // 0xCXNN sets vX to a random number masked with NN
+        // Machines with the same seed generate the same numbers
+        let mut chip_8 = Chip8::with_seed(1234);
+        let mut other = Chip8::with_seed(1234);
+        for chip_8 in [&mut chip_8, &mut other] {
+            // Fill v0 through v7 with random bytes
+            for register in 0..8 {
+                chip_8.memory[0x200 + 2 * register] = 0xC0 + register as u8;
+                chip_8.memory[0x201 + 2 * register] = 0xFF;
+            }
+            for _ in 0..8 {
+                chip_8.run_cycle(&BTreeSet::new());
+            }
+        }
+        assert_eq!(chip_8.v, other.v);
+
+        // And NN masks the result
+        chip_8.memory[0x210] = 0xC0;
+        chip_8.memory[0x211] = 0x0F;
+        chip_8.run_cycle(&BTreeSet::new());
+        assert!(chip_8.v[0] <= 0x0F);
+    }
+
     #[test]
     fn clear_screen() {
         // 0x00E0 clears the screen
@@ -558,8 +607,49 @@ mod test {
         chip_8.memory[0x200] = 0x00;
         chip_8.memory[0x201] = 0xE0;
 
-        chip_8.run_cycle(&HashSet::new());
+        chip_8.run_cycle(&BTreeSet::new());
 
         assert!(chip_8.display.is_empty());
     }
+
+    #[test]
+    fn quirks() {
+        // With SUPER-CHIP quirks, shifts work on vX in place,
+        // FX55 leaves I alone, and BXNN jumps relative to vX
+        let mut chip_8 = Chip8::new();
+        chip_8.quirks = Quirks::SCHIP;
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0x81, 0x26, // SHR V1, V2
+            0xA3, 0x00, // LD I, 0x300
+            0xF1, 0x55, // LD [I], V1
+            0xB3, 0x00, // JP V3, 0x300
+        ]).unwrap();
+        chip_8.v[1] = 0x05;
+        chip_8.v[2] = 0x80;
+        chip_8.v[3] = 0x10;
+        for _ in 0..4 {
+            chip_8.run_cycle(&BTreeSet::new());
+        }
+        assert_eq!((chip_8.v[1], chip_8.v[0xF]), (0x02, 1));
+        assert_eq!(chip_8.i, 0x300);
+        assert_eq!(chip_8.pc, 0x310);
+    }
+
+    #[test]
+    fn logic_quirk() {
+        // The VIP resets vF after 8XY1-8XY3, XO-CHIP leaves it
+        for (name, quirks, vf) in [("vip", Quirks::VIP, 0), ("xochip", Quirks::XO_CHIP, 7)] {
+            let mut chip_8 = Chip8::new();
+            chip_8.quirks = quirks;
+            chip_8.load_rom(&[0x80, 0x11]).unwrap(); // OR V0, V1
+            chip_8.v[0xF] = 7;
+            chip_8.run_cycle(&BTreeSet::new());
+            assert_eq!(chip_8.v[0xF], vf);
+            assert_eq!(quirks.to_string(), name);
+        }
+
+        // Without a preset, logic leaves vF alone as it always has
+        assert_eq!(Chip8::new().quirks, Quirks::XO_CHIP);
+    }
 }
@@ -0,0 +1,506 @@
+//! Compatibility runs: every ROM in some directories, headless under each quirks
+//! preset, checked for crashes, unknown opcodes, stuck loops and blank screens,
+//! and reported as a table to compare across releases.
+
+use crate::{
+    analysis,
+    globals::{self, Err},
+    screenshot::{ImageFormat, Screenshot},
+    Chip8, Instruction, Observer, Palette, Quirks,
+};
+use clap::ValueEnum;
+use std::{
+    any::Any,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+    fs, mem,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+// Frames running the same loop that count as stuck, a second of play
+const STUCK_FRAMES: u64 = globals::FRAMES_PER_SECOND as u64;
+
+// The most instructions a loop can have and count as stuck. Half a frame's worth,
+// so every frame runs all of it.
+const STUCK_LOOP_LEN: usize = globals::INSTRUCTIONS_PER_FRAME as usize / 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, ValueEnum)]
+pub enum CompatFormat {
+    /// A Markdown table
+    #[default]
+    Markdown,
+    /// An HTML page with a table
+    Html,
+}
+
+/// How to run each ROM
+pub struct CompatOptions {
+    pub frames: u64,
+    /// Where to save each run's final screen, if anywhere
+    pub screenshot_dir: Option<PathBuf>,
+    pub palette: Palette,
+    pub screenshot_scale: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Status {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Status {
+    fn name(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+        }
+    }
+}
+
+/// What happened running one ROM under one quirks preset
+#[derive(Debug)]
+pub struct CompatResult {
+    pub rom: PathBuf,
+    pub preset: &'static str,
+    /// A load error or panic message
+    pub error: Option<String>,
+    /// Opcodes run that this interpreter ignores, by address: unknown ones, machine
+    /// code calls, and SUPER-CHIP and XO-CHIP extensions
+    pub unknown_opcodes: BTreeMap<u16, u16>,
+    /// The start of a small loop the ROM ran for the last second or more, if it did
+    pub stuck_at: Option<u16>,
+    /// Whether that loop waits for a key (FX0A, EX9E or EXA1)
+    pub waiting_for_key: bool,
+    pub blank: bool,
+    pub screenshot: Option<PathBuf>,
+}
+
+impl CompatResult {
+    pub fn status(&self) -> Status {
+        if self.error.is_some() {
+            Status::Error
+        } else if !self.unknown_opcodes.is_empty()
+            || self.blank
+            || self.stuck_at.is_some() && !self.waiting_for_key
+        {
+            Status::Warning
+        } else {
+            Status::Ok
+        }
+    }
+
+    fn notes(&self) -> Vec<String> {
+        let mut notes = Vec::new();
+        if let Some(error) = &self.error {
+            notes.push(error.clone());
+        }
+        if let Some((address, opcode)) = self.unknown_opcodes.iter().next() {
+            notes.push(format!(
+                "{} unknown opcodes, first {:04X} at {:04X}",
+                self.unknown_opcodes.len(),
+                opcode,
+                address
+            ));
+        }
+        match self.stuck_at {
+            Some(address) if self.waiting_for_key => {
+                notes.push(format!("waiting for a key at {:04X}", address))
+            }
+            Some(address) => notes.push(format!("stuck at {:04X}", address)),
+            None => {}
+        }
+        if self.blank {
+            notes.push("blank screen".to_string());
+        }
+        notes
+    }
+}
+
+/// Watches for unsupported opcodes and a small loop the ROM never leaves
+#[derive(Default)]
+struct Checks {
+    frames: u64,
+    unknown_opcodes: BTreeMap<u16, u16>,
+    // The instructions run so far this frame
+    frame_pcs: BTreeSet<u16>,
+    // The instructions every frame since `loop_since` ran
+    loop_pcs: BTreeSet<u16>,
+    loop_since: u64,
+}
+
+impl Checks {
+    fn end_frame(&mut self) {
+        let pcs = mem::take(&mut self.frame_pcs);
+        if pcs != self.loop_pcs {
+            self.loop_pcs = pcs;
+            self.loop_since = self.frames;
+        }
+    }
+
+    /// The loop the ROM has run for STUCK_FRAMES or more, if it's small enough
+    fn stuck_loop(&self) -> Option<&BTreeSet<u16>> {
+        let stuck = self.frames - self.loop_since >= STUCK_FRAMES
+            && (1..=STUCK_LOOP_LEN).contains(&self.loop_pcs.len());
+        stuck.then_some(&self.loop_pcs)
+    }
+}
+
+impl Observer for Checks {
+    fn frame_start(&mut self, _: &Chip8) {
+        self.end_frame();
+        self.frames += 1;
+    }
+
+    fn before_cycle(&mut self, chip_8: &Chip8) {
+        let pc = chip_8.pc;
+        if let Some(&[high, low]) = chip_8.memory.get(pc as usize..pc as usize + 2) {
+            let opcode = u16::from_be_bytes([high, low]);
+            let ignored = matches!(
+                Instruction::decode(opcode),
+                Instruction::Sys { .. } | Instruction::Unknown { .. }
+            );
+            if ignored || analysis::extension(opcode).is_some() {
+                self.unknown_opcodes.insert(pc, opcode);
+            }
+        }
+        self.frame_pcs.insert(pc);
+    }
+}
+
+/// The ROMs in `dirs`, by extension `.ch8` or `.c8`, in order
+pub fn find_roms(dirs: &[PathBuf]) -> Result<Vec<PathBuf>, Err> {
+    let mut roms = BTreeSet::new();
+    for dir in dirs {
+        let entries = fs::read_dir(dir)
+            .map_err(|err| format!("can't read ROM directory {}: {}", dir.display(), err))?;
+        for entry in entries {
+            let path = entry?.path();
+            let extension = path.extension().and_then(|extension| extension.to_str());
+            if matches!(extension, Some("ch8" | "c8")) {
+                roms.insert(path);
+            }
+        }
+    }
+    Ok(roms.into_iter().collect())
+}
+
+/// Run every ROM under every quirks preset, on as many threads as there are cores
+pub fn run_all(roms: &[PathBuf], options: &CompatOptions) -> Vec<CompatResult> {
+    let runs: Vec<(usize, &PathBuf, (&'static str, Quirks))> = roms
+        .iter()
+        .enumerate()
+        .flat_map(|(number, rom)| {
+            Quirks::PRESETS
+                .iter()
+                .map(move |&preset| (number + 1, rom, preset))
+        })
+        .collect();
+    let threads = thread::available_parallelism()
+        .map_or(1, |threads| threads.get())
+        .min(runs.len());
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let Some(&(number, rom, preset)) = runs.get(index) else {
+                    break;
+                };
+                let result = run(number, rom, preset, options);
+                results.lock().unwrap().push((index, result));
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|&(index, _)| index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Run a ROM for `options.frames` frames with no keys held. `number` is its place
+/// in the list of ROMs, which keeps screenshot names apart.
+fn run(
+    number: usize,
+    rom: &Path,
+    (preset, quirks): (&'static str, Quirks),
+    options: &CompatOptions,
+) -> CompatResult {
+    let mut result = CompatResult {
+        rom: rom.to_path_buf(),
+        preset,
+        error: None,
+        unknown_opcodes: BTreeMap::new(),
+        stuck_at: None,
+        waiting_for_key: false,
+        blank: false,
+        screenshot: None,
+    };
+    // The same random numbers every run, so reports are comparable
+    let mut chip_8 = Chip8::with_seed(0);
+    chip_8.quirks = quirks;
+    let loaded = fs::read(rom)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| chip_8.load_rom(&bytes).map_err(|err| err.to_string()));
+    if let Err(err) = loaded {
+        result.error = Some(format!("can't load: {}", err));
+        return result;
+    }
+
+    let mut checks = Checks::default();
+    let held_keys = BTreeSet::new();
+    let run = panic::catch_unwind(AssertUnwindSafe(|| {
+        for _ in 0..options.frames {
+            chip_8.run_frame_observed(&held_keys, &mut checks);
+        }
+    }));
+    if let Err(panic) = run {
+        result.error = Some(format!("crashed: {}", panic_message(&*panic)));
+    }
+    checks.end_frame();
+    if let Some(pcs) = checks.stuck_loop() {
+        result.stuck_at = pcs.first().copied();
+        result.waiting_for_key = pcs.iter().any(|&pc| {
+            let opcode = chip_8
+                .memory
+                .get(pc as usize..pc as usize + 2)
+                .map_or(0, |bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+            matches!(
+                Instruction::decode(opcode),
+                Instruction::LdVxK { .. } | Instruction::Skp { .. } | Instruction::Sknp { .. }
+            )
+        });
+    }
+    result.unknown_opcodes = checks.unknown_opcodes;
+    result.blank = chip_8.display.is_empty();
+
+    if let Some(dir) = &options.screenshot_dir {
+        let name = rom.file_stem().unwrap_or_default().to_string_lossy();
+        let path = dir.join(format!("{:03}-{}-{}.png", number, name, preset));
+        let saved = Screenshot {
+            display: &chip_8.display,
+            width: globals::DISPLAY_WIDTH,
+            height: globals::DISPLAY_HEIGHT,
+            palette: options.palette,
+            scale: options.screenshot_scale,
+        }
+        .save(&path, ImageFormat::Png);
+        match saved {
+            Ok(()) => result.screenshot = Some(path),
+            Err(err) => result.error = Some(format!("can't save screenshot: {}", err)),
+        }
+    }
+    result
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message.to_string(),
+        (None, Some(message)) => message.clone(),
+        (None, None) => "panicked".to_string(),
+    }
+}
+
+/// A report with a row per run. Screenshot paths are relative to `report_dir`, the
+/// directory the report is written to, where they can be.
+pub fn report(
+    results: &[CompatResult],
+    frames: u64,
+    format: CompatFormat,
+    report_dir: &Path,
+) -> String {
+    let count = |status| {
+        results
+            .iter()
+            .filter(|result| result.status() == status)
+            .count()
+    };
+    let summary = format!(
+        "{} runs of {} frames: {} ok, {} with warnings, {} with errors",
+        results.len(),
+        frames,
+        count(Status::Ok),
+        count(Status::Warning),
+        count(Status::Error)
+    );
+    let rom_name = |result: &CompatResult| {
+        result
+            .rom
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string()
+    };
+    let screenshot = |result: &CompatResult| {
+        result.screenshot.as_ref().map(|path| {
+            path.strip_prefix(report_dir)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace('\\', "/")
+        })
+    };
+
+    let mut report = String::new();
+    match format {
+        CompatFormat::Markdown => {
+            let _ = writeln!(report, "# Compatibility report\n\n{}\n", summary);
+            let _ = writeln!(report, "| ROM | Quirks | Status | Notes | Screen |");
+            let _ = writeln!(report, "| --- | --- | --- | --- | --- |");
+            for result in results {
+                let screen = screenshot(result)
+                    .map(|path| format!("![{} {}]({})", rom_name(result), result.preset, path))
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    report,
+                    "| {} | {} | {} | {} | {} |",
+                    rom_name(result),
+                    result.preset,
+                    result.status().name(),
+                    result.notes().join("; ").replace('|', "\\|"),
+                    screen
+                );
+            }
+        }
+        CompatFormat::Html => {
+            report.push_str(concat!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+                "<title>CHIP-8 compatibility</title>\n<style>\n",
+                "td { padding: 4px 8px; vertical-align: top; }\n",
+                ".ok { color: green; }\n.warning { color: darkorange; }\n.error { color: red; }\n",
+                "</style>\n</head>\n<body>\n",
+            ));
+            let _ = writeln!(report, "<h1>Compatibility report</h1>\n<p>{}</p>", summary);
+            report.push_str(
+                "<table>\n<tr><th>ROM</th><th>Quirks</th><th>Status</th><th>Notes</th><th>Screen</th></tr>\n",
+            );
+            for result in results {
+                let status = result.status().name();
+                let screen = screenshot(result)
+                    .map(|path| format!("<img src=\"{}\">", escape(&path)))
+                    .unwrap_or_default();
+                let _ = writeln!(
+                    report,
+                    "<tr><td>{}</td><td>{}</td><td class=\"{}\">{}</td><td>{}</td><td>{}</td></tr>",
+                    escape(&rom_name(result)),
+                    result.preset,
+                    status,
+                    status,
+                    escape(&result.notes().join("; ")),
+                    screen
+                );
+            }
+            report.push_str("</table>\n</body>\n</html>\n");
+        }
+    }
+    report
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeSet, env, fs};
+
+    use super::{find_roms, report, run_all, CompatFormat, CompatOptions, Status};
+    use crate::Palette;
+
+    #[test]
+    fn runs_and_reports() {
+        let dir = env::temp_dir().join(format!("chip_8_compat_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        #[rustfmt::skip]
+        let roms: [(&str, &[u8]); 5] = [
+            ("draws.ch8", &[
+                0xA0, 0x50, // 200: LD I, 0x50
+                0xD0, 0x05, // 202: DRW V0, V0, 5
+                0xE0, 0x9E, // 204: SKP V0
+                0x12, 0x04, // 206: JP 0x204
+            ]),
+            ("spins.ch8", &[
+                0xA0, 0x50, // 200: LD I, 0x50
+                0xD0, 0x05, // 202: DRW V0, V0, 5
+                0x71, 0x01, // 204: ADD V1, 1
+                0x12, 0x04, // 206: JP 0x204
+            ]),
+            ("crashes.ch8", &[
+                0x00, 0xEE, // 200: RET with nothing on the stack
+            ]),
+            ("unknown.ch8", &[
+                0xA0, 0x50, // 200: LD I, 0x50
+                0xD0, 0x05, // 202: DRW V0, V0, 5
+                0xF0, 0x75, // 204: save flags, a SUPER-CHIP opcode
+                0x00, 0xFE, // 206: low resolution, another
+                0x12, 0x04, // 208: JP 0x204
+            ]),
+            ("waits.ch8", &[
+                0xF0, 0x0A, // 200: LD V0, K
+            ]),
+        ];
+        for (name, rom) in roms {
+            fs::write(dir.join(name), rom).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "not a ROM").unwrap();
+
+        // Another draws ROM, as draws.c8 in another directory
+        let other = dir.join("other");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("draws.c8"), roms[0].1).unwrap();
+
+        let roms = find_roms(&[dir.clone(), other]).unwrap();
+        assert_eq!(roms.len(), 6);
+        let options = CompatOptions {
+            frames: 120,
+            screenshot_dir: Some(dir.clone()),
+            palette: Palette::default(),
+            screenshot_scale: 1,
+        };
+        let results = run_all(&roms, &options);
+        assert_eq!(results.len(), 6 * 3);
+
+        let result = |name: &str| {
+            results
+                .iter()
+                .find(|result| result.rom.ends_with(name) && result.preset == "vip")
+                .unwrap()
+        };
+        assert_eq!(result("crashes.ch8").status(), Status::Error);
+        assert_eq!(result("draws.ch8").status(), Status::Ok);
+        assert!(result("draws.ch8").waiting_for_key);
+        assert_eq!(result("spins.ch8").status(), Status::Warning);
+        assert_eq!(result("spins.ch8").stuck_at, Some(0x204));
+        assert_eq!(result("unknown.ch8").status(), Status::Warning);
+        assert!(result("waits.ch8").waiting_for_key && result("waits.ch8").blank);
+        let screenshots: BTreeSet<_> = results
+            .iter()
+            .map(|result| result.screenshot.clone().unwrap())
+            .collect();
+        assert_eq!(screenshots.len(), results.len());
+        assert!(screenshots.iter().all(|path| path.exists()));
+
+        let markdown = report(&results, 120, CompatFormat::Markdown, &dir);
+        assert!(markdown.contains("18 runs of 120 frames: 6 ok, 9 with warnings, 3 with errors"));
+        assert!(markdown.contains(
+            "| draws.ch8 | vip | ok | waiting for a key at 0204 | ![draws.ch8 vip](002-draws-vip.png) |"
+        ));
+        assert!(markdown.contains(
+            "| unknown.ch8 | schip | warning | 2 unknown opcodes, first F075 at 0204; stuck at 0204 | ![unknown.ch8 schip]"
+        ));
+        let html = report(&results, 120, CompatFormat::Html, &dir);
+        assert!(html.contains("<td class=\"error\">error</td>"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
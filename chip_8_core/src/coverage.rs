@@ -0,0 +1,368 @@
+//! Which parts of a ROM a session exercised.
+//!
+//! Coverage data files are text, so runs can be merged and kept in version control:
+//! a `chip8-coverage 1` line, a `rom <length> <FNV-1a hash>` line identifying the
+//! ROM, then a line per touched address like `0200 x`, with `x` for executed as an
+//! instruction, `r` for read as data and `w` for written.
+
+use alloc::{format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{
+    chip_8::PROGRAM_START_LOCATION, globals::Err, instruction::MemoryAccess, Chip8, Instruction,
+    Observer,
+};
+
+const HEADER: &str = "chip8-coverage 1";
+
+const EXECUTED: u8 = 1;
+const READ: u8 = 2;
+const WRITTEN: u8 = 4;
+
+/// Report formats for `Coverage::report`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(clap::ValueEnum))]
+pub enum CoverageFormat {
+    /// Annotated disassembly
+    #[default]
+    Text,
+    /// Annotated disassembly with uncovered code highlighted
+    Html,
+}
+
+/// Records which addresses of memory were executed, read and written
+pub struct Coverage {
+    rom: Vec<u8>,
+    // EXECUTED, READ and WRITTEN flags per address
+    flags: Vec<u8>,
+}
+
+impl Coverage {
+    /// Track coverage of `rom`, which reports disassemble
+    pub fn new(rom: &[u8]) -> Self {
+        Coverage {
+            rom: rom.to_vec(),
+            flags: vec![0; 4096],
+        }
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    pub fn executed(&self, address: u16) -> bool {
+        self.flag(address, EXECUTED)
+    }
+
+    pub fn read(&self, address: u16) -> bool {
+        self.flag(address, READ)
+    }
+
+    pub fn written(&self, address: u16) -> bool {
+        self.flag(address, WRITTEN)
+    }
+
+    fn flag(&self, address: u16, flag: u8) -> bool {
+        self.flags
+            .get(address as usize)
+            .is_some_and(|flags| flags & flag != 0)
+    }
+
+    fn mark(&mut self, start: u16, len: usize, flag: u8) {
+        let start = start as usize;
+        let end = (start + len).min(self.flags.len());
+        for flags in self.flags.get_mut(start..end).unwrap_or_default() {
+            *flags |= flag;
+        }
+    }
+
+    /// Add the coverage of another run of the same ROM
+    pub fn merge(&mut self, other: &Coverage) -> Result<(), Err> {
+        if self.rom != other.rom {
+            return Err("can't merge coverage of different ROMs".into());
+        }
+        for (flags, other) in self.flags.iter_mut().zip(&other.flags) {
+            *flags |= other;
+        }
+        Ok(())
+    }
+
+    /// The coverage data file contents
+    pub fn to_data(&self) -> String {
+        let mut data = format!(
+            "{}\nrom {} {:016x}\n",
+            HEADER,
+            self.rom.len(),
+            fnv1a(&self.rom)
+        );
+        for (address, &flags) in self.flags.iter().enumerate() {
+            if flags != 0 {
+                let _ = writeln!(
+                    data,
+                    "{:04X} {}",
+                    address,
+                    flag_letters(flags).replace('-', "")
+                );
+            }
+        }
+        data
+    }
+
+    /// Read a coverage data file for `rom`
+    pub fn from_data(rom: &[u8], data: &str) -> Result<Coverage, Err> {
+        let mut lines = data.lines();
+        if lines.next() != Some(HEADER) {
+            return Err("not a coverage data file".into());
+        }
+        let rom_line = format!("rom {} {:016x}", rom.len(), fnv1a(rom));
+        if lines.next() != Some(rom_line.as_str()) {
+            return Err("the coverage data is for a different ROM".into());
+        }
+
+        let mut coverage = Coverage::new(rom);
+        for line in lines {
+            let invalid = || format!("invalid coverage line `{}`", line);
+            let (address, letters) = line.split_once(' ').ok_or_else(invalid)?;
+            let address = u16::from_str_radix(address, 16).map_err(|_| invalid())?;
+            for letter in letters.chars() {
+                let flag = match letter {
+                    'x' => EXECUTED,
+                    'r' => READ,
+                    'w' => WRITTEN,
+                    _ => return Err(invalid().into()),
+                };
+                coverage.mark(address, 1, flag);
+            }
+        }
+        Ok(coverage)
+    }
+
+    /// Bytes of the ROM executed, and in total
+    pub fn executed_bytes(&self) -> (usize, usize) {
+        let executed = (0..self.rom.len())
+            .filter(|&offset| self.executed((PROGRAM_START_LOCATION + offset) as u16))
+            .count();
+        (executed, self.rom.len())
+    }
+
+    /// An annotated disassembly of the ROM
+    pub fn report(&self, format: CoverageFormat) -> String {
+        let (executed, total) = self.executed_bytes();
+        let percent = if total == 0 {
+            0.0
+        } else {
+            executed as f64 * 100.0 / total as f64
+        };
+        let summary = format!(
+            "Executed {} of {} ROM bytes ({:.1}%). Flags: x executed, r read, w written.",
+            executed, total, percent
+        );
+
+        let mut report = String::new();
+        if format == CoverageFormat::Html {
+            report.push_str(concat!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n",
+                "<title>CHIP-8 coverage</title>\n<style>\n",
+                ".data { color: #06c; }\n",
+                ".uncovered { background: #fcc; }\n",
+                "</style>\n</head>\n<body>\n",
+            ));
+            let _ = writeln!(report, "<p>{}</p>\n<pre>", summary);
+        } else {
+            let _ = writeln!(report, "{}\n", summary);
+        }
+
+        for line in self.lines() {
+            let text = format!(
+                "{} {:04X}  {:<4}  {}",
+                flag_letters(line.flags),
+                line.address,
+                line.bytes,
+                line.text
+            );
+            match format {
+                CoverageFormat::Text => {
+                    let _ = writeln!(report, "{}", text.trim_end());
+                }
+                CoverageFormat::Html => {
+                    let class = if line.flags & EXECUTED != 0 {
+                        "code"
+                    } else if line.flags != 0 {
+                        "data"
+                    } else {
+                        "uncovered"
+                    };
+                    let _ = writeln!(
+                        report,
+                        "<span class=\"{}\">{}</span>",
+                        class,
+                        text.trim_end()
+                    );
+                }
+            }
+        }
+
+        if format == CoverageFormat::Html {
+            report.push_str("</pre>\n</body>\n</html>\n");
+        }
+        report
+    }
+
+    /// Split the ROM into instructions and data bytes. Untouched bytes are shown
+    /// as instructions, unless the next byte starts one.
+    fn lines(&self) -> Vec<Line> {
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < self.rom.len() {
+            let address = (PROGRAM_START_LOCATION + offset) as u16;
+            let flags = self.flags[address as usize];
+            let is_instruction = offset + 1 < self.rom.len()
+                && (flags & EXECUTED != 0 || (flags == 0 && !self.executed(address + 1)));
+
+            if is_instruction {
+                let opcode = BigEndian::read_u16(&self.rom[offset..offset + 2]);
+                lines.push(Line {
+                    address,
+                    flags: flags | self.flags[address as usize + 1],
+                    bytes: format!("{:04X}", opcode),
+                    text: format!("{}", Instruction::decode(opcode)),
+                });
+                offset += 2;
+            } else {
+                let byte = self.rom[offset];
+                lines.push(Line {
+                    address,
+                    flags,
+                    bytes: format!("{:02X}", byte),
+                    text: format!("DB {:#04X}", byte),
+                });
+                offset += 1;
+            }
+        }
+        lines
+    }
+}
+
+struct Line {
+    address: u16,
+    flags: u8,
+    bytes: String,
+    text: String,
+}
+
+fn flag_letters(flags: u8) -> String {
+    [(EXECUTED, 'x'), (READ, 'r'), (WRITTEN, 'w')]
+        .iter()
+        .map(|&(flag, letter)| if flags & flag != 0 { letter } else { '-' })
+        .collect()
+}
+
+/// 64 bit FNV-1a, to tell ROMs and machine states apart
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Observer for Coverage {
+    fn before_cycle(&mut self, chip_8: &Chip8) {
+        let pc = chip_8.pc;
+        let Some(opcode) = chip_8.memory.get(pc as usize..pc as usize + 2) else {
+            return;
+        };
+        self.mark(pc, 2, EXECUTED);
+
+        let instruction = Instruction::decode(BigEndian::read_u16(opcode));
+        match instruction.memory_access() {
+            Some((MemoryAccess::Read, len)) => self.mark(chip_8.i, len as usize, READ),
+            Some((MemoryAccess::Write, len)) => self.mark(chip_8.i, len as usize, WRITTEN),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::collections::BTreeSet;
+
+    use super::{Coverage, CoverageFormat};
+    use crate::Chip8;
+
+    #[rustfmt::skip]
+    const ROM: [u8; 14] = [
+        0xA2, 0x0C, // 200: LD I, 0x20C
+        0xD0, 0x02, // 202: DRW V0, V0, 2
+        0x12, 0x0A, // 204: JP 0x20A
+        0x60, 0x01, // 206: LD V0, 0x01 (never run)
+        0x00,       // 208: padding
+        0x00,       // 209: padding
+        0x12, 0x0A, // 20A: JP 0x20A
+        0xFF, 0x81, // 20C: sprite
+    ];
+
+    fn covered() -> Coverage {
+        let mut chip_8 = Chip8::new();
+        chip_8.load_rom(&ROM).unwrap();
+        let mut coverage = Coverage::new(&ROM);
+        chip_8.run_frame_observed(&BTreeSet::new(), &mut coverage);
+        coverage
+    }
+
+    #[test]
+    fn reports_coverage() {
+        let coverage = covered();
+        assert!(coverage.executed(0x202) && coverage.executed(0x20B));
+        assert!(!coverage.executed(0x206));
+        assert!(coverage.read(0x20D) && !coverage.read(0x20E));
+        assert_eq!(coverage.executed_bytes(), (8, 14));
+
+        let report = coverage.report(CoverageFormat::Text);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines[0],
+            "Executed 8 of 14 ROM bytes (57.1%). Flags: x executed, r read, w written."
+        );
+        assert_eq!(lines[2], "x-- 0200  A20C  LD I, 0x20C");
+        assert_eq!(lines[5], "--- 0206  6001  LD V0, 0x01");
+        // Data is shown a byte at a time
+        assert_eq!(lines[8], "-r- 020C  FF    DB 0xFF");
+        assert_eq!(lines[9], "-r- 020D  81    DB 0x81");
+
+        let html = coverage.report(CoverageFormat::Html);
+        assert!(html.contains("<span class=\"uncovered\">--- 0206  6001  LD V0, 0x01</span>"));
+    }
+
+    #[test]
+    fn merges_data_files() {
+        let data = covered().to_data();
+        assert!(data.starts_with("chip8-coverage 1\nrom 14 "));
+        assert!(data.ends_with("020B x\n020C r\n020D r\n"));
+
+        let mut other = Coverage::new(&ROM);
+        other.mark(0x206, 2, super::EXECUTED);
+        other
+            .merge(&Coverage::from_data(&ROM, &data).unwrap())
+            .unwrap();
+        assert_eq!(other.executed_bytes(), (10, 14));
+        assert_eq!(other.to_data().lines().count(), data.lines().count() + 2);
+
+        assert!(Coverage::from_data(&ROM[..12], &data).is_err());
+        assert!(Coverage::new(&ROM[..12]).merge(&other).is_err());
+    }
+
+    #[test]
+    fn keeps_mixed_flags() {
+        // Self-modifying code is executed and written, data read and written
+        let mut coverage = Coverage::new(&ROM);
+        coverage.mark(0x200, 1, super::EXECUTED | super::WRITTEN);
+        coverage.mark(0x20C, 1, super::READ | super::WRITTEN);
+        coverage.mark(0x20D, 1, super::EXECUTED | super::READ | super::WRITTEN);
+        let data = coverage.to_data();
+        assert!(data.ends_with("0200 xw\n020C rw\n020D xrw\n"));
+
+        let restored = Coverage::from_data(&ROM, &data).unwrap();
+        assert_eq!(restored.to_data(), data);
+    }
+}
@@ -0,0 +1,258 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{globals, instruction::MemoryAccess, Chip8, Instruction, Observer};
+
+/// Why the debugger stopped running
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// The frame's instructions all ran
+    FrameEnd,
+    /// The PC reached a breakpoint; the instruction there hasn't run yet
+    Breakpoint(u16),
+    /// An instruction touched a watched address; it has already run
+    Watchpoint(u16, Watch),
+    /// The instruction at this PC would stop the emulator (see `Chip8::fault`),
+    /// so it hasn't run
+    Fault(u16),
+}
+
+/// What to watch an address for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+impl Watch {
+    fn matches(self, access: MemoryAccess) -> bool {
+        matches!(
+            (self, access),
+            (Watch::Access, _)
+                | (Watch::Read, MemoryAccess::Read)
+                | (Watch::Write, MemoryAccess::Write)
+        )
+    }
+}
+
+/// Runs a Chip8 an instruction at a time, so it can stop in the middle of a frame
+#[derive(Default)]
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u16>,
+    pub watchpoints: BTreeMap<u16, Watch>,
+    // Frames started, and instructions run so far in the current one
+    frames: u64,
+    cycle: u32,
+    // Where the debugger last stopped, so continuing doesn't stop there again
+    stopped_at: Option<u16>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger::default()
+    }
+
+    /// Frames started so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Instructions run so far in the current frame
+    pub fn cycle(&self) -> u32 {
+        self.cycle
+    }
+
+    /// Run one instruction, starting a new frame first if the last one is over.
+    /// Returns whether that finished the frame.
+    pub fn step(
+        &mut self,
+        chip_8: &mut Chip8,
+        held_keys: &BTreeSet<u8>,
+        observer: &mut dyn Observer,
+    ) -> bool {
+        let frame_end = self.run_cycle(chip_8, held_keys, observer);
+        self.stopped_at = Some(chip_8.pc);
+        frame_end
+    }
+
+    /// Run to the end of the frame, stopping early at a breakpoint or watchpoint
+    pub fn run_frame(
+        &mut self,
+        chip_8: &mut Chip8,
+        held_keys: &BTreeSet<u8>,
+        observer: &mut dyn Observer,
+    ) -> Stop {
+        let mut resuming = self.stopped_at.take() == Some(chip_8.pc);
+        loop {
+            if !resuming && self.breakpoints.contains(&chip_8.pc) {
+                self.stopped_at = Some(chip_8.pc);
+                return Stop::Breakpoint(chip_8.pc);
+            }
+            resuming = false;
+            if chip_8.fault().is_some() {
+                return Stop::Fault(chip_8.pc);
+            }
+            let watched = self.watched(chip_8);
+            let frame_end = self.run_cycle(chip_8, held_keys, observer);
+            if let Some((address, watch)) = watched {
+                return Stop::Watchpoint(address, watch);
+            }
+            if frame_end {
+                return Stop::FrameEnd;
+            }
+        }
+    }
+
+    /// The first watchpoint the instruction at the PC will trigger
+    fn watched(&self, chip_8: &Chip8) -> Option<(u16, Watch)> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+        let pc = chip_8.pc as usize;
+        let opcode = chip_8.memory.get(pc..pc + 2)?;
+        let (access, len) = Instruction::decode(BigEndian::read_u16(opcode)).memory_access()?;
+        let end = chip_8.i.saturating_add(len);
+        self.watchpoints
+            .range(chip_8.i..end)
+            .find(|&(_, watch)| watch.matches(access))
+            .map(|(&address, &watch)| (address, watch))
+    }
+
+    fn run_cycle(
+        &mut self,
+        chip_8: &mut Chip8,
+        held_keys: &BTreeSet<u8>,
+        observer: &mut dyn Observer,
+    ) -> bool {
+        if self.cycle == 0 {
+            self.frames += 1;
+            observer.frame_start(chip_8);
+            chip_8.decrement_counters();
+        }
+        observer.before_cycle(chip_8);
+        chip_8.run_cycle(held_keys);
+        observer.after_cycle(chip_8);
+
+        self.cycle += 1;
+        if self.cycle == globals::INSTRUCTIONS_PER_FRAME as u32 {
+            self.cycle = 0;
+            observer.frame_end(chip_8);
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::collections::BTreeSet;
+
+    use super::{Debugger, Stop, Watch};
+    use crate::{globals, Chip8};
+
+    fn chip_8() -> Chip8 {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0x70, 0x01, // 200: ADD V0, 1
+            0x71, 0x01, // 202: ADD V1, 1
+            0x12, 0x00, // 204: JP 0x200
+        ]).unwrap();
+        chip_8
+    }
+
+    #[test]
+    fn stops_at_breakpoints() {
+        let mut chip_8 = chip_8();
+        let mut debugger = Debugger::new();
+        debugger.breakpoints.insert(0x202);
+
+        let keys = BTreeSet::new();
+        assert_eq!(
+            debugger.run_frame(&mut chip_8, &keys, &mut ()),
+            Stop::Breakpoint(0x202)
+        );
+        assert_eq!((chip_8.v[0], chip_8.v[1], debugger.cycle()), (1, 0, 1));
+
+        // Continuing runs the instruction at the breakpoint, then stops there next time around
+        assert_eq!(
+            debugger.run_frame(&mut chip_8, &keys, &mut ()),
+            Stop::Breakpoint(0x202)
+        );
+        assert_eq!((chip_8.v[0], chip_8.v[1], debugger.cycle()), (2, 1, 4));
+
+        debugger.breakpoints.clear();
+        assert_eq!(
+            debugger.run_frame(&mut chip_8, &keys, &mut ()),
+            Stop::FrameEnd
+        );
+        assert_eq!(debugger.cycle(), 0);
+        assert_eq!(debugger.frames(), 1);
+    }
+
+    #[test]
+    fn stops_before_faults() {
+        let mut chip_8 = chip_8();
+        chip_8.memory[0x204..0x206].copy_from_slice(&[0x00, 0xEE]); // 204: RET
+        let mut debugger = Debugger::new();
+        let keys = BTreeSet::new();
+        assert_eq!(
+            debugger.run_frame(&mut chip_8, &keys, &mut ()),
+            Stop::Fault(0x204)
+        );
+        assert_eq!(chip_8.fault(), Some("RET with an empty stack"));
+
+        chip_8.pc = 0xFFF;
+        assert_eq!(
+            debugger.run_frame(&mut chip_8, &keys, &mut ()),
+            Stop::Fault(0xFFF)
+        );
+    }
+
+    #[test]
+    fn steps_match_frames() {
+        let mut stepped = chip_8();
+        let mut debugger = Debugger::new();
+        stepped.dt = 10;
+        for _ in 0..globals::INSTRUCTIONS_PER_FRAME - 1 {
+            assert!(!debugger.step(&mut stepped, &BTreeSet::new(), &mut ()));
+        }
+        assert!(debugger.step(&mut stepped, &BTreeSet::new(), &mut ()));
+
+        let mut run = chip_8();
+        run.dt = 10;
+        run.run_frame(&BTreeSet::new());
+        assert_eq!((stepped.pc, stepped.v, stepped.dt), (run.pc, run.v, run.dt));
+    }
+
+    #[test]
+    fn stops_at_watchpoints() {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0xA3, 0x00, // 200: LD I, 0x300
+            0xF1, 0x65, // 202: LD V1, [I]
+            0xA3, 0x00, // 204: LD I, 0x300
+            0xF1, 0x55, // 206: LD [I], V1
+            0x12, 0x04, // 208: JP 0x204
+        ]).unwrap();
+        let mut debugger = Debugger::new();
+        debugger.watchpoints.insert(0x301, Watch::Write);
+
+        let keys = BTreeSet::new();
+        assert_eq!(
+            debugger.run_frame(&mut chip_8, &keys, &mut ()),
+            Stop::Watchpoint(0x301, Watch::Write)
+        );
+        assert_eq!((chip_8.pc, debugger.cycle()), (0x208, 4));
+
+        debugger.watchpoints.insert(0x301, Watch::Read);
+        debugger.watchpoints.insert(0x2FF, Watch::Access);
+        assert_eq!(
+            debugger.run_frame(&mut chip_8, &keys, &mut ()),
+            Stop::FrameEnd
+        );
+    }
+}
@@ -1,10 +1,10 @@
-use std::collections::HashSet;
+use alloc::{boxed::Box, collections::BTreeSet};
 
-pub type Err = Box<dyn std::error::Error>;
+pub type Err = Box<dyn core::error::Error>;
 
 pub enum Keys {
     Break,
-    Keys(HashSet<u8>),
+    Keys(BTreeSet<u8>),
 }
 
 pub const FRAMES_PER_SECOND: i32 = 60;
@@ -0,0 +1,317 @@
+use core::fmt;
+
+/// A decoded chip8 instruction.
+/// `x` and `y` are register numbers, `n`/`nn`/`nnn` are 4/8/12 bit immediates.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0NNN -- Machine code routine, ignored
+    Sys { nnn: u16 },
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 1NNN
+    Jp { nnn: u16 },
+    /// 2NNN
+    Call { nnn: u16 },
+    /// 3XNN
+    SeByte { x: u8, nn: u8 },
+    /// 4XNN
+    SneByte { x: u8, nn: u8 },
+    /// 5XY0
+    SeReg { x: u8, y: u8 },
+    /// 6XNN
+    LdByte { x: u8, nn: u8 },
+    /// 7XNN
+    AddByte { x: u8, nn: u8 },
+    /// 8XY0
+    LdReg { x: u8, y: u8 },
+    /// 8XY1
+    Or { x: u8, y: u8 },
+    /// 8XY2
+    And { x: u8, y: u8 },
+    /// 8XY3
+    Xor { x: u8, y: u8 },
+    /// 8XY4
+    AddReg { x: u8, y: u8 },
+    /// 8XY5
+    Sub { x: u8, y: u8 },
+    /// 8XY6
+    Shr { x: u8, y: u8 },
+    /// 8XY7
+    Subn { x: u8, y: u8 },
+    /// 8XYE
+    Shl { x: u8, y: u8 },
+    /// 9XY0
+    SneReg { x: u8, y: u8 },
+    /// ANNN
+    LdI { nnn: u16 },
+    /// BNNN
+    JpV0 { nnn: u16 },
+    /// CXNN
+    Rnd { x: u8, nn: u8 },
+    /// DXYN
+    Drw { x: u8, y: u8, n: u8 },
+    /// EX9E
+    Skp { x: u8 },
+    /// EXA1
+    Sknp { x: u8 },
+    /// FX07
+    LdVxDt { x: u8 },
+    /// FX0A
+    LdVxK { x: u8 },
+    /// FX15
+    LdDtVx { x: u8 },
+    /// FX18
+    LdStVx { x: u8 },
+    /// FX1E
+    AddIVx { x: u8 },
+    /// FX29
+    LdFVx { x: u8 },
+    /// FX33
+    LdBVx { x: u8 },
+    /// FX55
+    LdIVx { x: u8 },
+    /// FX65
+    LdVxI { x: u8 },
+    /// Anything else, which the interpreter skips
+    Unknown { opcode: u16 },
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Instruction {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let nn = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match (opcode >> 12, n) {
+            _ if opcode == 0x00E0 => Instruction::Cls,
+            _ if opcode == 0x00EE => Instruction::Ret,
+            (0x0, _) => Instruction::Sys { nnn },
+            (0x1, _) => Instruction::Jp { nnn },
+            (0x2, _) => Instruction::Call { nnn },
+            (0x3, _) => Instruction::SeByte { x, nn },
+            (0x4, _) => Instruction::SneByte { x, nn },
+            (0x5, 0x0) => Instruction::SeReg { x, y },
+            (0x6, _) => Instruction::LdByte { x, nn },
+            (0x7, _) => Instruction::AddByte { x, nn },
+            (0x8, 0x0) => Instruction::LdReg { x, y },
+            (0x8, 0x1) => Instruction::Or { x, y },
+            (0x8, 0x2) => Instruction::And { x, y },
+            (0x8, 0x3) => Instruction::Xor { x, y },
+            (0x8, 0x4) => Instruction::AddReg { x, y },
+            (0x8, 0x5) => Instruction::Sub { x, y },
+            (0x8, 0x6) => Instruction::Shr { x, y },
+            (0x8, 0x7) => Instruction::Subn { x, y },
+            (0x8, 0xE) => Instruction::Shl { x, y },
+            (0x9, 0x0) => Instruction::SneReg { x, y },
+            (0xA, _) => Instruction::LdI { nnn },
+            (0xB, _) => Instruction::JpV0 { nnn },
+            (0xC, _) => Instruction::Rnd { x, nn },
+            (0xD, _) => Instruction::Drw { x, y, n },
+            (0xE, _) if nn == 0x9E => Instruction::Skp { x },
+            (0xE, _) if nn == 0xA1 => Instruction::Sknp { x },
+            (0xF, _) => match nn {
+                0x07 => Instruction::LdVxDt { x },
+                0x0A => Instruction::LdVxK { x },
+                0x15 => Instruction::LdDtVx { x },
+                0x18 => Instruction::LdStVx { x },
+                0x1E => Instruction::AddIVx { x },
+                0x29 => Instruction::LdFVx { x },
+                0x33 => Instruction::LdBVx { x },
+                0x55 => Instruction::LdIVx { x },
+                0x65 => Instruction::LdVxI { x },
+                _ => Instruction::Unknown { opcode },
+            },
+            _ => Instruction::Unknown { opcode },
+        }
+    }
+
+    /// The opcode pattern this instruction matches, e.g. `8XY4`.
+    /// Used to group instructions into classes.
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Instruction::Sys { .. } => "0NNN",
+            Instruction::Cls => "00E0",
+            Instruction::Ret => "00EE",
+            Instruction::Jp { .. } => "1NNN",
+            Instruction::Call { .. } => "2NNN",
+            Instruction::SeByte { .. } => "3XNN",
+            Instruction::SneByte { .. } => "4XNN",
+            Instruction::SeReg { .. } => "5XY0",
+            Instruction::LdByte { .. } => "6XNN",
+            Instruction::AddByte { .. } => "7XNN",
+            Instruction::LdReg { .. } => "8XY0",
+            Instruction::Or { .. } => "8XY1",
+            Instruction::And { .. } => "8XY2",
+            Instruction::Xor { .. } => "8XY3",
+            Instruction::AddReg { .. } => "8XY4",
+            Instruction::Sub { .. } => "8XY5",
+            Instruction::Shr { .. } => "8XY6",
+            Instruction::Subn { .. } => "8XY7",
+            Instruction::Shl { .. } => "8XYE",
+            Instruction::SneReg { .. } => "9XY0",
+            Instruction::LdI { .. } => "ANNN",
+            Instruction::JpV0 { .. } => "BNNN",
+            Instruction::Rnd { .. } => "CXNN",
+            Instruction::Drw { .. } => "DXYN",
+            Instruction::Skp { .. } => "EX9E",
+            Instruction::Sknp { .. } => "EXA1",
+            Instruction::LdVxDt { .. } => "FX07",
+            Instruction::LdVxK { .. } => "FX0A",
+            Instruction::LdDtVx { .. } => "FX15",
+            Instruction::LdStVx { .. } => "FX18",
+            Instruction::AddIVx { .. } => "FX1E",
+            Instruction::LdFVx { .. } => "FX29",
+            Instruction::LdBVx { .. } => "FX33",
+            Instruction::LdIVx { .. } => "FX55",
+            Instruction::LdVxI { .. } => "FX65",
+            Instruction::Unknown { .. } => "????",
+        }
+    }
+
+    /// What kind of thing the instruction does, e.g. `flow` or `display`
+    pub fn category(&self) -> &'static str {
+        match self {
+            Instruction::Cls | Instruction::Drw { .. } => "display",
+            Instruction::Sys { .. }
+            | Instruction::Ret
+            | Instruction::Jp { .. }
+            | Instruction::Call { .. }
+            | Instruction::JpV0 { .. } => "flow",
+            Instruction::SeByte { .. }
+            | Instruction::SneByte { .. }
+            | Instruction::SeReg { .. }
+            | Instruction::SneReg { .. } => "branch",
+            Instruction::LdByte { .. }
+            | Instruction::AddByte { .. }
+            | Instruction::LdReg { .. }
+            | Instruction::Or { .. }
+            | Instruction::And { .. }
+            | Instruction::Xor { .. }
+            | Instruction::AddReg { .. }
+            | Instruction::Sub { .. }
+            | Instruction::Shr { .. }
+            | Instruction::Subn { .. }
+            | Instruction::Shl { .. }
+            | Instruction::Rnd { .. } => "arithmetic",
+            Instruction::LdI { .. }
+            | Instruction::AddIVx { .. }
+            | Instruction::LdFVx { .. }
+            | Instruction::LdBVx { .. }
+            | Instruction::LdIVx { .. }
+            | Instruction::LdVxI { .. } => "memory",
+            Instruction::Skp { .. } | Instruction::Sknp { .. } | Instruction::LdVxK { .. } => {
+                "input"
+            }
+            Instruction::LdVxDt { .. }
+            | Instruction::LdDtVx { .. }
+            | Instruction::LdStVx { .. } => "timers",
+            Instruction::Unknown { .. } => "unknown",
+        }
+    }
+
+    /// Whether the instruction reads or writes memory starting at I, and how many bytes
+    pub fn memory_access(&self) -> Option<(MemoryAccess, u16)> {
+        match *self {
+            Instruction::Drw { n, .. } => Some((MemoryAccess::Read, n as u16)),
+            Instruction::LdVxI { x } => Some((MemoryAccess::Read, x as u16 + 1)),
+            Instruction::LdBVx { .. } => Some((MemoryAccess::Write, 3)),
+            Instruction::LdIVx { x } => Some((MemoryAccess::Write, x as u16 + 1)),
+            _ => None,
+        }
+    }
+}
+
+/// How an instruction uses the memory at I
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+}
+
+/// Disassembly, in the mnemonics of Cowgod's technical reference
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::Sys { nnn } => write!(f, "SYS {:#05X}", nnn),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jp { nnn } => write!(f, "JP {:#05X}", nnn),
+            Instruction::Call { nnn } => write!(f, "CALL {:#05X}", nnn),
+            Instruction::SeByte { x, nn } => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            Instruction::SneByte { x, nn } => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            Instruction::SeReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LdByte { x, nn } => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            Instruction::AddByte { x, nn } => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            Instruction::LdReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI { nnn } => write!(f, "LD I, {:#05X}", nnn),
+            Instruction::JpV0 { nnn } => write!(f, "JP V0, {:#05X}", nnn),
+            Instruction::Rnd { x, nn } => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Instruction::Drw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp { x } => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp { x } => write!(f, "SKNP V{:X}", x),
+            Instruction::LdVxDt { x } => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK { x } => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx { x } => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx { x } => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIVx { x } => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdFVx { x } => write!(f, "LD F, V{:X}", x),
+            Instruction::LdBVx { x } => write!(f, "LD B, V{:X}", x),
+            Instruction::LdIVx { x } => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI { x } => write!(f, "LD V{:X}, [I]", x),
+            Instruction::Unknown { opcode } => write!(f, "DW {:#06X}", opcode),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::string::ToString;
+
+    use super::Instruction;
+
+    #[test]
+    fn decodes_instructions() {
+        assert_eq!(Instruction::decode(0x00E0), Instruction::Cls);
+        assert_eq!(
+            Instruction::decode(0x8AB4),
+            Instruction::AddReg { x: 0xA, y: 0xB }
+        );
+        assert_eq!(
+            Instruction::decode(0xD125),
+            Instruction::Drw { x: 1, y: 2, n: 5 }
+        );
+        assert_eq!(
+            Instruction::decode(0x5121),
+            Instruction::Unknown { opcode: 0x5121 }
+        );
+        assert_eq!(
+            Instruction::decode(0xF0FF),
+            Instruction::Unknown { opcode: 0xF0FF }
+        );
+        assert_eq!(Instruction::decode(0xE39E).pattern(), "EX9E");
+    }
+
+    #[test]
+    fn disassembles_instructions() {
+        let disassemble = |opcode| Instruction::decode(opcode).to_string();
+        assert_eq!(disassemble(0x2ABC), "CALL 0xABC");
+        assert_eq!(disassemble(0x3F0A), "SE VF, 0x0A");
+        assert_eq!(disassemble(0xD015), "DRW V0, V1, 5");
+        assert_eq!(disassemble(0xF355), "LD [I], V3");
+        assert_eq!(disassemble(0x0123), "SYS 0x123");
+        assert_eq!(disassemble(0xE0FF), "DW 0xE0FF");
+    }
+}
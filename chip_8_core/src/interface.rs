@@ -1,4 +1,7 @@
+use alloc::vec::Vec;
+
 use crate::{
+    cheat::Cheat,
     chip_8::Chip8,
     globals::{Err, Keys},
 };
@@ -13,6 +16,10 @@ pub trait Interface {
 
     fn load_rom(&mut self, chip_8: &mut Chip8) -> Result<(), Err>;
 
+    /// Replace the cheats applied after each frame, when another ROM is loaded.
+    /// Interfaces without cheats ignore them.
+    fn set_cheats(&mut self, _cheats: Vec<Cheat>) {}
+
     /// Read held chip8 keys, or a break signal
     fn read_keys(&mut self) -> Result<Keys, Err>;
 
@@ -20,4 +27,10 @@ pub trait Interface {
     fn draw(&mut self, chip_8: &mut Chip8) -> Result<(), Err>;
 
     fn cleanup(&mut self) -> Result<(), Err>;
+
+    /// Whether the run loop should wait between frames to run at 60 Hz.
+    /// Interfaces nobody is watching can run flat out.
+    fn real_time(&self) -> bool {
+        true
+    }
 }
@@ -1,9 +1,61 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod analysis;
+
+pub mod cheat;
+
 pub mod chip_8;
 pub use chip_8::Chip8;
 
 pub mod globals;
 
+pub mod instruction;
+pub use instruction::Instruction;
+
+pub mod observer;
+pub use observer::Observer;
+
+pub mod palette;
+pub use palette::Palette;
+
+pub mod persistence;
+pub use persistence::Persistence;
+
+pub mod quirks;
+pub use quirks::Quirks;
+
+pub mod profiler;
+pub use profiler::Profiler;
+
+pub mod coverage;
+pub use coverage::Coverage;
+
+pub mod debugger;
+pub use debugger::Debugger;
+
 pub mod interface;
 pub use interface::Interface;
 
+#[cfg(feature = "std")]
+pub mod compat;
+
+#[cfg(feature = "std")]
 pub mod runner;
+
+#[cfg(feature = "std")]
+pub mod recording;
+
+#[cfg(feature = "std")]
+pub mod screenshot;
+
+#[cfg(feature = "std")]
+pub mod sprite_sheet;
+
+#[cfg(feature = "std")]
+pub mod trace;
+
+mod rng;
+
+mod state;
@@ -0,0 +1,111 @@
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::{globals::Err, Chip8};
+
+/// Watches a Chip8 run, e.g. to trace or profile it.
+/// Every method does nothing by default.
+pub trait Observer {
+    /// Called at the start of each frame, before the timers tick
+    fn frame_start(&mut self, _chip_8: &Chip8) {}
+
+    /// Called before each instruction runs, with the PC on it
+    fn before_cycle(&mut self, _chip_8: &Chip8) {}
+
+    /// Called after each instruction runs
+    fn after_cycle(&mut self, _chip_8: &Chip8) {}
+
+    /// Called once all of a frame's instructions have run
+    fn frame_end(&mut self, _chip_8: &Chip8) {}
+
+    /// Called once the run is over, to write out results
+    fn finish(&mut self) -> Result<(), Err> {
+        Ok(())
+    }
+}
+
+/// Observes nothing
+impl Observer for () {}
+
+impl<T: Observer + ?Sized> Observer for &mut T {
+    fn frame_start(&mut self, chip_8: &Chip8) {
+        (**self).frame_start(chip_8);
+    }
+
+    fn before_cycle(&mut self, chip_8: &Chip8) {
+        (**self).before_cycle(chip_8);
+    }
+
+    fn after_cycle(&mut self, chip_8: &Chip8) {
+        (**self).after_cycle(chip_8);
+    }
+
+    fn frame_end(&mut self, chip_8: &Chip8) {
+        (**self).frame_end(chip_8);
+    }
+
+    fn finish(&mut self) -> Result<(), Err> {
+        (**self).finish()
+    }
+}
+
+/// Passes everything on to the first observer, then the second
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn frame_start(&mut self, chip_8: &Chip8) {
+        self.0.frame_start(chip_8);
+        self.1.frame_start(chip_8);
+    }
+
+    fn before_cycle(&mut self, chip_8: &Chip8) {
+        self.0.before_cycle(chip_8);
+        self.1.before_cycle(chip_8);
+    }
+
+    fn after_cycle(&mut self, chip_8: &Chip8) {
+        self.0.after_cycle(chip_8);
+        self.1.after_cycle(chip_8);
+    }
+
+    fn frame_end(&mut self, chip_8: &Chip8) {
+        self.0.frame_end(chip_8);
+        self.1.frame_end(chip_8);
+    }
+
+    fn finish(&mut self) -> Result<(), Err> {
+        self.0.finish()?;
+        self.1.finish()
+    }
+}
+
+/// Passes everything on to each observer in turn
+impl Observer for Vec<Box<dyn Observer>> {
+    fn frame_start(&mut self, chip_8: &Chip8) {
+        for observer in self.iter_mut() {
+            observer.frame_start(chip_8);
+        }
+    }
+
+    fn before_cycle(&mut self, chip_8: &Chip8) {
+        for observer in self.iter_mut() {
+            observer.before_cycle(chip_8);
+        }
+    }
+
+    fn after_cycle(&mut self, chip_8: &Chip8) {
+        for observer in self.iter_mut() {
+            observer.after_cycle(chip_8);
+        }
+    }
+
+    fn frame_end(&mut self, chip_8: &Chip8) {
+        for observer in self.iter_mut() {
+            observer.frame_end(chip_8);
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), Err> {
+        for observer in self.iter_mut() {
+            observer.finish()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,181 @@
+use alloc::{format, string::String, vec::Vec};
+use core::{fmt, str::FromStr};
+
+/// An RGB color
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+}
+
+impl fmt::Display for Rgb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+}
+
+impl FromStr for Rgb {
+    type Err = String;
+
+    /// Parse a hex color, `#RRGGBB` or `RRGGBB`
+    fn from_str(s: &str) -> Result<Rgb, String> {
+        let hex = s.strip_prefix('#').unwrap_or(s);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("`{}` isn't a hex color like #FFCC00", s));
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Ok(Rgb::new(channel(0), channel(2), channel(4)))
+    }
+}
+
+/// Display colors.
+/// XO-CHIP draws on two bitplanes, so there are four colors: background,
+/// plane 1, plane 2, and pixels lit on both planes.
+/// Plain chip8 only uses the first two.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [Rgb; 4],
+}
+
+impl Palette {
+    pub const CLASSIC: Palette = Palette {
+        colors: [
+            Rgb::new(0x00, 0x00, 0x00),
+            Rgb::new(0xFF, 0xFF, 0xFF),
+            Rgb::new(0xAA, 0xAA, 0xAA),
+            Rgb::new(0x55, 0x55, 0x55),
+        ],
+    };
+    pub const AMBER: Palette = Palette {
+        colors: [
+            Rgb::new(0x1A, 0x0F, 0x00),
+            Rgb::new(0xFF, 0xB0, 0x00),
+            Rgb::new(0xCC, 0x7A, 0x00),
+            Rgb::new(0x66, 0x3D, 0x00),
+        ],
+    };
+    pub const GREEN_PHOSPHOR: Palette = Palette {
+        colors: [
+            Rgb::new(0x05, 0x14, 0x05),
+            Rgb::new(0x33, 0xFF, 0x33),
+            Rgb::new(0x20, 0xA0, 0x20),
+            Rgb::new(0x10, 0x50, 0x10),
+        ],
+    };
+    /// Octo's default colors
+    pub const OCTO: Palette = Palette {
+        colors: [
+            Rgb::new(0x99, 0x66, 0x00),
+            Rgb::new(0xFF, 0xCC, 0x00),
+            Rgb::new(0xFF, 0x66, 0x00),
+            Rgb::new(0x66, 0x22, 0x00),
+        ],
+    };
+
+    /// The built-in palettes, in hotkey cycling order
+    pub const NAMED: [(&'static str, Palette); 4] = [
+        ("classic", Palette::CLASSIC),
+        ("amber", Palette::AMBER),
+        ("green", Palette::GREEN_PHOSPHOR),
+        ("octo", Palette::OCTO),
+    ];
+
+    /// Background color
+    pub fn off(&self) -> Rgb {
+        self.colors[0]
+    }
+
+    /// Color of lit pixels
+    pub fn on(&self) -> Rgb {
+        self.colors[1]
+    }
+
+    /// The built-in palette after this one, for cycling with a hotkey.
+    /// Custom palettes cycle back to the first built-in one.
+    pub fn next(&self) -> Palette {
+        let index = Palette::NAMED
+            .iter()
+            .position(|(_, palette)| palette == self)
+            .map_or(0, |index| (index + 1) % Palette::NAMED.len());
+        Palette::NAMED[index].1
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::CLASSIC
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Parse a palette name, or comma-separated hex colors.
+    /// Two colors set the background and lit pixels; the XO-CHIP plane colors
+    /// default to the lit color unless all four are given.
+    fn from_str(s: &str) -> Result<Palette, String> {
+        if let Some((_, palette)) = Palette::NAMED
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+        {
+            return Ok(*palette);
+        }
+
+        let colors = s
+            .split(',')
+            .map(|color| color.trim().parse())
+            .collect::<Result<Vec<Rgb>, _>>()
+            .map_err(|err| {
+                let names: Vec<&str> = Palette::NAMED.iter().map(|(name, _)| *name).collect();
+                format!("{}; palettes are {}, or hex colors", err, names.join(", "))
+            })?;
+        match colors[..] {
+            [off, on] => Ok(Palette {
+                colors: [off, on, on, on],
+            }),
+            [off, on, plane_2, both] => Ok(Palette {
+                colors: [off, on, plane_2, both],
+            }),
+            _ => Err(format!(
+                "a custom palette needs 2 or 4 colors, not {}",
+                colors.len()
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Palette, Rgb};
+
+    #[test]
+    fn parse_palettes() {
+        assert_eq!("amber".parse(), Ok(Palette::AMBER));
+        assert_eq!("Octo".parse(), Ok(Palette::OCTO));
+
+        let custom: Palette = "#102030, ffcc00".parse().unwrap();
+        assert_eq!(custom.off(), Rgb::new(0x10, 0x20, 0x30));
+        assert_eq!(custom.on(), Rgb::new(0xFF, 0xCC, 0x00));
+        assert_eq!(custom.colors[3], Rgb::new(0xFF, 0xCC, 0x00));
+
+        assert!("mauve".parse::<Palette>().is_err());
+        assert!("#000000".parse::<Palette>().is_err());
+        assert!("#000000,#FFFFFF,#123456".parse::<Palette>().is_err());
+        assert!("#00000,#FFFFFF".parse::<Palette>().is_err());
+    }
+
+    #[test]
+    fn cycle_palettes() {
+        assert_eq!(Palette::CLASSIC.next(), Palette::AMBER);
+        assert_eq!(Palette::OCTO.next(), Palette::CLASSIC);
+        let custom: Palette = "#102030,#ffcc00".parse().unwrap();
+        assert_eq!(custom.next(), Palette::CLASSIC);
+    }
+}
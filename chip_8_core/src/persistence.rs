@@ -0,0 +1,160 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use core::{fmt, str::FromStr};
+
+use crate::chip_8;
+
+/// Brightness of a fully lit pixel
+pub const FULL: u8 = 255;
+
+/// How long lit pixels linger after they're erased.
+/// Games erase and redraw sprites with XOR, so without persistence moving
+/// sprites flicker, like they did on phosphor screens with a short afterglow.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub enum Persistence {
+    /// Show the display as is
+    #[default]
+    Off,
+    /// Show pixels lit in this frame or the last one
+    Blend,
+    /// Fade erased pixels out over this many frames
+    Decay(u8),
+}
+
+impl fmt::Display for Persistence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Persistence::Off => write!(f, "off"),
+            Persistence::Blend => write!(f, "blend"),
+            Persistence::Decay(frames) => write!(f, "{}", frames),
+        }
+    }
+}
+
+impl FromStr for Persistence {
+    type Err = String;
+
+    /// Parse `off`, `blend`, or a number of frames to decay over
+    fn from_str(s: &str) -> Result<Persistence, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" | "0" => Ok(Persistence::Off),
+            "blend" => Ok(Persistence::Blend),
+            frames => frames.parse().map(Persistence::Decay).map_err(|_| {
+                format!(
+                    "`{}` isn't a persistence; use off, blend, or a number of frames",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+/// Per-pixel brightness of a display, with persistence applied.
+/// Update it once per frame, then draw pixels by their brightness.
+pub struct PersistenceFilter {
+    persistence: Persistence,
+    width: i32,
+    height: i32,
+    // Row-major brightness, 0 to FULL
+    brightness: Vec<u8>,
+    // Row-major pixels lit in the last frame, for Blend
+    lit: Vec<bool>,
+}
+
+impl PersistenceFilter {
+    pub fn new(persistence: Persistence, width: i32, height: i32) -> Self {
+        let size = (width * height) as usize;
+        PersistenceFilter {
+            persistence,
+            width,
+            height,
+            brightness: vec![0; size],
+            lit: vec![false; size],
+        }
+    }
+
+    pub fn persistence(&self) -> Persistence {
+        self.persistence
+    }
+
+    /// Apply the next frame of the display
+    pub fn update(&mut self, display: &chip_8::Display) {
+        let fade = match self.persistence {
+            Persistence::Decay(frames) if frames > 1 => FULL.div_ceil(frames),
+            _ => FULL,
+        };
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let index = (y * self.width + x) as usize;
+                let lit = display.contains(&(x, y));
+                let brightness = &mut self.brightness[index];
+                *brightness = match self.persistence {
+                    _ if lit => FULL,
+                    Persistence::Off => 0,
+                    Persistence::Blend if self.lit[index] => FULL,
+                    Persistence::Blend => 0,
+                    Persistence::Decay(_) => brightness.saturating_sub(fade),
+                };
+                self.lit[index] = lit;
+            }
+        }
+    }
+
+    /// Brightness of a pixel, 0 to FULL. Pixels off the display are dark.
+    pub fn brightness(&self, x: i32, y: i32) -> u8 {
+        if (0..self.width).contains(&x) && (0..self.height).contains(&y) {
+            self.brightness[(y * self.width + x) as usize]
+        } else {
+            0
+        }
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::collections::BTreeSet;
+
+    use super::{Persistence, PersistenceFilter, FULL};
+
+    #[test]
+    fn parse_persistence() {
+        assert_eq!("off".parse(), Ok(Persistence::Off));
+        assert_eq!("Blend".parse(), Ok(Persistence::Blend));
+        assert_eq!("4".parse(), Ok(Persistence::Decay(4)));
+        assert!("sometimes".parse::<Persistence>().is_err());
+    }
+
+    #[test]
+    fn filters_erased_pixels() {
+        let lit = BTreeSet::from([(0, 0)]);
+        let erased = BTreeSet::new();
+
+        let mut off = PersistenceFilter::new(Persistence::Off, 2, 1);
+        off.update(&lit);
+        assert_eq!((off.brightness(0, 0), off.brightness(1, 0)), (FULL, 0));
+        off.update(&erased);
+        assert_eq!(off.brightness(0, 0), 0);
+
+        let mut blend = PersistenceFilter::new(Persistence::Blend, 2, 1);
+        blend.update(&lit);
+        blend.update(&erased);
+        assert_eq!(blend.brightness(0, 0), FULL);
+        blend.update(&erased);
+        assert_eq!(blend.brightness(0, 0), 0);
+
+        let mut decay = PersistenceFilter::new(Persistence::Decay(3), 2, 1);
+        decay.update(&lit);
+        let fading: [u8; 3] = core::array::from_fn(|_| {
+            decay.update(&erased);
+            decay.brightness(0, 0)
+        });
+        assert_eq!(fading, [170, 85, 0]);
+        // Redrawn pixels are fully lit again
+        decay.update(&lit);
+        assert_eq!(decay.brightness(0, 0), FULL);
+    }
+}
@@ -0,0 +1,378 @@
+use alloc::{collections::BTreeMap, format, string::String, vec, vec::Vec};
+use core::fmt::Write;
+
+use byteorder::{BigEndian, ByteOrder};
+
+use crate::{globals, Chip8, Instruction, Observer};
+
+// Hot addresses shown in the text report
+const TEXT_REPORT_ADDRESSES: usize = 20;
+// A DT poll that comes back around within this many instructions is a busy-wait loop
+const MAX_BUSY_WAIT_LOOP: u64 = 4;
+
+/// Profile report formats
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "std", derive(clap::ValueEnum))]
+pub enum ProfileFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Instruction counts for one subroutine
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct SubroutineStats {
+    pub calls: u64,
+    /// Instructions run in the subroutine and everything it called
+    pub inclusive: u64,
+    /// Instructions run in the subroutine itself
+    pub exclusive: u64,
+}
+
+// A subroutine call that hasn't returned yet
+struct Call {
+    address: u16,
+    entered_at: u64,
+    // Inclusive instructions of the subroutines it called
+    children: u64,
+}
+
+/// Counts what a ROM spends its instructions on, to tune `INSTRUCTIONS_PER_FRAME`
+/// and find hot spots
+pub struct Profiler {
+    instructions: u64,
+    // Executions per address
+    executions: Vec<u64>,
+    classes: BTreeMap<&'static str, u64>,
+    subroutines: BTreeMap<u16, SubroutineStats>,
+    calls: Vec<Call>,
+    // Address of the last DT read, and the instruction count then
+    dt_poll: Option<(u16, u64)>,
+    // Instructions spent polling DT, per address of the poll
+    busy_wait: BTreeMap<u16, u64>,
+
+    frames: u64,
+    // Draws and busy-wait instructions in the current frame
+    frame_draws: u64,
+    frame_busy_wait: u64,
+    // Histograms: how many frames had this many draws / this many useful instructions
+    draws_per_frame: BTreeMap<u64, u64>,
+    useful_per_frame: BTreeMap<u64, u64>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            instructions: 0,
+            executions: vec![0; 4096],
+            classes: BTreeMap::new(),
+            subroutines: BTreeMap::new(),
+            calls: Vec::new(),
+            dt_poll: None,
+            busy_wait: BTreeMap::new(),
+            frames: 0,
+            frame_draws: 0,
+            frame_busy_wait: 0,
+            draws_per_frame: BTreeMap::new(),
+            useful_per_frame: BTreeMap::new(),
+        }
+    }
+
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    pub fn executions(&self, address: u16) -> u64 {
+        self.executions.get(address as usize).copied().unwrap_or(0)
+    }
+
+    pub fn subroutines(&self) -> &BTreeMap<u16, SubroutineStats> {
+        &self.subroutines
+    }
+
+    pub fn busy_wait(&self) -> &BTreeMap<u16, u64> {
+        &self.busy_wait
+    }
+
+    /// Addresses by executions, most executed first
+    fn hot_addresses(&self) -> Vec<(u16, u64)> {
+        let mut addresses: Vec<(u16, u64)> = self
+            .executions
+            .iter()
+            .enumerate()
+            .filter(|&(_, &count)| count > 0)
+            .map(|(address, &count)| (address as u16, count))
+            .collect();
+        addresses.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        addresses
+    }
+
+    /// The largest and mean value of a per-frame histogram
+    fn summarize(histogram: &BTreeMap<u64, u64>) -> (u64, f64) {
+        let frames: u64 = histogram.values().sum();
+        let max = histogram.keys().next_back().copied().unwrap_or(0);
+        let total: u64 = histogram.iter().map(|(value, count)| value * count).sum();
+        let mean = if frames == 0 {
+            0.0
+        } else {
+            total as f64 / frames as f64
+        };
+        (max, mean)
+    }
+
+    /// A human-readable report
+    pub fn report_text(&self) -> String {
+        let mut report = String::new();
+        let (max_draws, mean_draws) = Profiler::summarize(&self.draws_per_frame);
+        let (max_useful, mean_useful) = Profiler::summarize(&self.useful_per_frame);
+
+        // Writing to a String can't fail
+        let _ = writeln!(
+            report,
+            "{} instructions over {} frames ({} per frame)",
+            self.instructions,
+            self.frames,
+            globals::INSTRUCTIONS_PER_FRAME
+        );
+        let _ = writeln!(
+            report,
+            "Useful instructions per frame: {:.1} mean, {} max (the rest poll DT)",
+            mean_useful, max_useful
+        );
+        let _ = writeln!(
+            report,
+            "Draws per frame: {:.1} mean, {} max",
+            mean_draws, max_draws
+        );
+
+        let _ = writeln!(report, "\nHot addresses:");
+        for (address, count) in self.hot_addresses().into_iter().take(TEXT_REPORT_ADDRESSES) {
+            let _ = writeln!(report, "  {:04X} {:>10}", address, count);
+        }
+
+        let _ = writeln!(report, "\nOpcode classes:");
+        let mut classes: Vec<_> = self.classes.iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(a.1));
+        for (class, count) in classes {
+            let _ = writeln!(report, "  {} {:>10}", class, count);
+        }
+
+        if !self.subroutines.is_empty() {
+            let _ = writeln!(report, "\nSubroutines:   calls  inclusive  exclusive");
+            for (address, stats) in &self.subroutines {
+                let _ = writeln!(
+                    report,
+                    "  {:04X} {:>10} {:>10} {:>10}",
+                    address, stats.calls, stats.inclusive, stats.exclusive
+                );
+            }
+        }
+
+        if !self.busy_wait.is_empty() {
+            let _ = writeln!(report, "\nBusy-wait loops on DT:");
+            for (address, count) in &self.busy_wait {
+                let _ = writeln!(report, "  {:04X} {:>10}", address, count);
+            }
+        }
+        report
+    }
+
+    /// The report as JSON
+    pub fn report_json(&self) -> String {
+        let object = |entries: Vec<String>| format!("{{{}}}", entries.join(","));
+        let (max_draws, mean_draws) = Profiler::summarize(&self.draws_per_frame);
+        let (max_useful, mean_useful) = Profiler::summarize(&self.useful_per_frame);
+        let histogram = |histogram: &BTreeMap<u64, u64>| {
+            object(
+                histogram
+                    .iter()
+                    .map(|(value, frames)| format!("\"{}\":{}", value, frames))
+                    .collect(),
+            )
+        };
+        let by_address = |counts: &mut dyn Iterator<Item = (u16, u64)>| {
+            object(
+                counts
+                    .map(|(address, count)| format!("\"0x{:03X}\":{}", address, count))
+                    .collect(),
+            )
+        };
+
+        let executions = by_address(&mut self.hot_addresses().into_iter());
+        let classes = object(
+            self.classes
+                .iter()
+                .map(|(class, count)| format!("\"{}\":{}", class, count))
+                .collect(),
+        );
+        let subroutines = object(
+            self.subroutines
+                .iter()
+                .map(|(address, stats)| {
+                    format!(
+                        "\"0x{:03X}\":{{\"calls\":{},\"inclusive\":{},\"exclusive\":{}}}",
+                        address, stats.calls, stats.inclusive, stats.exclusive
+                    )
+                })
+                .collect(),
+        );
+        let busy_wait = by_address(&mut self.busy_wait.iter().map(|(&a, &c)| (a, c)));
+
+        object(vec![
+            format!("\"instructions\":{}", self.instructions),
+            format!("\"frames\":{}", self.frames),
+            format!(
+                "\"instructions_per_frame\":{}",
+                globals::INSTRUCTIONS_PER_FRAME
+            ),
+            format!(
+                "\"useful_per_frame\":{{\"mean\":{:.3},\"max\":{},\"histogram\":{}}}",
+                mean_useful,
+                max_useful,
+                histogram(&self.useful_per_frame)
+            ),
+            format!(
+                "\"draws_per_frame\":{{\"mean\":{:.3},\"max\":{},\"histogram\":{}}}",
+                mean_draws,
+                max_draws,
+                histogram(&self.draws_per_frame)
+            ),
+            format!("\"executions\":{}", executions),
+            format!("\"classes\":{}", classes),
+            format!("\"subroutines\":{}", subroutines),
+            format!("\"busy_wait\":{}", busy_wait),
+        ])
+    }
+
+    pub fn report(&self, format: ProfileFormat) -> String {
+        match format {
+            ProfileFormat::Text => self.report_text(),
+            ProfileFormat::Json => self.report_json(),
+        }
+    }
+}
+
+impl Observer for Profiler {
+    fn frame_start(&mut self, _chip_8: &Chip8) {
+        self.frames += 1;
+        self.frame_draws = 0;
+        self.frame_busy_wait = 0;
+    }
+
+    fn frame_end(&mut self, _chip_8: &Chip8) {
+        let useful = (globals::INSTRUCTIONS_PER_FRAME as u64).saturating_sub(self.frame_busy_wait);
+        *self.draws_per_frame.entry(self.frame_draws).or_default() += 1;
+        *self.useful_per_frame.entry(useful).or_default() += 1;
+    }
+
+    fn before_cycle(&mut self, chip_8: &Chip8) {
+        let pc = chip_8.pc;
+        let Some(opcode) = chip_8.memory.get(pc as usize..pc as usize + 2) else {
+            return;
+        };
+        let instruction = Instruction::decode(BigEndian::read_u16(opcode));
+
+        self.instructions += 1;
+        self.executions[pc as usize] += 1;
+        *self.classes.entry(instruction.pattern()).or_default() += 1;
+
+        match instruction {
+            Instruction::Call { nnn } => {
+                self.subroutines.entry(nnn).or_default().calls += 1;
+                self.calls.push(Call {
+                    address: nnn,
+                    entered_at: self.instructions,
+                    children: 0,
+                });
+            }
+            Instruction::Ret => {
+                if let Some(call) = self.calls.pop() {
+                    let inclusive = self.instructions - call.entered_at;
+                    let stats = self.subroutines.entry(call.address).or_default();
+                    stats.inclusive += inclusive;
+                    stats.exclusive += inclusive - call.children;
+                    if let Some(caller) = self.calls.last_mut() {
+                        caller.children += inclusive;
+                    }
+                }
+            }
+            Instruction::Drw { .. } => self.frame_draws += 1,
+            Instruction::LdVxDt { .. } => {
+                if let Some((address, since)) = self.dt_poll {
+                    let loop_length = self.instructions - since;
+                    if address == pc && loop_length <= MAX_BUSY_WAIT_LOOP {
+                        *self.busy_wait.entry(pc).or_default() += loop_length;
+                        self.frame_busy_wait += loop_length;
+                    }
+                }
+                self.dt_poll = Some((pc, self.instructions));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::collections::BTreeSet;
+
+    use super::{Profiler, SubroutineStats};
+    use crate::Chip8;
+
+    #[test]
+    fn profiles_subroutines_and_busy_waits() {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0x22, 0x08, // 200: CALL 0x208
+            0xF0, 0x07, // 202: LD V0, DT
+            0x30, 0x00, // 204: SE V0, 0
+            0x12, 0x02, // 206: JP 0x202
+            0x22, 0x0C, // 208: CALL 0x20C
+            0x00, 0xEE, // 20A: RET
+            0x60, 0x05, // 20C: LD V0, 5
+            0xF0, 0x15, // 20E: LD DT, V0
+            0x00, 0xEE, // 210: RET
+        ]).unwrap();
+
+        let mut profiler = Profiler::new();
+        for _ in 0..3 {
+            chip_8.run_frame_observed(&BTreeSet::new(), &mut profiler);
+        }
+
+        assert_eq!(profiler.instructions(), 48);
+        assert_eq!(profiler.executions(0x200), 1);
+        assert_eq!(
+            profiler.subroutines()[&0x208],
+            SubroutineStats {
+                calls: 1,
+                inclusive: 5,
+                exclusive: 2,
+            }
+        );
+        assert_eq!(
+            profiler.subroutines()[&0x20C],
+            SubroutineStats {
+                calls: 1,
+                inclusive: 3,
+                exclusive: 3,
+            }
+        );
+        // The first poll isn't a loop yet; every poll after it is 3 instructions around
+        let polls = profiler.executions(0x202);
+        assert_eq!(profiler.busy_wait()[&0x202], (polls - 1) * 3);
+
+        let json = profiler.report_json();
+        assert!(json.starts_with("{\"instructions\":48,\"frames\":3,"));
+        assert!(json.contains("\"0x208\":{\"calls\":1,\"inclusive\":5,\"exclusive\":2}"));
+        assert!(profiler
+            .report_text()
+            .contains("Busy-wait loops on DT:\n  0202"));
+    }
+}
@@ -0,0 +1,94 @@
+use alloc::{format, string::String};
+use core::{fmt, str::FromStr};
+
+/// Behaviors that differ between chip8 interpreters, which ROMs written for one
+/// may depend on
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift vY into vX, rather than shifting vX in place
+    pub shift_uses_vy: bool,
+    /// FX55 and FX65 leave I pointing past the last register stored or loaded
+    pub load_store_increments_i: bool,
+    /// BNNN jumps to NNN + vX, where X is the high nibble of NNN, rather than NNN + v0
+    pub jump_uses_vx: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset vF to 0, as the VIP's arithmetic routines left it
+    pub logic_resets_vf: bool,
+}
+
+impl Quirks {
+    /// The original COSMAC VIP interpreter
+    pub const VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+    };
+
+    /// SUPER-CHIP 1.1 on the HP 48, which most 90s and 2000s ROMs target
+    pub const SCHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+    };
+
+    /// XO-CHIP, as run by Octo: the VIP's behaviors, but logic leaves vF alone
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+    };
+
+    pub const PRESETS: [(&'static str, Quirks); 3] = [
+        ("vip", Quirks::VIP),
+        ("schip", Quirks::SCHIP),
+        ("xochip", Quirks::XO_CHIP),
+    ];
+}
+
+/// XO-CHIP, which is how this emulator behaved before quirks could be chosen
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::XO_CHIP
+    }
+}
+
+/// The preset's name, or the quirks that are on
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((name, _)) = Quirks::PRESETS.iter().find(|(_, quirks)| quirks == self) {
+            return write!(f, "{}", name);
+        }
+        let flags = [
+            ("shift-vy", self.shift_uses_vy),
+            ("load-store-i", self.load_store_increments_i),
+            ("jump-vx", self.jump_uses_vx),
+            ("logic-vf", self.logic_resets_vf),
+        ];
+        let mut first = true;
+        for (name, on) in flags {
+            if on {
+                write!(f, "{}{}", if first { "" } else { "," }, name)?;
+                first = false;
+            }
+        }
+        if first {
+            write!(f, "none")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Quirks {
+    type Err = String;
+
+    /// Parse a preset name: vip, schip or xochip
+    fn from_str(s: &str) -> Result<Quirks, String> {
+        Quirks::PRESETS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s))
+            .map(|&(_, quirks)| quirks)
+            .ok_or_else(|| format!("`{}` isn't a quirks preset: vip, schip or xochip", s))
+    }
+}
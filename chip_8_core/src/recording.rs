@@ -0,0 +1,293 @@
+use crate::{chip_8, globals, globals::Err, Palette};
+use clap::ValueEnum;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+/// Video file formats for recordings
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, ValueEnum)]
+pub enum VideoFormat {
+    /// Animated GIF. Repeated frames are merged into one longer frame.
+    #[default]
+    Gif,
+    /// Uncompressed YUV4MPEG2 at 60 fps, for piping into ffmpeg
+    Y4m,
+}
+
+impl VideoFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoFormat::Gif => "gif",
+            VideoFormat::Y4m => "y4m",
+        }
+    }
+}
+
+/// Records every frame of a display to a video file.
+/// Call `finish` when done, or the end of the recording may be lost.
+pub struct Recorder<W: Write> {
+    width: i32,
+    height: i32,
+    scale: u32,
+    encoder: Encoder<W>,
+}
+
+enum Encoder<W: Write> {
+    Gif {
+        encoder: gif::Encoder<W>,
+        // The last frame's pixels, not written until we know how long it's shown
+        pending: Option<Vec<u8>>,
+        // Frames recorded so far, and hundredths of a second written as GIF frames
+        frames: u64,
+        written: u64,
+    },
+    Y4m {
+        writer: W,
+        palette: Palette,
+    },
+}
+
+impl Recorder<BufWriter<File>> {
+    /// Start recording to a file at `path`
+    pub fn create(
+        path: &Path,
+        format: VideoFormat,
+        width: i32,
+        height: i32,
+        palette: Palette,
+        scale: u32,
+    ) -> Result<Self, Err> {
+        let writer = BufWriter::new(File::create(path)?);
+        Recorder::new(writer, format, width, height, palette, scale)
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    pub fn new(
+        mut writer: W,
+        format: VideoFormat,
+        width: i32,
+        height: i32,
+        palette: Palette,
+        scale: u32,
+    ) -> Result<Self, Err> {
+        let (image_width, image_height) = (width as u32 * scale, height as u32 * scale);
+        let encoder = match format {
+            VideoFormat::Gif => {
+                let (off, on) = (palette.off(), palette.on());
+                let mut encoder = gif::Encoder::new(
+                    writer,
+                    image_width.try_into()?,
+                    image_height.try_into()?,
+                    &[off.r, off.g, off.b, on.r, on.g, on.b],
+                )?;
+                encoder.set_repeat(gif::Repeat::Infinite)?;
+                Encoder::Gif {
+                    encoder,
+                    pending: None,
+                    frames: 0,
+                    written: 0,
+                }
+            }
+            VideoFormat::Y4m => {
+                writeln!(
+                    writer,
+                    // Samples are full range, which readers assume isn't so unless told
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444 XCOLORRANGE=FULL",
+                    image_width,
+                    image_height,
+                    globals::FRAMES_PER_SECOND
+                )?;
+                Encoder::Y4m { writer, palette }
+            }
+        };
+        Ok(Recorder {
+            width,
+            height,
+            scale,
+            encoder,
+        })
+    }
+
+    /// Record one 60 Hz frame
+    pub fn record_frame(&mut self, display: &chip_8::Display) -> Result<(), Err> {
+        let lit = self.lit_pixels(display);
+        match &mut self.encoder {
+            Encoder::Gif {
+                encoder,
+                pending,
+                frames,
+                written,
+            } => {
+                if pending.as_ref() != Some(&lit) {
+                    let delay = centiseconds(*frames) - *written;
+                    // A frame shown too briefly for viewers to keep to is dropped,
+                    // and the next one is shown from when it started instead
+                    if let Some(pixels) = pending.take().filter(|_| delay >= MIN_GIF_DELAY) {
+                        write_gif_frame(encoder, self.width as u32 * self.scale, &pixels, delay)?;
+                        *written += delay;
+                    }
+                    *pending = Some(lit);
+                }
+                *frames += 1;
+            }
+            Encoder::Y4m { writer, palette } => {
+                writer.write_all(b"FRAME\n")?;
+                let (off, on) = (yuv(palette.off()), yuv(palette.on()));
+                for plane in 0..3 {
+                    let plane: Vec<u8> = lit
+                        .iter()
+                        .map(|&lit| if lit == 1 { on[plane] } else { off[plane] })
+                        .collect();
+                    writer.write_all(&plane)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Write out the rest of the recording
+    pub fn finish(self) -> Result<W, Err> {
+        let width = self.width as u32 * self.scale;
+        match self.encoder {
+            Encoder::Gif {
+                mut encoder,
+                pending,
+                frames,
+                written,
+            } => {
+                if let Some(pixels) = pending {
+                    let delay = (centiseconds(frames) - written).max(MIN_GIF_DELAY);
+                    write_gif_frame(&mut encoder, width, &pixels, delay)?;
+                }
+                Ok(encoder.into_inner()?)
+            }
+            Encoder::Y4m { mut writer, .. } => {
+                writer.flush()?;
+                Ok(writer)
+            }
+        }
+    }
+
+    /// The scaled display, row-major, 1 for lit pixels and 0 otherwise
+    fn lit_pixels(&self, display: &chip_8::Display) -> Vec<u8> {
+        let (width, height) = (
+            self.width as u32 * self.scale,
+            self.height as u32 * self.scale,
+        );
+        let mut pixels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = ((x / self.scale) as i32, (y / self.scale) as i32);
+                pixels.push(display.contains(&pixel) as u8);
+            }
+        }
+        pixels
+    }
+}
+
+/// The shortest GIF delay, in hundredths of a second, that browsers and most
+/// viewers play as written; they slow shorter ones to about a tenth of a second
+const MIN_GIF_DELAY: u64 = 2;
+
+/// Hundredths of a second from the start of the recording to a 60 Hz frame.
+/// Delays are rounded from the start so 60 Hz frames don't drift.
+fn centiseconds(frames: u64) -> u64 {
+    (frames * 100 + 30) / globals::FRAMES_PER_SECOND as u64
+}
+
+/// Write a GIF frame shown for `delay` hundredths of a second
+fn write_gif_frame<W: Write>(
+    encoder: &mut gif::Encoder<W>,
+    width: u32,
+    pixels: &[u8],
+    delay: u64,
+) -> Result<(), Err> {
+    let height = pixels.len() as u32 / width;
+    let mut frame =
+        gif::Frame::from_indexed_pixels(width.try_into()?, height.try_into()?, pixels, None);
+    frame.delay = delay.try_into()?;
+    encoder.write_frame(&frame)?;
+    Ok(())
+}
+
+/// Full range BT.601 Y, Cb and Cr for a color
+fn yuv(rgb: crate::palette::Rgb) -> [u8; 3] {
+    let (r, g, b) = (rgb.r as f32, rgb.g as f32, rgb.b as f32);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 + (b - y) * 0.564;
+    let cr = 128.0 + (r - y) * 0.713;
+    [y, cb, cr].map(|value| value.round().clamp(0.0, 255.0) as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::{Recorder, VideoFormat};
+    use crate::Palette;
+
+    #[test]
+    fn merges_repeated_gif_frames() {
+        let lit = BTreeSet::from([(0, 0)]);
+        let dark = BTreeSet::new();
+        let mut recorder =
+            Recorder::new(Vec::new(), VideoFormat::Gif, 2, 1, Palette::default(), 1).unwrap();
+        for display in [&lit, &lit, &lit, &dark, &lit] {
+            recorder.record_frame(display).unwrap();
+        }
+        let gif = recorder.finish().unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&gif[..]).unwrap();
+        let mut frames = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.buffer.to_vec(), frame.delay));
+        }
+        // 3 frames last 5/100 s; the 1/60 s frames round to 2, at least
+        assert_eq!(
+            frames,
+            vec![(vec![1, 0], 5), (vec![0, 0], 2), (vec![1, 0], 2)]
+        );
+    }
+
+    #[test]
+    fn keeps_gif_delays_playable() {
+        // A second of flicker every frame still lasts a second, without any
+        // frame shorter than viewers play at its real speed
+        let mut recorder =
+            Recorder::new(Vec::new(), VideoFormat::Gif, 1, 1, Palette::default(), 1).unwrap();
+        for frame in 0..60 {
+            let display = BTreeSet::from_iter((frame % 2 == 0).then_some((0, 0)));
+            recorder.record_frame(&display).unwrap();
+        }
+        let gif = recorder.finish().unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(&gif[..]).unwrap();
+        let mut delays = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+        }
+        assert!(delays.iter().all(|&delay| delay >= 2));
+        assert_eq!(delays.iter().sum::<u16>(), 100);
+    }
+
+    #[test]
+    fn writes_y4m_frames() {
+        let mut recorder =
+            Recorder::new(Vec::new(), VideoFormat::Y4m, 2, 1, Palette::default(), 2).unwrap();
+        recorder.record_frame(&BTreeSet::from([(1, 0)])).unwrap();
+        let video = recorder.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W4 H2 F60:1 Ip A1:1 C444 XCOLORRANGE=FULL\nFRAME\n";
+        assert_eq!(&video[..header.len()], header);
+        let planes = &video[header.len()..];
+        assert_eq!(planes.len(), 4 * 2 * 3);
+        // Luma, then flat chroma for black and white
+        assert_eq!(&planes[..8], &[0, 0, 255, 255, 0, 0, 255, 255]);
+        assert!(planes[8..].iter().all(|&chroma| chroma == 128));
+    }
+}
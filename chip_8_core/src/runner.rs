@@ -1,6 +1,6 @@
 use crate::globals::{self, Err, Keys};
 use crate::interface::Interface;
-use crate::Chip8;
+use crate::{Chip8, Observer};
 use clap::{Parser, ValueEnum};
 
 use std::thread;
@@ -23,7 +23,9 @@ struct Args {
     interface: InterfaceType,
 }
 
-pub fn run(interface: &mut Box<dyn Interface>) -> Result<(), Err> {
+/// Run the ROM the interface loads until it breaks out, telling `observer` about
+/// every instruction
+pub fn run(interface: &mut Box<dyn Interface>, observer: &mut dyn Observer) -> Result<(), Err> {
     let mut chip_8 = Chip8::new();
 
     interface.load_rom(&mut chip_8)?;
@@ -33,41 +35,28 @@ pub fn run(interface: &mut Box<dyn Interface>) -> Result<(), Err> {
 
     // Game loop
     let mut last_frame_end = Instant::now();
-    let mut last_frame_display = chip_8.display.clone();
     loop {
-        let frame_start = Instant::now();
-
         let held_keys = interface.read_keys()?;
 
         let Keys::Keys(held_keys) = held_keys else {
             break;
         };
 
-        chip_8.decrement_counters();
-
-        // Run n cycles
-        for _ in 0..globals::INSTRUCTIONS_PER_FRAME {
-            chip_8.run_cycle(&held_keys);
-        }
+        chip_8.run_frame_observed(&held_keys, observer);
 
         interface.draw(&mut chip_8)?;
 
+        if !interface.real_time() {
+            continue;
+        }
         let time_remaining =
             Duration::from_nanos(ns_per_frame).saturating_sub(last_frame_end.elapsed());
         // debug!("Time remaining: {} ms", time_remaining.as_millis());
 
         thread::sleep(time_remaining);
-        let frame_end = Instant::now();
-
-        // debug!(
-        //     "Frame duration: {} ms",
-        //     (frame_end - frame_start).as_millis()
-        // );
-
-        last_frame_display = chip_8.display.clone();
-        last_frame_end = frame_end;
+        last_frame_end = Instant::now();
     }
 
     interface.cleanup()?;
-    Ok(())
+    observer.finish()
 }
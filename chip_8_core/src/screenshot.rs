@@ -0,0 +1,156 @@
+use crate::{chip_8, globals::Err, Palette};
+use clap::ValueEnum;
+use std::{fs::File, io::Write, path::Path};
+
+/// Image file formats for screenshots
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, ValueEnum)]
+pub enum ImageFormat {
+    /// PNG in the palette's colors
+    #[default]
+    Png,
+    /// Plain (ASCII) PBM, black on white, for diffing in golden tests
+    Pbm,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Pbm => "pbm",
+        }
+    }
+}
+
+/// A display to write out, and how
+pub struct Screenshot<'a> {
+    pub display: &'a chip_8::Display,
+    pub width: i32,
+    pub height: i32,
+    pub palette: Palette,
+    /// Image pixels per chip8 pixel, in each direction
+    pub scale: u32,
+}
+
+impl Screenshot<'_> {
+    /// Encode the screenshot as an image file
+    pub fn encode(&self, format: ImageFormat) -> Result<Vec<u8>, Err> {
+        match format {
+            ImageFormat::Png => self.png(),
+            ImageFormat::Pbm => Ok(self.pbm()),
+        }
+    }
+
+    /// Write the screenshot to `path`
+    pub fn save(&self, path: &Path, format: ImageFormat) -> Result<(), Err> {
+        let image = self.encode(format)?;
+        File::create(path)?.write_all(&image)?;
+        Ok(())
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (
+            self.width as u32 * self.scale,
+            self.height as u32 * self.scale,
+        )
+    }
+
+    /// Whether the image pixel at x, y is lit
+    fn lit(&self, x: u32, y: u32) -> bool {
+        let (x, y) = ((x / self.scale) as i32, (y / self.scale) as i32);
+        self.display.contains(&(x, y))
+    }
+
+    fn png(&self) -> Result<Vec<u8>, Err> {
+        let (width, height) = self.size();
+        let (off, on) = (self.palette.off(), self.palette.on());
+
+        let mut pixels = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let color = if self.lit(x, y) { on } else { off };
+                pixels.extend([color.r, color.g, color.b]);
+            }
+        }
+
+        let mut image = Vec::new();
+        let mut encoder = png::Encoder::new(&mut image, width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&pixels)?;
+        writer.finish()?;
+        Ok(image)
+    }
+
+    fn pbm(&self) -> Vec<u8> {
+        // Plain PBM lines shouldn't be longer than 70 characters, and
+        // whitespace between pixels is optional, so rows are wrapped
+        const LINE_LENGTH: usize = 70;
+
+        let (width, height) = self.size();
+        let mut image = format!("P1\n{} {}\n", width, height);
+        for y in 0..height {
+            let row: Vec<char> = (0..width)
+                .map(|x| if self.lit(x, y) { '1' } else { '0' })
+                .collect();
+            for line in row.chunks(LINE_LENGTH) {
+                image.extend(line);
+                image.push('\n');
+            }
+        }
+        image.into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::{ImageFormat, Screenshot};
+    use crate::Palette;
+
+    #[test]
+    fn encodes_pbm() {
+        let display = BTreeSet::from([(1, 0)]);
+        let screenshot = Screenshot {
+            display: &display,
+            width: 2,
+            height: 1,
+            palette: Palette::default(),
+            scale: 2,
+        };
+
+        let image = screenshot.encode(ImageFormat::Pbm).unwrap();
+        assert_eq!(String::from_utf8(image).unwrap(), "P1\n4 2\n0011\n0011\n");
+
+        let wide = Screenshot {
+            width: 36,
+            ..screenshot
+        };
+        let image = String::from_utf8(wide.encode(ImageFormat::Pbm).unwrap()).unwrap();
+        assert!(image.lines().all(|line| line.len() <= 70));
+        assert_eq!(image.lines().count(), 2 + 2 * 2);
+    }
+
+    #[test]
+    fn encodes_png() {
+        let display = BTreeSet::from([(0, 0)]);
+        let screenshot = Screenshot {
+            display: &display,
+            width: 2,
+            height: 1,
+            palette: Palette::OCTO,
+            scale: 3,
+        };
+
+        let image = screenshot.encode(ImageFormat::Png).unwrap();
+        let decoder = png::Decoder::new(&image[..]);
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+
+        assert_eq!((info.width, info.height), (6, 3));
+        assert_eq!(&pixels[..3], &[0xFF, 0xCC, 0x00]);
+        assert_eq!(&pixels[pixels.len() - 3..], &[0x99, 0x66, 0x00]);
+    }
+}
@@ -0,0 +1,278 @@
+//! Sprite sheets: the sprites a ROM draws, laid out in a PNG for artists to edit
+//! and write back into the ROM.
+//!
+//! Each sprite gets a cell with its address above it in the chip8 font, and its
+//! rows in the palette's colors. Cells run left to right, 8 to a row, in address
+//! order, so reading an edited sheet back only needs the same ROM.
+
+use crate::{
+    analysis::Analysis,
+    chip_8::{FONT, PROGRAM_START_LOCATION},
+    globals::Err,
+    palette::Rgb,
+    Palette,
+};
+use std::{fs, path::Path};
+
+const COLUMNS: u32 = 8;
+// A margin, 4 digits 4 pixels wide with a pixel between them, and a margin
+const CELL_WIDTH: u32 = 1 + 4 * 5 - 1 + 1;
+// A margin, a digit 5 pixels high, a gap, the tallest sprite DXYN draws, and a margin
+const CELL_HEIGHT: u32 = 1 + 5 + 1 + 15 + 1;
+// Where a sprite starts in its cell
+const SPRITE_X: u32 = 1;
+const SPRITE_Y: u32 = 7;
+
+const BACKGROUND: Rgb = Rgb::new(0x40, 0x40, 0x40);
+const LABEL: Rgb = Rgb::new(0xA0, 0xA0, 0xA0);
+
+/// Bytes of sprite rows, where the ROM draws them from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Sprite {
+    pub address: u16,
+    pub rows: Vec<u8>,
+}
+
+/// The sprites in a ROM, and how to draw them
+pub struct SpriteSheet {
+    pub sprites: Vec<Sprite>,
+    pub palette: Palette,
+    /// Image pixels per sprite pixel, in each direction
+    pub scale: u32,
+}
+
+impl SpriteSheet {
+    /// The sprites analysis finds inside the ROM. Ones drawn from outside it, e.g.
+    /// built at run time, can't be written back, so they're left out.
+    pub fn find(rom: &[u8], palette: Palette, scale: u32) -> SpriteSheet {
+        let end = PROGRAM_START_LOCATION + rom.len();
+        let sprites = Analysis::analyze(rom)
+            .sprites
+            .into_iter()
+            .filter(|&(address, rows)| {
+                address as usize >= PROGRAM_START_LOCATION
+                    && address as usize + rows as usize <= end
+            })
+            .map(|(address, rows)| {
+                let start = address as usize - PROGRAM_START_LOCATION;
+                Sprite {
+                    address,
+                    rows: rom[start..start + rows as usize].to_vec(),
+                }
+            })
+            .collect();
+        SpriteSheet {
+            sprites,
+            palette,
+            scale,
+        }
+    }
+
+    /// Sheet size in sprite pixels
+    fn size(&self) -> (u32, u32) {
+        let rows = (self.sprites.len() as u32).div_ceil(COLUMNS).max(1);
+        (COLUMNS * CELL_WIDTH, rows * CELL_HEIGHT)
+    }
+
+    /// Where the cell for the nth sprite starts, in sprite pixels
+    fn cell(index: usize) -> (u32, u32) {
+        let index = index as u32;
+        (index % COLUMNS * CELL_WIDTH, index / COLUMNS * CELL_HEIGHT)
+    }
+
+    /// The sheet in sprite pixels, a row at a time
+    fn pixels(&self) -> Vec<Rgb> {
+        let (width, height) = self.size();
+        let mut pixels = vec![BACKGROUND; (width * height) as usize];
+        let mut set = |x: u32, y: u32, color: Rgb| pixels[(y * width + x) as usize] = color;
+        for (index, sprite) in self.sprites.iter().enumerate() {
+            let (cell_x, cell_y) = SpriteSheet::cell(index);
+            let digits = format!("{:04X}", sprite.address);
+            for (position, digit) in digits.chars().enumerate() {
+                let glyph = digit.to_digit(16).unwrap() as usize * 5;
+                for (y, row) in FONT[glyph..glyph + 5].iter().enumerate() {
+                    for x in 0..4 {
+                        if row >> (7 - x) & 1 == 1 {
+                            set(
+                                cell_x + 1 + position as u32 * 5 + x,
+                                cell_y + 1 + y as u32,
+                                LABEL,
+                            );
+                        }
+                    }
+                }
+            }
+            for (y, row) in sprite.rows.iter().enumerate() {
+                for x in 0..8 {
+                    let lit = row >> (7 - x) & 1 == 1;
+                    let color = if lit {
+                        self.palette.on()
+                    } else {
+                        self.palette.off()
+                    };
+                    set(cell_x + SPRITE_X + x, cell_y + SPRITE_Y + y as u32, color);
+                }
+            }
+        }
+        pixels
+    }
+
+    pub fn png(&self) -> Result<Vec<u8>, Err> {
+        let (width, height) = self.size();
+        let pixels = self.pixels();
+        let mut data = Vec::with_capacity(pixels.len() * (self.scale * self.scale) as usize * 3);
+        for row in pixels.chunks(width as usize) {
+            for _ in 0..self.scale {
+                for color in row {
+                    for _ in 0..self.scale {
+                        data.extend([color.r, color.g, color.b]);
+                    }
+                }
+            }
+        }
+
+        let mut image = Vec::new();
+        let mut encoder = png::Encoder::new(&mut image, width * self.scale, height * self.scale);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        writer.finish()?;
+        Ok(image)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Err> {
+        fs::write(path, self.png()?)?;
+        Ok(())
+    }
+
+    /// Read the sprites back from an edited copy of this sheet, at any scale. Each
+    /// pixel is lit if it's closer to the palette's lit color than its background.
+    pub fn read_png(&mut self, image: &[u8]) -> Result<(), Err> {
+        let mut decoder = png::Decoder::new(image);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+        let channels = info.color_type.samples();
+
+        let (width, height) = self.size();
+        let scale = info.width / width;
+        if scale == 0 || (info.width, info.height) != (width * scale, height * scale) {
+            return Err(format!(
+                "the sprite sheet is {}x{}, but this ROM's is {}x{} at some scale",
+                info.width, info.height, width, height
+            )
+            .into());
+        }
+        let (off, on) = (self.palette.off(), self.palette.on());
+        let lit = |x: u32, y: u32| {
+            // Sample the middle of the scaled pixel
+            let (x, y) = (x * scale + scale / 2, y * scale + scale / 2);
+            let offset = (y * info.width + x) as usize * channels;
+            let color = match &data[offset..offset + channels] {
+                [grey] | [grey, _] => Rgb::new(*grey, *grey, *grey),
+                [r, g, b, ..] => Rgb::new(*r, *g, *b),
+                _ => unreachable!(),
+            };
+            distance(color, on) < distance(color, off)
+        };
+        for (index, sprite) in self.sprites.iter_mut().enumerate() {
+            let (cell_x, cell_y) = SpriteSheet::cell(index);
+            for (y, row) in sprite.rows.iter_mut().enumerate() {
+                *row = (0..8).fold(0, |row, x| {
+                    let pixel = lit(cell_x + SPRITE_X + x, cell_y + SPRITE_Y + y as u32);
+                    row << 1 | pixel as u8
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the sprites into the ROM they came from. Sprites can share bytes, so
+    /// only the bytes a sprite changes are written, and it's an error for two
+    /// sprites to change a shared byte differently.
+    pub fn write_to_rom(&self, rom: &mut [u8]) -> Result<(), Err> {
+        let mut edited = rom.to_vec();
+        for sprite in &self.sprites {
+            let start = sprite.address as usize - PROGRAM_START_LOCATION;
+            for (offset, &row) in sprite.rows.iter().enumerate() {
+                let index = start + offset;
+                if row == rom[index] {
+                    continue;
+                }
+                if edited[index] != rom[index] && edited[index] != row {
+                    return Err(format!(
+                        "sprites sharing the byte at {:04X} were edited differently",
+                        PROGRAM_START_LOCATION + index
+                    )
+                    .into());
+                }
+                edited[index] = row;
+            }
+        }
+        rom.copy_from_slice(&edited);
+        Ok(())
+    }
+}
+
+fn distance(a: Rgb, b: Rgb) -> i32 {
+    let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+    channel(a.r, b.r) + channel(a.g, b.g) + channel(a.b, b.b)
+}
+
+#[cfg(test)]
+mod test {
+    use super::SpriteSheet;
+    use crate::Palette;
+
+    #[test]
+    fn round_trips_sprites() {
+        #[rustfmt::skip]
+        let rom = [
+            0xA2, 0x06, // 200: LD I, 0x206
+            0xD0, 0x13, // 202: DRW V0, V1, 3
+            0x12, 0x00, // 204: JP 0x200
+            0x18, 0x3C, 0x7E, // 206: sprite
+        ];
+        let sheet = SpriteSheet::find(&rom, Palette::AMBER, 3);
+        assert_eq!(sheet.sprites.len(), 1);
+        assert_eq!(sheet.sprites[0].rows, [0x18, 0x3C, 0x7E]);
+        let image = sheet.png().unwrap();
+
+        let mut edited = rom;
+        edited[6..9].copy_from_slice(&[0, 0, 0]);
+        let mut sheet = SpriteSheet::find(&edited, Palette::AMBER, 1);
+        sheet.read_png(&image).unwrap();
+        sheet.write_to_rom(&mut edited).unwrap();
+        assert_eq!(edited, rom);
+
+        assert!(sheet.read_png(&image[..image.len() / 2]).is_err());
+    }
+
+    #[test]
+    fn writes_overlapping_sprites() {
+        #[rustfmt::skip]
+        let rom = [
+            0xA2, 0x0A, // 200: LD I, 0x20A
+            0xD0, 0x15, // 202: DRW V0, V1, 5
+            0xA2, 0x0C, // 204: LD I, 0x20C
+            0xD0, 0x13, // 206: DRW V0, V1, 3
+            0x12, 0x00, // 208: JP 0x200
+            0x01, 0x02, 0x03, 0x04, 0x05, 0x06, // 20A: sprites sharing 20C-20E
+        ];
+        let mut sheet = SpriteSheet::find(&rom, Palette::AMBER, 1);
+        assert_eq!(sheet.sprites.len(), 2);
+
+        // An edit to the first sprite's shared rows isn't undone by the second's
+        sheet.sprites[0].rows[2] = 0xFF;
+        let mut edited = rom;
+        sheet.write_to_rom(&mut edited).unwrap();
+        assert_eq!(edited[10..16], [0x01, 0x02, 0xFF, 0x04, 0x05, 0x06]);
+
+        sheet.sprites[1].rows[0] = 0xF0;
+        let mut conflicted = rom;
+        assert!(sheet.write_to_rom(&mut conflicted).is_err());
+        assert_eq!(conflicted, rom);
+    }
+}
@@ -0,0 +1,247 @@
+use alloc::{format, vec::Vec};
+
+use crate::{
+    chip_8::Chip8,
+    globals::{self, Err},
+    Quirks,
+};
+
+// Save states start with a magic number and a format version,
+// so stale or foreign files are rejected instead of misread
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 3;
+
+// Bytes needed for the display, one bit per pixel
+const DISPLAY_BYTES: usize = (globals::DISPLAY_WIDTH * globals::DISPLAY_HEIGHT / 8) as usize;
+
+// The deepest stack a state can hold, as on SUPER-CHIP
+const MAX_STACK: usize = 16;
+
+// Bits of the quirks byte
+const SHIFT_USES_VY: u8 = 1 << 0;
+const LOAD_STORE_INCREMENTS_I: u8 = 1 << 1;
+const JUMP_USES_VX: u8 = 1 << 2;
+const LOGIC_RESETS_VF: u8 = 1 << 3;
+
+impl Chip8 {
+    /// Serialize the full machine state to bytes
+    ///
+    /// Layout (big-endian): magic, version, memory, v0-vF, pc, I, dt, st,
+    /// stack length + stack, the display as a 1 bit per pixel bitmap, the quirks
+    /// as a byte of flags, then the random number generator's state.
+    pub fn save_state(&self) -> Result<Vec<u8>, Err> {
+        if self.stack.len() > MAX_STACK {
+            return Err(format!(
+                "the stack is {} deep, more than a save state holds ({})",
+                self.stack.len(),
+                MAX_STACK
+            )
+            .into());
+        }
+        let mut state = Vec::new();
+        state.extend_from_slice(MAGIC);
+        state.push(VERSION);
+        state.extend_from_slice(&self.memory);
+        state.extend_from_slice(&self.v);
+        state.extend_from_slice(&self.pc.to_be_bytes());
+        state.extend_from_slice(&self.i.to_be_bytes());
+        state.push(self.dt);
+        state.push(self.st);
+        state.push(self.stack.len() as u8);
+        for address in &self.stack {
+            state.extend_from_slice(&address.to_be_bytes());
+        }
+
+        let mut display = [0u8; DISPLAY_BYTES];
+        for &(x, y) in self.display.iter() {
+            if (0..globals::DISPLAY_WIDTH).contains(&x) && (0..globals::DISPLAY_HEIGHT).contains(&y)
+            {
+                let bit = (y * globals::DISPLAY_WIDTH + x) as usize;
+                display[bit / 8] |= 0x80 >> (bit % 8);
+            }
+        }
+        state.extend_from_slice(&display);
+
+        let quirks = [
+            (SHIFT_USES_VY, self.quirks.shift_uses_vy),
+            (LOAD_STORE_INCREMENTS_I, self.quirks.load_store_increments_i),
+            (JUMP_USES_VX, self.quirks.jump_uses_vx),
+            (LOGIC_RESETS_VF, self.quirks.logic_resets_vf),
+        ];
+        state.push(
+            quirks
+                .iter()
+                .filter(|&&(_, on)| on)
+                .fold(0, |flags, &(bit, _)| flags | bit),
+        );
+        state.extend_from_slice(&self.rng.state.to_be_bytes());
+
+        Ok(state)
+    }
+
+    /// Restore a machine from bytes produced by `save_state`
+    pub fn load_state(state: &[u8]) -> Result<Chip8, Err> {
+        let mut reader = Reader { state, position: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err("not a chip8 save state".into());
+        }
+        let version = reader.byte()?;
+        if version != VERSION {
+            return Err(format!("unsupported save state version {}", version).into());
+        }
+
+        let mut chip_8 = Chip8::new();
+        let memory_len = chip_8.memory.len();
+        chip_8.memory.copy_from_slice(reader.take(memory_len)?);
+        chip_8.v.copy_from_slice(reader.take(16)?);
+        chip_8.pc = reader.u16()?;
+        chip_8.i = reader.u16()?;
+        chip_8.dt = reader.byte()?;
+        chip_8.st = reader.byte()?;
+        let stack_len = reader.byte()?;
+        if stack_len as usize > MAX_STACK {
+            return Err(
+                format!("save state stack is {} deep, past {}", stack_len, MAX_STACK).into(),
+            );
+        }
+        for _ in 0..stack_len {
+            chip_8.stack.push(reader.u16()?);
+        }
+
+        let display = reader.take(DISPLAY_BYTES)?;
+        for y in 0..globals::DISPLAY_HEIGHT {
+            for x in 0..globals::DISPLAY_WIDTH {
+                let bit = (y * globals::DISPLAY_WIDTH + x) as usize;
+                if display[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    chip_8.display.insert((x, y));
+                }
+            }
+        }
+
+        let quirks = reader.byte()?;
+        chip_8.quirks = Quirks {
+            shift_uses_vy: quirks & SHIFT_USES_VY != 0,
+            load_store_increments_i: quirks & LOAD_STORE_INCREMENTS_I != 0,
+            jump_uses_vx: quirks & JUMP_USES_VX != 0,
+            logic_resets_vf: quirks & LOGIC_RESETS_VF != 0,
+        };
+        chip_8.rng.state = reader.u64()?;
+
+        if reader.position != state.len() {
+            return Err("trailing data after save state".into());
+        }
+        Ok(chip_8)
+    }
+}
+
+struct Reader<'a> {
+    state: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Err> {
+        let bytes = self
+            .state
+            .get(self.position..self.position + len)
+            .ok_or("save state is truncated")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Err> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Err> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, Err> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{collections::BTreeSet, vec, vec::Vec};
+
+    use super::Chip8;
+    use crate::Quirks;
+
+    #[test]
+    fn round_trip() {
+        let mut chip_8 = Chip8::new();
+        chip_8.load_rom(&[0x12, 0x34, 0x56]).unwrap();
+        chip_8.v[3] = 0x42;
+        chip_8.pc = 0x2AB;
+        chip_8.i = 0x123;
+        chip_8.dt = 7;
+        chip_8.st = 9;
+        chip_8.stack = vec![0x204, 0x310];
+        chip_8.display.insert((0, 0));
+        chip_8.display.insert((63, 31));
+        chip_8.display.insert((10, 20));
+
+        chip_8.quirks = Quirks::SCHIP;
+
+        let restored = Chip8::load_state(&chip_8.save_state().unwrap()).unwrap();
+
+        assert_eq!(restored.memory, chip_8.memory);
+        assert_eq!(restored.v, chip_8.v);
+        assert_eq!(restored.pc, chip_8.pc);
+        assert_eq!(restored.i, chip_8.i);
+        assert_eq!(restored.dt, chip_8.dt);
+        assert_eq!(restored.st, chip_8.st);
+        assert_eq!(restored.stack, chip_8.stack);
+        assert_eq!(restored.display, chip_8.display);
+        assert_eq!(restored.quirks, chip_8.quirks);
+    }
+
+    #[test]
+    fn keeps_random_numbers() {
+        // After a save and load, RND carries on with the same numbers
+        let mut chip_8 = Chip8::with_seed(3);
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0xC0, 0xFF, // 200: RND V0, 0xFF
+            0x12, 0x00, // 202: JP 0x200
+        ]).unwrap();
+        let numbers = |chip_8: &mut Chip8| {
+            (0..8)
+                .map(|_| {
+                    chip_8.run_cycle(&BTreeSet::new());
+                    chip_8.run_cycle(&BTreeSet::new());
+                    chip_8.v[0]
+                })
+                .collect::<Vec<_>>()
+        };
+        numbers(&mut chip_8);
+
+        let mut restored = Chip8::load_state(&chip_8.save_state().unwrap()).unwrap();
+        assert_eq!(numbers(&mut restored), numbers(&mut chip_8));
+    }
+
+    #[test]
+    fn rejects_bad_states() {
+        let state = Chip8::new().save_state().unwrap();
+
+        assert!(Chip8::load_state(&state[..state.len() - 1]).is_err());
+        assert!(Chip8::load_state(b"not a save state").is_err());
+
+        let mut trailing = state.clone();
+        trailing.push(0);
+        assert!(Chip8::load_state(&trailing).is_err());
+
+        let mut deep = Chip8::new();
+        deep.stack = vec![0x200; 17];
+        assert!(deep.save_state().is_err());
+        let mut deep_state = state.clone();
+        deep_state[4 + 1 + 4096 + 16 + 2 + 2 + 1 + 1] = 17;
+        assert!(Chip8::load_state(&deep_state).is_err());
+    }
+}
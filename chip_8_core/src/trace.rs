@@ -0,0 +1,356 @@
+//! Execution traces: one record per instruction run, with what it changed.
+//!
+//! Text traces have a line per instruction:
+//! `frame pc opcode disassembly changes`, e.g.
+//! `     3 0208 F033 LD B, V0           [0300]=01 [0301]=02 [0302]=03`
+//!
+//! Binary traces start with the magic `C8TR` and a version byte (1), then one
+//! record per instruction, big-endian: frame (u32), pc (u16), opcode (u16),
+//! and a count (u8) of changes, each a kind (u8), index (u16) and value (u16).
+//! Kinds are 0 for a V register (indexed by number), 1 for I, 2 for a memory
+//! byte (indexed by address), 3 for DT and 4 for ST.
+
+use crate::{globals::Err, Chip8, Instruction, Observer};
+use byteorder::{BigEndian, ByteOrder};
+use clap::ValueEnum;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    ops::RangeInclusive,
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"C8TR";
+const VERSION: u8 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default, ValueEnum)]
+pub enum TraceFormat {
+    /// A line of text per instruction
+    #[default]
+    Text,
+    /// Compact binary records: a fixed header per instruction, then its changes
+    Binary,
+}
+
+/// Which instructions to trace. Empty filters match everything.
+#[derive(Clone, Debug, Default)]
+pub struct TraceFilter {
+    /// Addresses to trace instructions at
+    pub pcs: Vec<RangeInclusive<u16>>,
+    /// Opcode patterns like `DXYN`, or their first digit like `8`
+    pub classes: Vec<String>,
+    /// Frames to trace, counting from 1
+    pub frames: Option<RangeInclusive<u64>>,
+}
+
+impl TraceFilter {
+    pub fn matches(&self, frame: u64, pc: u16, instruction: &Instruction) -> bool {
+        let pattern = instruction.pattern();
+        self.frames
+            .as_ref()
+            .is_none_or(|frames| frames.contains(&frame))
+            && (self.pcs.is_empty() || self.pcs.iter().any(|pcs| pcs.contains(&pc)))
+            && (self.classes.is_empty()
+                || self.classes.iter().any(|class| {
+                    pattern.eq_ignore_ascii_case(class)
+                        || (class.len() == 1 && pattern[..1].eq_ignore_ascii_case(class))
+                }))
+    }
+}
+
+/// Parse an address range like `0x200-0x2FF`, or a single address
+pub fn parse_pc_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let range = parse_range(s)?;
+    let to_address = |value: u64| {
+        u16::try_from(value).map_err(|_| format!("{:#X} is out of the address space", value))
+    };
+    Ok(to_address(*range.start())?..=to_address(*range.end())?)
+}
+
+/// Parse a frame range like `100-200`, or a single frame
+pub fn parse_frame_range(s: &str) -> Result<RangeInclusive<u64>, String> {
+    parse_range(s)
+}
+
+fn parse_range(s: &str) -> Result<RangeInclusive<u64>, String> {
+    let parse = |value: &str| {
+        let value = value.trim();
+        match value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix("0X"))
+        {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => value.parse(),
+        }
+        .map_err(|_| format!("`{}` isn't a number", value))
+    };
+    let (start, end) = match s.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(s)?, parse(s)?),
+    };
+    if start > end {
+        return Err(format!("`{}` is an empty range", s));
+    }
+    Ok(start..=end)
+}
+
+/// State from before a traced instruction ran, to find what it changed
+struct Before {
+    pc: u16,
+    opcode: u16,
+    instruction: Instruction,
+    v: [u8; 16],
+    i: u16,
+    dt: u8,
+    st: u8,
+    memory: Box<[u8; 4096]>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Change {
+    V(u8, u8),
+    I(u16),
+    Memory(u16, u8),
+    Dt(u8),
+    St(u8),
+}
+
+/// Writes a trace of the instructions that pass its filter
+pub struct Tracer<W: Write> {
+    writer: W,
+    format: TraceFormat,
+    filter: TraceFilter,
+    frame: u64,
+    before: Option<Before>,
+    // Observers can't fail, so the first write error is kept for `finish`
+    error: Option<Err>,
+}
+
+impl Tracer<BufWriter<File>> {
+    /// Trace to a file at `path`
+    pub fn create(path: &Path, format: TraceFormat, filter: TraceFilter) -> Result<Self, Err> {
+        let writer = BufWriter::new(File::create(path)?);
+        Tracer::new(writer, format, filter)
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(mut writer: W, format: TraceFormat, filter: TraceFilter) -> Result<Self, Err> {
+        if format == TraceFormat::Binary {
+            writer.write_all(MAGIC)?;
+            writer.write_all(&[VERSION])?;
+        }
+        Ok(Tracer {
+            writer,
+            format,
+            filter,
+            frame: 0,
+            before: None,
+            error: None,
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    fn write_record(&mut self, before: &Before, changes: &[Change]) -> Result<(), Err> {
+        match self.format {
+            TraceFormat::Text => {
+                let disassembly = before.instruction.to_string();
+                write!(
+                    self.writer,
+                    "{:>6} {:04X} {:04X} ",
+                    self.frame, before.pc, before.opcode
+                )?;
+                // Line the changes up in a column
+                if changes.is_empty() {
+                    write!(self.writer, "{}", disassembly)?;
+                } else {
+                    write!(self.writer, "{:<18}", disassembly)?;
+                }
+                for change in changes {
+                    match change {
+                        Change::V(x, value) => write!(self.writer, " V{:X}={:02X}", x, value)?,
+                        Change::I(value) => write!(self.writer, " I={:04X}", value)?,
+                        Change::Memory(address, value) => {
+                            write!(self.writer, " [{:04X}]={:02X}", address, value)?
+                        }
+                        Change::Dt(value) => write!(self.writer, " DT={:02X}", value)?,
+                        Change::St(value) => write!(self.writer, " ST={:02X}", value)?,
+                    }
+                }
+                writeln!(self.writer)?;
+            }
+            TraceFormat::Binary => {
+                let mut record = [0; 9];
+                BigEndian::write_u32(&mut record[0..4], self.frame as u32);
+                BigEndian::write_u16(&mut record[4..6], before.pc);
+                BigEndian::write_u16(&mut record[6..8], before.opcode);
+                record[8] = changes.len() as u8;
+                self.writer.write_all(&record)?;
+                for &change in changes {
+                    let (kind, index, value) = match change {
+                        Change::V(x, value) => (0, x as u16, value as u16),
+                        Change::I(value) => (1, 0, value),
+                        Change::Memory(address, value) => (2, address, value as u16),
+                        Change::Dt(value) => (3, 0, value as u16),
+                        Change::St(value) => (4, 0, value as u16),
+                    };
+                    let mut change = [kind, 0, 0, 0, 0];
+                    BigEndian::write_u16(&mut change[1..3], index);
+                    BigEndian::write_u16(&mut change[3..5], value);
+                    self.writer.write_all(&change)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Observer for Tracer<W> {
+    fn frame_start(&mut self, _chip_8: &Chip8) {
+        self.frame += 1;
+    }
+
+    fn before_cycle(&mut self, chip_8: &Chip8) {
+        let pc = chip_8.pc;
+        let Some(opcode) = chip_8.memory.get(pc as usize..pc as usize + 2) else {
+            return;
+        };
+        let opcode = BigEndian::read_u16(opcode);
+        let instruction = Instruction::decode(opcode);
+        if !self.filter.matches(self.frame, pc, &instruction) {
+            return;
+        }
+        self.before = Some(Before {
+            pc,
+            opcode,
+            instruction,
+            v: chip_8.v,
+            i: chip_8.i,
+            dt: chip_8.dt,
+            st: chip_8.st,
+            memory: Box::new(chip_8.memory),
+        });
+    }
+
+    fn after_cycle(&mut self, chip_8: &Chip8) {
+        let Some(before) = self.before.take() else {
+            return;
+        };
+
+        let mut changes = Vec::new();
+        for (x, (&old, &new)) in before.v.iter().zip(chip_8.v.iter()).enumerate() {
+            if old != new {
+                changes.push(Change::V(x as u8, new));
+            }
+        }
+        if before.i != chip_8.i {
+            changes.push(Change::I(chip_8.i));
+        }
+        for (address, (&old, &new)) in before.memory.iter().zip(chip_8.memory.iter()).enumerate() {
+            if old != new {
+                changes.push(Change::Memory(address as u16, new));
+            }
+        }
+        if before.dt != chip_8.dt {
+            changes.push(Change::Dt(chip_8.dt));
+        }
+        if before.st != chip_8.st {
+            changes.push(Change::St(chip_8.st));
+        }
+
+        if self.error.is_none() {
+            if let Err(err) = self.write_record(&before, &changes) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<(), Err> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use super::{parse_frame_range, parse_pc_range, TraceFilter, TraceFormat, Tracer};
+    use crate::{Chip8, Instruction, Observer};
+
+    fn traced(format: TraceFormat, filter: TraceFilter) -> Vec<u8> {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0x6A, 0x7B, // LD VA, 0x7B
+            0xA3, 0x00, // LD I, 0x300
+            0xFA, 0x33, // LD B, VA
+            0x12, 0x06, // JP 0x206
+        ]).unwrap();
+
+        let mut tracer = Tracer::new(Vec::new(), format, filter).unwrap();
+        chip_8.run_frame_observed(&BTreeSet::new(), &mut tracer);
+        tracer.finish().unwrap();
+        tracer.into_inner()
+    }
+
+    #[test]
+    fn traces_text() {
+        let trace = String::from_utf8(traced(TraceFormat::Text, TraceFilter::default())).unwrap();
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(
+            lines[0].trim_end(),
+            "     1 0200 6A7B LD VA, 0x7B        VA=7B"
+        );
+        assert_eq!(
+            lines[1].trim_end(),
+            "     1 0202 A300 LD I, 0x300        I=0300"
+        );
+        assert_eq!(
+            lines[2].trim_end(),
+            "     1 0204 FA33 LD B, VA           [0300]=01 [0301]=02 [0302]=03"
+        );
+        assert_eq!(lines[3], "     1 0206 1206 JP 0x206");
+        assert_eq!(lines.len(), 16);
+    }
+
+    #[test]
+    fn traces_binary() {
+        let filter = TraceFilter {
+            classes: vec!["6xnn".to_string()],
+            ..TraceFilter::default()
+        };
+        let trace = traced(TraceFormat::Binary, filter);
+        #[rustfmt::skip]
+        assert_eq!(trace, vec![
+            b'C', b'8', b'T', b'R', 1,
+            0, 0, 0, 1,  0x02, 0x00,  0x6A, 0x7B,  1,
+            0,  0x00, 0x0A,  0x00, 0x7B,
+        ]);
+    }
+
+    #[test]
+    fn filters_instructions() {
+        let filter = TraceFilter {
+            pcs: vec![parse_pc_range("0x200-0x203").unwrap()],
+            classes: vec!["a".to_string()],
+            frames: Some(parse_frame_range("1-2").unwrap()),
+        };
+        let ld_i = Instruction::decode(0xA300);
+        assert!(filter.matches(1, 0x202, &ld_i));
+        assert!(!filter.matches(3, 0x202, &ld_i));
+        assert!(!filter.matches(1, 0x204, &ld_i));
+        assert!(!filter.matches(1, 0x202, &Instruction::decode(0x6A7B)));
+
+        assert_eq!(parse_pc_range("0x208"), Ok(0x208..=0x208));
+        assert!(parse_pc_range("0x300-0x200").is_err());
+        assert!(parse_pc_range("0x10000").is_err());
+        assert!(parse_frame_range("ten").is_err());
+    }
+}
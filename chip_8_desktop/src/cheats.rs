@@ -0,0 +1,17 @@
+use chip_8_core::{cheat::CheatFile, globals::Err};
+use std::{fs, io, path::Path};
+
+/// Read the cheat file at `path`. A missing file has no cheats yet.
+pub fn load(path: &Path) -> Result<CheatFile, Err> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(CheatFile::default()),
+        Err(err) => return Err(err.into()),
+    };
+    CheatFile::parse(&text).map_err(|err| format!("{}: {}", path.display(), err).into())
+}
+
+pub fn save(path: &Path, file: &CheatFile) -> Result<(), Err> {
+    fs::write(path, file.to_text())
+        .map_err(|err| format!("can't write cheat file {}: {}", path.display(), err).into())
+}
@@ -0,0 +1,127 @@
+use chip_8_core::{globals::Err, Palette, Persistence, Quirks};
+use serde::Deserialize;
+use std::{collections::HashMap, fs, io, path::Path, str::FromStr};
+
+/// Settings read from a TOML config file, e.g.
+///
+/// ```toml
+/// palette = "amber"
+///
+/// # Settings for one ROM, by file name
+/// [roms."brick.ch8"]
+/// persistence = "blend"
+/// quirks = "schip"
+/// ```
+///
+/// Command line arguments take precedence over the config file,
+/// and per-ROM settings over the top-level ones.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// A palette name, or comma-separated hex colors
+    pub palette: Option<String>,
+    /// `off`, `blend`, or a number of frames for erased pixels to fade out over
+    pub persistence: Option<String>,
+    /// A quirks preset: `vip`, `schip` or `xochip`
+    pub quirks: Option<String>,
+    /// Settings for ROMs, by file name
+    pub roms: HashMap<String, RomConfig>,
+}
+
+/// Settings for one ROM, overriding the top-level ones
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RomConfig {
+    pub palette: Option<String>,
+    pub persistence: Option<String>,
+    pub quirks: Option<String>,
+}
+
+impl Config {
+    /// Read the config file at `path`. A missing file is an empty config.
+    pub fn load(path: &Path) -> Result<Config, Err> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(err.into()),
+        };
+        toml::from_str(&text).map_err(|err| format!("{}: {}", path.display(), err).into())
+    }
+
+    pub fn palette(&self, rom: &Path) -> Result<Option<Palette>, Err> {
+        self.setting(
+            rom,
+            |config| config.palette.as_deref(),
+            self.palette.as_deref(),
+        )
+    }
+
+    pub fn persistence(&self, rom: &Path) -> Result<Option<Persistence>, Err> {
+        self.setting(
+            rom,
+            |config| config.persistence.as_deref(),
+            self.persistence.as_deref(),
+        )
+    }
+
+    pub fn quirks(&self, rom: &Path) -> Result<Option<Quirks>, Err> {
+        self.setting(
+            rom,
+            |config| config.quirks.as_deref(),
+            self.quirks.as_deref(),
+        )
+    }
+
+    /// A setting for the ROM at `rom`, falling back to the top-level one
+    fn setting<'a, T>(
+        &'a self,
+        rom: &Path,
+        get: impl Fn(&'a RomConfig) -> Option<&'a str>,
+        default: Option<&'a str>,
+    ) -> Result<Option<T>, Err>
+    where
+        T: FromStr<Err = String>,
+    {
+        let rom_config = rom
+            .file_name()
+            .and_then(|name| self.roms.get(name.to_string_lossy().as_ref()));
+        match rom_config.and_then(get).or(default) {
+            Some(value) => Ok(Some(value.parse()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use chip_8_core::{Palette, Persistence};
+
+    use super::Config;
+
+    #[test]
+    fn rom_settings_override_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            palette = "amber"
+            persistence = "blend"
+
+            [roms."brick.ch8"]
+            persistence = "4"
+            "#,
+        )
+        .unwrap();
+
+        let brick = Path::new("roms/brick.ch8");
+        assert_eq!(config.palette(brick).unwrap(), Some(Palette::AMBER));
+        assert_eq!(
+            config.persistence(brick).unwrap(),
+            Some(Persistence::Decay(4))
+        );
+        let other = Path::new("roms/pong.ch8");
+        assert_eq!(config.persistence(other).unwrap(), Some(Persistence::Blend));
+
+        assert!(toml::from_str::<Config>("colour = \"amber\"").is_err());
+    }
+}
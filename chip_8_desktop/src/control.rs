@@ -0,0 +1,653 @@
+//! A JSON-RPC 2.0 control socket, so test harnesses can drive a running emulator.
+//!
+//! Clients connect to a local TCP port or a Unix socket and send one request per
+//! line, getting one response per line. Requests without an `id` are notifications
+//! and get no response. Methods:
+//!
+//! ```text
+//! load_rom { path }              reset                      quit
+//! pause                          resume
+//! step { count = 1 }             run_frames { count = 1 }   both work while paused,
+//!                                                           up to 100000 at a time
+//! press_key { key }              release_key { key }        held until released
+//! read_registers                 -> { v, i, pc, dt, st, stack }
+//! read_memory { address, length } -> { data: hex }
+//! write_memory { address, data: hex }
+//! screenshot { path }            PNG, or PBM for a .pbm path
+//! save_state { path? }           -> { state: hex }, also written to path
+//! load_state { path } | { state: hex }
+//! ```
+
+use crate::interface;
+use chip_8_core::{
+    cheat::{self, CheatFile},
+    globals::{self, Err, Keys},
+    screenshot::{ImageFormat, Screenshot},
+    Chip8, Debugger, Interface, Observer, Palette, Quirks,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    ops::Range,
+    path::Path,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const SERVER_ERROR: i64 = -32000;
+
+// Most steps or frames one request can run, so a client can't hang the server
+const MAX_COUNT: u64 = 100_000;
+
+const METHODS: [&str; 15] = [
+    "load_rom",
+    "reset",
+    "pause",
+    "resume",
+    "quit",
+    "step",
+    "run_frames",
+    "press_key",
+    "release_key",
+    "read_registers",
+    "read_memory",
+    "write_memory",
+    "screenshot",
+    "save_state",
+    "load_state",
+];
+
+/// A request from a connection, and where to send its response
+struct Request {
+    message: Result<Value, String>,
+    reply: Sender<Option<Value>>,
+}
+
+/// Runs the ROM like `runner::run`, between requests from control socket clients
+pub struct ControlServer {
+    requests: Receiver<Request>,
+    chip_8: Chip8,
+    // A ROM a client loaded, which `reset` restarts instead of the interface's
+    rom: Option<Vec<u8>>,
+    // The quirks the interface's ROM runs with, which loaded ROMs get too
+    quirks: Quirks,
+    // Where loaded ROMs' cheats come from
+    cheat_file: CheatFile,
+    debugger: Debugger,
+    pressed: BTreeSet<u8>,
+    held_keys: BTreeSet<u8>,
+    paused: bool,
+    quit: bool,
+    palette: Palette,
+    screenshot_scale: u32,
+}
+
+impl ControlServer {
+    /// Listen on a local TCP port, or a Unix socket at any other address. Screenshots
+    /// use `palette` and `screenshot_scale`, and ROMs clients load get their cheats
+    /// from `cheat_file`.
+    pub fn bind(
+        address: &str,
+        palette: Palette,
+        screenshot_scale: u32,
+        cheat_file: CheatFile,
+    ) -> Result<Self, Err> {
+        let (sender, requests) = mpsc::channel();
+        if let Ok(port) = address.parse::<u16>() {
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    if let Ok(reader) = stream.try_clone() {
+                        serve_connection(reader, stream, sender.clone());
+                    }
+                }
+            });
+        } else {
+            listen_unix(Path::new(address), sender)?;
+        }
+        Ok(ControlServer::new(
+            requests,
+            palette,
+            screenshot_scale,
+            cheat_file,
+        ))
+    }
+
+    fn new(
+        requests: Receiver<Request>,
+        palette: Palette,
+        screenshot_scale: u32,
+        cheat_file: CheatFile,
+    ) -> Self {
+        ControlServer {
+            requests,
+            chip_8: Chip8::new(),
+            rom: None,
+            quirks: Quirks::default(),
+            cheat_file,
+            debugger: Debugger::new(),
+            pressed: BTreeSet::new(),
+            held_keys: BTreeSet::new(),
+            paused: false,
+            quit: false,
+            palette,
+            screenshot_scale,
+        }
+    }
+
+    /// Run the ROM the interface loads, answering requests between frames, until
+    /// the interface breaks out or a client asks to quit
+    pub fn run(
+        &mut self,
+        interface: &mut Box<dyn Interface>,
+        observer: &mut dyn Observer,
+    ) -> Result<(), Err> {
+        interface.load_rom(&mut self.chip_8)?;
+        self.quirks = self.chip_8.quirks;
+        interface.setup()?;
+
+        let frame = Duration::from_nanos(hertz::fps_to_ns_per_frame(
+            globals::FRAMES_PER_SECOND as usize,
+        ));
+        let mut last_frame_end = Instant::now();
+        loop {
+            let Keys::Keys(mut held_keys) = interface.read_keys()? else {
+                break;
+            };
+            held_keys.extend(&self.pressed);
+            self.held_keys = held_keys;
+
+            // While paused, wait a frame for requests instead of spinning
+            if self.paused {
+                match self.requests.recv_timeout(frame) {
+                    Ok(request) => self.answer(request, interface, observer),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            while let Ok(request) = self.requests.try_recv() {
+                self.answer(request, interface, observer);
+            }
+            if self.quit {
+                break;
+            }
+
+            if !self.paused {
+                self.debugger
+                    .run_frame(&mut self.chip_8, &self.held_keys, observer);
+            }
+            interface.draw(&mut self.chip_8)?;
+
+            if self.paused || !interface.real_time() {
+                continue;
+            }
+            thread::sleep(frame.saturating_sub(last_frame_end.elapsed()));
+            last_frame_end = Instant::now();
+        }
+
+        interface.cleanup()?;
+        observer.finish()
+    }
+
+    fn answer(
+        &mut self,
+        request: Request,
+        interface: &mut Box<dyn Interface>,
+        observer: &mut dyn Observer,
+    ) {
+        let response = match request.message {
+            Ok(message) => self.handle(&message, interface, observer),
+            Err(err) => Some(error(Value::Null, PARSE_ERROR, err)),
+        };
+        // The client may have gone
+        let _ = request.reply.send(response);
+    }
+
+    /// The response to a request, if it has an id
+    fn handle(
+        &mut self,
+        message: &Value,
+        interface: &mut Box<dyn Interface>,
+        observer: &mut dyn Observer,
+    ) -> Option<Value> {
+        let id = message.get("id").cloned();
+        let method = message["method"].as_str().unwrap_or_default();
+        if !METHODS.contains(&method) {
+            return Some(error(
+                id?,
+                METHOD_NOT_FOUND,
+                format!("Unknown method `{}`", method),
+            ));
+        }
+        let result = self.call(method, &message["params"], interface, observer);
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id?, "result": result }),
+            Err(err) => error(id?, SERVER_ERROR, err.to_string()),
+        })
+    }
+
+    /// The result of a call to one of `METHODS`
+    fn call(
+        &mut self,
+        method: &str,
+        params: &Value,
+        interface: &mut Box<dyn Interface>,
+        observer: &mut dyn Observer,
+    ) -> Result<Value, Err> {
+        let result = match method {
+            "load_rom" => {
+                let path = string_param(params, "path")?;
+                let rom = fs::read(path)
+                    .map_err(|err| format!("can't read ROM file {}: {}", path, err))?;
+                let mut chip_8 = Chip8::new();
+                interface::load_rom(&mut chip_8, &rom, self.quirks)?;
+                interface.set_cheats(self.cheat_file.cheats(&rom).to_vec());
+                self.chip_8 = chip_8;
+                self.rom = Some(rom);
+                self.debugger = Debugger::new();
+                Value::Null
+            }
+            "reset" => {
+                self.chip_8 = Chip8::new();
+                match &self.rom {
+                    Some(rom) => interface::load_rom(&mut self.chip_8, rom, self.quirks)?,
+                    None => interface.load_rom(&mut self.chip_8)?,
+                }
+                self.debugger = Debugger::new();
+                Value::Null
+            }
+            "pause" => {
+                self.paused = true;
+                Value::Null
+            }
+            "resume" => {
+                self.paused = false;
+                Value::Null
+            }
+            "quit" => {
+                self.quit = true;
+                Value::Null
+            }
+            "step" => {
+                for _ in 0..count_param(params)? {
+                    if let Some(fault) = self.chip_8.fault() {
+                        return Err(format!("Stopped at {:#X}: {}", self.chip_8.pc, fault).into());
+                    }
+                    self.debugger
+                        .step(&mut self.chip_8, &self.held_keys, observer);
+                }
+                self.registers()
+            }
+            "run_frames" => {
+                for _ in 0..count_param(params)? {
+                    self.debugger
+                        .run_frame(&mut self.chip_8, &self.held_keys, observer);
+                }
+                json!({ "frames": self.debugger.frames() })
+            }
+            "press_key" => {
+                let key = key_param(params)?;
+                self.pressed.insert(key);
+                self.held_keys.insert(key);
+                Value::Null
+            }
+            "release_key" => {
+                let key = key_param(params)?;
+                self.pressed.remove(&key);
+                self.held_keys.remove(&key);
+                Value::Null
+            }
+            "read_registers" => self.registers(),
+            "read_memory" => {
+                let start = address_param(params)?;
+                let length = params["length"].as_u64().ok_or("missing `length`")? as usize;
+                let memory = self
+                    .chip_8
+                    .memory
+                    .get(memory_range(start, length)?)
+                    .ok_or("That's past the end of memory")?;
+                json!({ "data": hex(memory) })
+            }
+            "write_memory" => {
+                let start = address_param(params)?;
+                let data = string_param(params, "data")?;
+                let bytes = cheat::parse_bytes(data)
+                    .ok_or_else(|| format!("`{}` isn't hex bytes", data))?;
+                self.chip_8
+                    .memory
+                    .get_mut(memory_range(start, bytes.len())?)
+                    .ok_or("That's past the end of memory")?
+                    .copy_from_slice(&bytes);
+                Value::Null
+            }
+            "screenshot" => {
+                let path = string_param(params, "path")?;
+                let format = match Path::new(path).extension() {
+                    Some(extension) if extension == "pbm" => ImageFormat::Pbm,
+                    _ => ImageFormat::Png,
+                };
+                Screenshot {
+                    display: &self.chip_8.display,
+                    width: globals::DISPLAY_WIDTH,
+                    height: globals::DISPLAY_HEIGHT,
+                    palette: self.palette,
+                    scale: self.screenshot_scale,
+                }
+                .save(Path::new(path), format)
+                .map_err(|err| format!("can't save screenshot {}: {}", path, err))?;
+                Value::Null
+            }
+            "save_state" => {
+                let state = self.chip_8.save_state()?;
+                if let Some(path) = params["path"].as_str() {
+                    fs::write(path, &state)
+                        .map_err(|err| format!("can't write save state {}: {}", path, err))?;
+                }
+                json!({ "state": hex(&state) })
+            }
+            "load_state" => {
+                let state = match (params["path"].as_str(), params["state"].as_str()) {
+                    (Some(path), _) => fs::read(path)
+                        .map_err(|err| format!("can't read save state {}: {}", path, err))?,
+                    (None, Some(state)) => {
+                        cheat::parse_bytes(state).ok_or("`state` isn't hex bytes")?
+                    }
+                    (None, None) => return Err("load_state needs a `path` or `state`".into()),
+                };
+                self.chip_8 = Chip8::load_state(&state)?;
+                Value::Null
+            }
+            _ => unreachable!(),
+        };
+        Ok(result)
+    }
+
+    fn registers(&self) -> Value {
+        let chip_8 = &self.chip_8;
+        json!({
+            "v": chip_8.v,
+            "i": chip_8.i,
+            "pc": chip_8.pc,
+            "dt": chip_8.dt,
+            "st": chip_8.st,
+            "stack": chip_8.stack,
+        })
+    }
+}
+
+#[cfg(unix)]
+fn listen_unix(path: &Path, sender: Sender<Request>) -> Result<(), Err> {
+    use std::os::unix::{fs::FileTypeExt, net::UnixListener};
+
+    // A socket left by an earlier run is in the way, but any other file isn't ours
+    if fs::metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|err| format!("can't listen on {}: {}", path.display(), err))?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            if let Ok(reader) = stream.try_clone() {
+                serve_connection(reader, stream, sender.clone());
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn listen_unix(path: &Path, _: Sender<Request>) -> Result<(), Err> {
+    Err(format!(
+        "`{}` isn't a port, and Unix sockets aren't supported here",
+        path.display()
+    )
+    .into())
+}
+
+/// Pass a client's requests to the run loop and write back the responses
+fn serve_connection(
+    reader: impl Read + Send + 'static,
+    mut writer: impl Write + Send + 'static,
+    sender: Sender<Request>,
+) {
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (reply, response) = mpsc::channel();
+            let message = serde_json::from_str(&line).map_err(|err| err.to_string());
+            if sender.send(Request { message, reply }).is_err() {
+                break;
+            }
+            let Ok(response) = response.recv() else {
+                break;
+            };
+            if let Some(response) = response {
+                if writeln!(writer, "{}", response).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+fn error(id: Value, code: i64, message: String) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message } })
+}
+
+fn string_param<'a>(params: &'a Value, name: &str) -> Result<&'a str, Err> {
+    params[name]
+        .as_str()
+        .ok_or_else(|| format!("missing `{}`", name).into())
+}
+
+fn count_param(params: &Value) -> Result<u64, Err> {
+    match &params["count"] {
+        Value::Null => Ok(1),
+        count => match count.as_u64() {
+            Some(count) if count <= MAX_COUNT => Ok(count),
+            _ => Err(format!("`count` isn't a count up to {}", MAX_COUNT).into()),
+        },
+    }
+}
+
+fn key_param(params: &Value) -> Result<u8, Err> {
+    match params["key"].as_u64() {
+        Some(key) if key < 16 => Ok(key as u8),
+        _ => Err("`key` isn't a key from 0 to 15".into()),
+    }
+}
+
+fn address_param(params: &Value) -> Result<usize, Err> {
+    params["address"]
+        .as_u64()
+        .map(|address| address as usize)
+        .ok_or_else(|| "missing `address`".into())
+}
+
+fn memory_range(start: usize, length: usize) -> Result<Range<usize>, Err> {
+    let end = start
+        .checked_add(length)
+        .ok_or("That's past the end of memory")?;
+    Ok(start..end)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+#[cfg(all(test, unix))]
+mod test {
+    use chip_8_core::{
+        cheat::CheatFile,
+        globals::{Err, Keys},
+        Chip8, Interface, Palette,
+    };
+    use serde_json::{json, Value};
+    use std::{
+        collections::BTreeSet,
+        env, fs,
+        io::{BufRead, BufReader, Write},
+        os::unix::net::UnixStream,
+        thread,
+        time::Duration,
+    };
+
+    use super::ControlServer;
+
+    /// Loads a ROM and runs flat out, without a display
+    struct Bare;
+
+    impl Interface for Bare {
+        // The control server runs the frames itself, and only calls the other methods
+        fn run(&mut self, _: &mut Chip8) -> Result<(), Err> {
+            Ok(())
+        }
+
+        fn setup(&mut self) -> Result<(), Err> {
+            Ok(())
+        }
+
+        fn load_rom(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
+            #[rustfmt::skip]
+            chip_8.load_rom(&[
+                0x60, 0x00, // 200: LD V0, 0
+                0x70, 0x01, // 202: ADD V0, 1
+                0xE1, 0x9E, // 204: SKP V1
+                0x12, 0x02, // 206: JP 0x202
+                0x12, 0x08, // 208: JP 0x208
+            ])?;
+            Ok(())
+        }
+
+        fn read_keys(&mut self) -> Result<Keys, Err> {
+            Ok(Keys::Keys(BTreeSet::new()))
+        }
+
+        fn draw(&mut self, _: &mut Chip8) -> Result<(), Err> {
+            Ok(())
+        }
+
+        fn cleanup(&mut self) -> Result<(), Err> {
+            Ok(())
+        }
+
+        fn real_time(&self) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn serves_requests() {
+        let directory = env::temp_dir().join(format!("chip_8_control_{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let socket = directory.join("control.sock");
+        let screenshot = directory.join("screen.pbm");
+
+        let path = socket.to_str().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let mut interface: Box<dyn Interface> = Box::new(Bare);
+            ControlServer::bind(&path, Palette::default(), 1, CheatFile::default())
+                .unwrap()
+                .run(&mut interface, &mut ())
+                .unwrap();
+        });
+        let stream = loop {
+            match UnixStream::connect(&socket) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        let mut responses = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut writer = stream;
+        let mut call = |method: &str, params: Value| -> Value {
+            let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+            writeln!(writer, "{}", request).unwrap();
+            serde_json::from_str(&responses.next().unwrap().unwrap()).unwrap()
+        };
+
+        call("pause", json!({}));
+        call("reset", json!({}));
+        let registers = &call("step", json!({ "count": 3 }))["result"];
+        assert_eq!(
+            (&registers["pc"], &registers["v"][0]),
+            (&json!(0x206), &json!(1))
+        );
+
+        call("write_memory", json!({ "address": 0x300, "data": "F0A5" }));
+        assert_eq!(
+            call("read_memory", json!({ "address": 0x2FF, "length": 3 }))["result"],
+            json!({ "data": "00F0A5" })
+        );
+
+        // Key 0 ends the loop
+        call("press_key", json!({ "key": 0 }));
+        assert_eq!(
+            call("run_frames", json!({}))["result"],
+            json!({ "frames": 1 })
+        );
+        assert_eq!(
+            call("read_registers", json!(null))["result"]["pc"],
+            json!(0x208)
+        );
+
+        let state = call("save_state", json!({}))["result"]["state"].clone();
+        call("reset", json!({}));
+        call("load_state", json!({ "state": state }));
+        assert_eq!(
+            call("read_registers", json!(null))["result"]["pc"],
+            json!(0x208)
+        );
+
+        call("screenshot", json!({ "path": screenshot }));
+        assert!(fs::read_to_string(&screenshot)
+            .unwrap()
+            .starts_with("P1\n64 32\n"));
+
+        assert_eq!(call("jump", json!({}))["error"]["code"], json!(-32601));
+        let huge = json!({ "address": u64::MAX, "length": 2, "data": "0102" });
+        assert_eq!(
+            call("read_memory", huge.clone())["error"]["code"],
+            json!(-32000)
+        );
+        assert_eq!(call("write_memory", huge)["error"]["code"], json!(-32000));
+        assert_eq!(
+            call("run_frames", json!({ "count": u64::MAX }))["error"]["message"],
+            json!("`count` isn't a count up to 100000")
+        );
+        assert_eq!(
+            call("press_key", json!({ "key": 16 }))["error"]["message"],
+            json!("`key` isn't a key from 0 to 15")
+        );
+
+        // A loaded ROM is set up like the interface's, and reset restarts it
+        let rom = directory.join("loaded.ch8");
+        fs::write(&rom, [0x61, 0x07, 0x12, 0x02]).unwrap();
+        call("load_rom", json!({ "path": rom }));
+        call("step", json!({}));
+        call("reset", json!({}));
+        assert_eq!(
+            call("read_memory", json!({ "address": 0x1FE, "length": 4 }))["result"],
+            json!({ "data": "02056107" })
+        );
+        assert_eq!(
+            call("read_registers", json!(null))["result"]["pc"],
+            json!(0x200)
+        );
+
+        call("quit", json!({}));
+        server.join().unwrap();
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
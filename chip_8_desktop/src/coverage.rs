@@ -0,0 +1,56 @@
+use chip_8_core::{
+    coverage::{Coverage, CoverageFormat},
+    globals::Err,
+    Chip8, Observer,
+};
+use log::info;
+use std::{fs, path::PathBuf};
+
+/// Tracks coverage of a run, and once it's over adds it to a data file
+/// and writes a report of everything covered so far
+pub struct CoverageReport {
+    coverage: Coverage,
+    data_path: Option<PathBuf>,
+    report_path: Option<PathBuf>,
+    format: CoverageFormat,
+}
+
+impl CoverageReport {
+    pub fn new(
+        rom: &[u8],
+        data_path: Option<PathBuf>,
+        report_path: Option<PathBuf>,
+        format: CoverageFormat,
+    ) -> Self {
+        CoverageReport {
+            coverage: Coverage::new(rom),
+            data_path,
+            report_path,
+            format,
+        }
+    }
+}
+
+impl Observer for CoverageReport {
+    fn before_cycle(&mut self, chip_8: &Chip8) {
+        self.coverage.before_cycle(chip_8);
+    }
+
+    fn finish(&mut self) -> Result<(), Err> {
+        if let Some(path) = &self.data_path {
+            if path.exists() {
+                let data = fs::read_to_string(path)?;
+                let previous = Coverage::from_data(self.coverage.rom(), &data)
+                    .map_err(|err| format!("{}: {}", path.display(), err))?;
+                self.coverage.merge(&previous)?;
+            }
+            fs::write(path, self.coverage.to_data())?;
+            info!("Wrote coverage data {}", path.display());
+        }
+        if let Some(path) = &self.report_path {
+            fs::write(path, self.coverage.report(self.format))?;
+            info!("Wrote coverage report {}", path.display());
+        }
+        Ok(())
+    }
+}
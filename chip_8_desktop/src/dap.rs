@@ -0,0 +1,598 @@
+//! A Debug Adapter Protocol server, so editors like VS Code can debug ROMs.
+//!
+//! `launch` takes the ROM as `program`, and optionally a `symbols` map, a `quirks`
+//! preset and `stopOnEntry`. The symbol map's `:breakpoint`s are set at launch, as
+//! the debugger prompt sets them. Breakpoints can be set on source lines or labels from
+//! the symbol map, or on addresses as instruction breakpoints. Memory references
+//! are hex addresses like `0x0202`.
+
+use crate::debugger::{find_source, read_symbol_map};
+use chip_8_core::{
+    debugger::Stop,
+    globals::{self, Err},
+    Chip8, Debugger, Instruction, Observer, Quirks,
+};
+use chip_8_octo::SymbolMap;
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+const THREAD_ID: u64 = 1;
+
+// Variable references for the scopes every stack frame shows
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const STACK: u64 = 3;
+
+// Instructions `next` and `stepOut` run waiting for a subroutine to return, a minute of play
+const MAX_STEPS: u64 = 60 * 60 * globals::INSTRUCTIONS_PER_FRAME as u64;
+
+/// Debugs a ROM for one DAP client, running it in real time between requests
+pub struct DapServer {
+    chip_8: Chip8,
+    debugger: Debugger,
+    held_keys: BTreeSet<u8>,
+    observers: Vec<Box<dyn Observer>>,
+    symbols: SymbolMap,
+    source: Option<PathBuf>,
+    // Each kind of breakpoint request replaces the breakpoints it set last time.
+    // The symbol map's are set at launch.
+    symbol_breakpoints: BTreeSet<u16>,
+    line_breakpoints: BTreeSet<u16>,
+    function_breakpoints: BTreeSet<u16>,
+    instruction_breakpoints: BTreeSet<u16>,
+    stop_on_entry: bool,
+    running: bool,
+    seq: u64,
+}
+
+impl DapServer {
+    pub fn new(observers: Vec<Box<dyn Observer>>) -> Self {
+        DapServer {
+            chip_8: Chip8::new(),
+            debugger: Debugger::new(),
+            held_keys: BTreeSet::new(),
+            observers,
+            symbols: SymbolMap::default(),
+            source: None,
+            symbol_breakpoints: BTreeSet::new(),
+            line_breakpoints: BTreeSet::new(),
+            function_breakpoints: BTreeSet::new(),
+            instruction_breakpoints: BTreeSet::new(),
+            stop_on_entry: false,
+            running: false,
+            seq: 0,
+        }
+    }
+
+    /// Read requests from `input` and write responses and events to `out` until the
+    /// client disconnects
+    pub fn serve(
+        mut self,
+        input: impl Read + Send + 'static,
+        mut out: impl Write,
+    ) -> Result<(), Err> {
+        let (sender, messages) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            while let Ok(Some(message)) = read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let frame = Duration::from_nanos(hertz::fps_to_ns_per_frame(
+            globals::FRAMES_PER_SECOND as usize,
+        ));
+        loop {
+            let message = if self.running {
+                match messages.recv_timeout(frame) {
+                    Ok(message) => Some(message),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            } else {
+                match messages.recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => break,
+                }
+            };
+            match message {
+                Some(message) => {
+                    if !self.handle(&message, &mut out)? {
+                        break;
+                    }
+                }
+                None => self.run_frame(&mut out)?,
+            }
+        }
+        self.observers.finish()
+    }
+
+    /// Respond to a request, then do anything that has to follow the response, like
+    /// stepping. Returns false once the client disconnects.
+    fn handle(&mut self, message: &Value, out: &mut impl Write) -> Result<bool, Err> {
+        let command = message["command"].as_str().unwrap_or_default();
+        let result = self.request(command, &message["arguments"]);
+        self.seq += 1;
+        let mut response = json!({
+            "seq": self.seq,
+            "type": "response",
+            "request_seq": message["seq"],
+            "command": command,
+            "success": result.is_ok(),
+        });
+        match &result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body.clone(),
+            Err(err) => response["message"] = err.to_string().into(),
+        }
+        write_message(out, &response)?;
+        if result.is_err() {
+            return Ok(true);
+        }
+
+        match command {
+            "launch" => self.event(out, "initialized", Value::Null)?,
+            "configurationDone" if self.stop_on_entry => self.stopped(out, "entry")?,
+            "configurationDone" => self.running = true,
+            "next" | "stepIn" | "stepOut" => self.step(command, out)?,
+            "pause" => {
+                self.running = false;
+                self.stopped(out, "pause")?;
+            }
+            "disconnect" => return Ok(false),
+            "terminate" => {
+                self.event(out, "terminated", Value::Null)?;
+                return Ok(false);
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// The response body for a request
+    fn request(&mut self, command: &str, arguments: &Value) -> Result<Value, Err> {
+        let body = match command {
+            "initialize" => json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsReadMemoryRequest": true,
+            }),
+            "launch" => {
+                self.launch(arguments)?;
+                Value::Null
+            }
+            "setBreakpoints" => self.set_line_breakpoints(arguments),
+            "setFunctionBreakpoints" => self.set_function_breakpoints(arguments),
+            "setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments)?,
+            "threads" => json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] }),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => json!({
+                "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                    { "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+                    { "name": "Stack", "variablesReference": STACK, "expensive": false },
+                ]
+            }),
+            "variables" => self.variables(arguments["variablesReference"].as_u64()),
+            "readMemory" => self.read_memory(arguments)?,
+            "continue" => {
+                self.running = true;
+                json!({ "allThreadsContinued": true })
+            }
+            "configurationDone" | "next" | "stepIn" | "stepOut" | "pause" | "disconnect"
+            | "terminate" => Value::Null,
+            _ => return Err(format!("Unsupported request `{}`", command).into()),
+        };
+        Ok(body)
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<(), Err> {
+        let program = arguments["program"]
+            .as_str()
+            .ok_or("launch needs the ROM as `program`")?;
+        let rom = fs::read(program).map_err(|err| format!("can't read ROM file: {}", err))?;
+        self.chip_8.load_rom(&rom)?;
+        if let Some(quirks) = arguments["quirks"].as_str() {
+            self.chip_8.quirks = quirks.parse::<Quirks>()?;
+        }
+        if let Some(path) = arguments["symbols"].as_str() {
+            self.symbols = read_symbol_map(Path::new(path))?;
+            self.source = find_source(&self.symbols, Path::new(path))
+                .map(|source| fs::canonicalize(&source).unwrap_or(source));
+            self.symbol_breakpoints = self.symbols.breakpoints.keys().copied().collect();
+            self.update_breakpoints();
+        }
+        self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(())
+    }
+
+    /// Breakpoints on the first instruction compiled from each source line
+    fn set_line_breakpoints(&mut self, arguments: &Value) -> Value {
+        let same_source = match (&self.source, arguments["source"]["path"].as_str()) {
+            (Some(source), Some(path)) => source.file_name() == Path::new(path).file_name(),
+            _ => false,
+        };
+        self.line_breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+            let address = self
+                .symbols
+                .lines
+                .iter()
+                .find(|&(_, &address_line)| same_source && address_line == line)
+                .map(|(&address, _)| address);
+            let mut breakpoint = self.breakpoint(address);
+            breakpoint["line"] = line.into();
+            breakpoints.push(breakpoint);
+            self.line_breakpoints.extend(self.in_memory(address));
+        }
+        self.update_breakpoints();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    /// Breakpoints on labels
+    fn set_function_breakpoints(&mut self, arguments: &Value) -> Value {
+        self.function_breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let name = breakpoint["name"].as_str().unwrap_or_default();
+            let address = self.symbols.labels.get(name).copied();
+            breakpoints.push(self.breakpoint(address));
+            self.function_breakpoints.extend(self.in_memory(address));
+        }
+        self.update_breakpoints();
+        json!({ "breakpoints": breakpoints })
+    }
+
+    /// Breakpoints on addresses
+    fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, Err> {
+        self.instruction_breakpoints.clear();
+        let mut breakpoints = Vec::new();
+        for breakpoint in arguments["breakpoints"].as_array().into_iter().flatten() {
+            let reference = breakpoint["instructionReference"]
+                .as_str()
+                .unwrap_or_default();
+            let offset = breakpoint["offset"].as_i64().unwrap_or_default();
+            let address = (parse_reference(reference)? as i64)
+                .checked_add(offset)
+                .and_then(|address| u16::try_from(address).ok());
+            breakpoints.push(self.breakpoint(address));
+            self.instruction_breakpoints.extend(self.in_memory(address));
+        }
+        self.update_breakpoints();
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn in_memory(&self, address: Option<u16>) -> Option<u16> {
+        address.filter(|&address| (address as usize) < self.chip_8.memory.len())
+    }
+
+    /// The breakpoint a client asked for, verified if it's somewhere in memory
+    fn breakpoint(&self, address: Option<u16>) -> Value {
+        match self.in_memory(address) {
+            Some(address) => {
+                json!({ "verified": true, "instructionReference": reference(address as usize) })
+            }
+            None => json!({ "verified": false }),
+        }
+    }
+
+    fn update_breakpoints(&mut self) {
+        self.debugger.breakpoints = self
+            .symbol_breakpoints
+            .iter()
+            .chain(&self.line_breakpoints)
+            .chain(&self.function_breakpoints)
+            .chain(&self.instruction_breakpoints)
+            .copied()
+            .collect();
+    }
+
+    /// The PC, then the return addresses on the stack, innermost first
+    fn stack_trace(&self) -> Value {
+        let addresses =
+            std::iter::once(self.chip_8.pc).chain(self.chip_8.stack.iter().rev().copied());
+        let frames: Vec<Value> = addresses
+            .enumerate()
+            .map(|(id, address)| {
+                let name = match self.symbols.locate(address) {
+                    Some((name, 0)) => name.to_string(),
+                    Some((name, offset)) => format!("{}+{}", name, offset),
+                    None => reference(address as usize),
+                };
+                let mut frame = json!({
+                    "id": id,
+                    "name": name,
+                    "line": 0,
+                    "column": 0,
+                    "instructionPointerReference": reference(address as usize),
+                });
+                if let (Some(source), Some(&line)) =
+                    (&self.source, self.symbols.lines.get(&address))
+                {
+                    frame["source"] = json!({
+                        "name": source.file_name().map(|name| name.to_string_lossy()),
+                        "path": source.display().to_string(),
+                    });
+                    frame["line"] = line.into();
+                    frame["column"] = 1.into();
+                }
+                frame
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn variables(&self, reference: Option<u64>) -> Value {
+        let chip_8 = &self.chip_8;
+        let variables: Vec<(String, String)> = match reference {
+            Some(REGISTERS) => (0..16)
+                .map(|x| (format!("V{:X}", x), format!("0x{:02X}", chip_8.v[x])))
+                .chain([
+                    ("I".to_string(), format!("0x{:04X}", chip_8.i)),
+                    ("PC".to_string(), format!("0x{:04X}", chip_8.pc)),
+                ])
+                .collect(),
+            Some(TIMERS) => vec![
+                ("DT".to_string(), format!("0x{:02X}", chip_8.dt)),
+                ("ST".to_string(), format!("0x{:02X}", chip_8.st)),
+            ],
+            Some(STACK) => chip_8
+                .stack
+                .iter()
+                .enumerate()
+                .map(|(depth, address)| (depth.to_string(), format!("0x{:04X}", address)))
+                .collect(),
+            _ => Vec::new(),
+        };
+        let variables: Vec<Value> = variables
+            .into_iter()
+            .map(|(name, value)| json!({ "name": name, "value": value, "variablesReference": 0 }))
+            .collect();
+        json!({ "variables": variables })
+    }
+
+    fn read_memory(&self, arguments: &Value) -> Result<Value, Err> {
+        let memory_reference = arguments["memoryReference"]
+            .as_str()
+            .ok_or("readMemory needs a `memoryReference`")?;
+        let start = (parse_reference(memory_reference)? as i64)
+            .saturating_add(arguments["offset"].as_i64().unwrap_or(0));
+        let count = arguments["count"].as_u64().unwrap_or(0) as usize;
+        let memory = &self.chip_8.memory;
+        let start = start.clamp(0, memory.len() as i64) as usize;
+        let bytes = &memory[start..start.saturating_add(count).min(memory.len())];
+        Ok(json!({
+            "address": reference(start),
+            "data": base64(bytes),
+            "unreadableBytes": count - bytes.len(),
+        }))
+    }
+
+    /// Run one instruction, or with `next` on a call or `stepOut` until the
+    /// subroutine returns, stopping early at breakpoints
+    fn step(&mut self, command: &str, out: &mut impl Write) -> Result<(), Err> {
+        self.running = false;
+        if self.chip_8.fault().is_some() {
+            return self.stopped(out, "exception");
+        }
+        let depth = self.chip_8.stack.len();
+        let pc = self.chip_8.pc as usize;
+        let opcode = self
+            .chip_8
+            .memory
+            .get(pc..pc + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+        let return_depth = match command {
+            "next"
+                if matches!(
+                    opcode.map(Instruction::decode),
+                    Some(Instruction::Call { .. })
+                ) =>
+            {
+                Some(depth)
+            }
+            "stepOut" => depth.checked_sub(1),
+            _ => None,
+        };
+
+        self.debugger
+            .step(&mut self.chip_8, &self.held_keys, &mut self.observers);
+        if let Some(return_depth) = return_depth {
+            for _ in 0..MAX_STEPS {
+                if self.chip_8.stack.len() <= return_depth {
+                    break;
+                }
+                if self.debugger.breakpoints.contains(&self.chip_8.pc) {
+                    return self.stopped(out, "breakpoint");
+                }
+                if self.chip_8.fault().is_some() {
+                    return self.stopped(out, "exception");
+                }
+                self.debugger
+                    .step(&mut self.chip_8, &self.held_keys, &mut self.observers);
+            }
+        }
+        self.stopped(out, "step")
+    }
+
+    fn run_frame(&mut self, out: &mut impl Write) -> Result<(), Err> {
+        let stop = self
+            .debugger
+            .run_frame(&mut self.chip_8, &self.held_keys, &mut self.observers);
+        let reason = match stop {
+            Stop::FrameEnd => return Ok(()),
+            Stop::Breakpoint(_) => "breakpoint",
+            Stop::Watchpoint(..) => "data breakpoint",
+            Stop::Fault(_) => "exception",
+        };
+        self.running = false;
+        self.stopped(out, reason)
+    }
+
+    fn stopped(&mut self, out: &mut impl Write, reason: &str) -> Result<(), Err> {
+        let body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        self.event(out, "stopped", body)
+    }
+
+    fn event(&mut self, out: &mut impl Write, event: &str, body: Value) -> Result<(), Err> {
+        self.seq += 1;
+        let mut message = json!({ "seq": self.seq, "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        write_message(out, &message)?;
+        Ok(())
+    }
+}
+
+/// Read a `Content-Length` framed message. Returns None at the end of the input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>, Err> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+    let mut body = vec![0; length.ok_or("DAP message without a Content-Length")?];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(out: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()
+}
+
+fn reference(address: usize) -> String {
+    format!("0x{:04X}", address)
+}
+
+fn parse_reference(reference: &str) -> Result<u16, Err> {
+    let hex = reference.strip_prefix("0x").unwrap_or(reference);
+    u16::from_str_radix(hex, 16)
+        .map_err(|_| format!("`{}` isn't a memory reference", reference).into())
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let group = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |group, (index, &byte)| {
+                group | (byte as u32) << (16 - 8 * index)
+            });
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(group >> (18 - 6 * index) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::{json, Value};
+    use std::{env, fs, io::Cursor, process};
+
+    use super::{read_message, DapServer};
+
+    // Frames to let a continued ROM run before giving up on it stopping
+    const MAX_FRAMES: usize = 600;
+
+    #[test]
+    fn replays_transcript() {
+        let root = fs::canonicalize(concat!(env!("CARGO_MANIFEST_DIR"), "/..")).unwrap();
+        let transcript =
+            include_str!("../tests/dap/hello.txt").replace("$ROOT", root.to_str().unwrap());
+
+        let mut server = DapServer::new(Vec::new());
+        let mut out = Vec::new();
+        let mut expected = Vec::new();
+        for line in transcript.lines() {
+            if let Some(request) = line.strip_prefix("-> ") {
+                let request: Value = serde_json::from_str(request).unwrap();
+                server.handle(&request, &mut out).unwrap();
+                for _ in 0..MAX_FRAMES {
+                    if !server.running {
+                        break;
+                    }
+                    server.run_frame(&mut out).unwrap();
+                }
+            } else if let Some(reply) = line.strip_prefix("<- ") {
+                expected.push(serde_json::from_str::<Value>(reply).unwrap());
+            }
+        }
+
+        let mut sent = Vec::new();
+        let mut out = Cursor::new(out);
+        while let Some(message) = read_message(&mut out).unwrap() {
+            sent.push(message);
+        }
+        assert_eq!(sent, expected);
+    }
+
+    #[test]
+    fn handles_out_of_range_requests() {
+        let dir = env::temp_dir().join(format!("chip_8_dap_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (rom, symbols) = (dir.join("ret.ch8"), dir.join("ret.sym"));
+        fs::write(&rom, [0x00, 0xEE]).unwrap(); // 200: RET
+        fs::write(&symbols, "label 0200 main\nbreakpoint 0200 start\n").unwrap();
+
+        let mut server = DapServer::new(Vec::new());
+        server
+            .request("launch", &json!({ "program": rom, "symbols": symbols }))
+            .unwrap();
+        assert!(server.debugger.breakpoints.contains(&0x200));
+
+        let memory = json!({ "memoryReference": "0x0FFE", "count": u64::MAX });
+        let memory = server.request("readMemory", &memory).unwrap();
+        assert_eq!(memory["unreadableBytes"], u64::MAX - 2);
+
+        let breakpoints = json!({ "breakpoints": [
+            { "instructionReference": "0x0200", "offset": i64::MAX },
+        ] });
+        let breakpoints = server
+            .request("setInstructionBreakpoints", &breakpoints)
+            .unwrap();
+        assert_eq!(breakpoints["breakpoints"][0]["verified"], false);
+
+        // Stepping stops before a RET with nothing to return to, or a PC past memory
+        for pc in [0x200, 0xFFF] {
+            server.chip_8.pc = pc;
+            let mut out = Vec::new();
+            server.step("next", &mut out).unwrap();
+            let stopped = read_message(&mut Cursor::new(out)).unwrap().unwrap();
+            assert_eq!(stopped["body"]["reason"], "exception");
+            assert_eq!(server.chip_8.pc, pc);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
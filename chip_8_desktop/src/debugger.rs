@@ -0,0 +1,801 @@
+use crate::{
+    cheats,
+    memory_view::{self, MemoryView},
+    profile::ProfileReport,
+};
+use chip_8_core::{
+    cheat::{self, Cheat, CheatFile, Search, SearchFilter},
+    debugger::Stop,
+    globals,
+    globals::Err,
+    Chip8, Debugger, Instruction, Observer,
+};
+use chip_8_octo::SymbolMap;
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    path::{Path, PathBuf},
+};
+
+// Candidates `search` lists, once it's narrowed them down this far
+const MAX_LISTED_CANDIDATES: usize = 16;
+
+// Rows of 16 bytes `view` shows
+const VIEW_ROWS: usize = 8;
+
+// Frames `continue` runs before giving up on hitting a breakpoint, a minute of play
+const MAX_CONTINUE_FRAMES: u64 = 60 * 60;
+
+const HELP: &str = "\
+step [n]           run n instructions (default 1)
+frame [n]          run n frames, stopping at breakpoints (default 1)
+continue [n]       run until a breakpoint, for at most n frames (default 3600)
+break <addr>       stop before running the instruction at addr
+delete [addr]      remove a breakpoint, or all of them
+breakpoints        list breakpoints
+regs               show registers, timers and the stack
+disasm [addr] [n]  disassemble n instructions (default 10) from addr (default PC)
+mem <addr> [len]   dump len bytes of memory (default 64)
+view [addr|i|off]  show memory from addr, or following I, after every stop. The byte
+                   at I is marked >, bytes changed this frame *. Alone, shows it now.
+poke <addr> <hex bytes>
+                   write bytes to memory, e.g. `poke 300 0A0B`
+sprite [addr] [n]  show n bytes (default 15) from addr (default I) as sprite rows
+screen             show the display
+keys [keys]        hold these hex keys while running, e.g. `keys 4 6`
+profile [json]     show the profile so far
+search [filter]    narrow down a memory search by a value, or changed, unchanged,
+                   increased or decreased since the last search. Alone, starts over.
+cheat              list the ROM's cheats, which apply at the start of every frame
+cheat add <addr> <hex bytes> [name]
+                   add a cheat holding bytes of memory fixed, e.g. `cheat add 300 05`
+cheat on|off|delete <n>
+                   turn cheat n on or off, or delete it. Changes save to the cheat file.
+quit               stop debugging
+An empty line repeats the last command. Addresses are hex, or label and constant
+names from the symbol map.";
+
+/// A command prompt for stepping through a ROM
+pub struct Repl {
+    chip_8: Chip8,
+    debugger: Debugger,
+    held_keys: BTreeSet<u8>,
+    profile: ProfileReport,
+    observers: Vec<Box<dyn Observer>>,
+    symbols: SymbolMap,
+    source: Vec<String>,
+    search: Option<Search>,
+    cheat_file: CheatFile,
+    cheats_path: Option<PathBuf>,
+    rom: Vec<u8>,
+    view: Option<MemoryView>,
+    /// Whether `view` highlights with terminal colors
+    colors: bool,
+    /// Memory at the start of the frame, to show what's changed since
+    frame_start_memory: Vec<u8>,
+}
+
+impl Repl {
+    pub fn new(chip_8: Chip8, profile: ProfileReport, observers: Vec<Box<dyn Observer>>) -> Self {
+        Repl {
+            frame_start_memory: chip_8.memory.to_vec(),
+            chip_8,
+            debugger: Debugger::new(),
+            held_keys: BTreeSet::new(),
+            profile,
+            observers,
+            symbols: SymbolMap::default(),
+            source: Vec::new(),
+            search: None,
+            cheat_file: CheatFile::default(),
+            cheats_path: None,
+            rom: Vec::new(),
+            view: None,
+            colors: false,
+        }
+    }
+
+    /// Apply and edit the cheats for `rom` in a cheat file, saving changes to `path`
+    pub fn use_cheats(&mut self, cheat_file: CheatFile, path: PathBuf, rom: Vec<u8>) {
+        self.cheat_file = cheat_file;
+        self.cheats_path = Some(path);
+        self.rom = rom;
+    }
+
+    /// Use the labels, constants, `:breakpoint`s and source lines from a symbol map
+    pub fn load_symbols(&mut self, path: &Path) -> Result<(), Err> {
+        self.symbols = read_symbol_map(path)?;
+        self.debugger
+            .breakpoints
+            .extend(self.symbols.breakpoints.keys());
+        if let Some(source) = find_source(&self.symbols, path) {
+            let source = fs::read_to_string(&source)
+                .map_err(|err| format!("can't read source file {}: {}", source.display(), err))?;
+            self.source = source.lines().map(str::to_string).collect();
+        }
+        Ok(())
+    }
+
+    /// Read commands from stdin until `quit` or the end of input
+    pub fn run(&mut self) -> Result<(), Err> {
+        let mut stdout = io::stdout();
+        self.colors = stdout.is_terminal();
+        let mut last_command = String::new();
+        writeln!(stdout, "Type `help` for commands")?;
+        self.show_location(&mut stdout)?;
+        write!(stdout, "(chip8) ")?;
+        stdout.flush()?;
+        for line in io::stdin().lock().lines() {
+            let line = line?;
+            let command = match line.trim() {
+                "" => last_command.clone(),
+                command => command.to_string(),
+            };
+            match self.execute(&command, &mut stdout) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(err) => writeln!(stdout, "{}", err)?,
+            }
+            last_command = command;
+            write!(stdout, "(chip8) ")?;
+            stdout.flush()?;
+        }
+        (&mut self.profile, &mut self.observers).finish()
+    }
+
+    /// Run one command. Returns false once it's time to quit.
+    fn execute(&mut self, command: &str, out: &mut impl Write) -> Result<bool, Err> {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(true);
+        };
+        let args: Vec<&str> = words.collect();
+        let arg = |index: usize| args.get(index).copied();
+
+        match name {
+            "s" | "step" => {
+                for _ in 0..parse_count(arg(0), 1)? {
+                    if let Some(fault) = self.chip_8.fault() {
+                        writeln!(out, "Stopped at {:04X}: {}", self.chip_8.pc, fault)?;
+                        break;
+                    }
+                    self.start_frame();
+                    self.debugger.step(
+                        &mut self.chip_8,
+                        &self.held_keys,
+                        &mut (&mut self.profile, &mut self.observers),
+                    );
+                }
+                self.show_location(out)?;
+            }
+            "f" | "frame" => {
+                let frames = parse_count(arg(0), 1)?;
+                self.run_frames(frames, out)?;
+            }
+            "c" | "continue" => {
+                let frames = parse_count(arg(0), MAX_CONTINUE_FRAMES)?;
+                if !self.run_frames(frames, out)? {
+                    writeln!(out, "No breakpoint hit in {} frames", frames)?;
+                }
+            }
+            "b" | "break" => {
+                let address = self.parse_address(arg(0).ok_or("break needs an address")?)?;
+                self.debugger.breakpoints.insert(address);
+            }
+            "delete" => match arg(0) {
+                Some(address) => {
+                    let address = self.parse_address(address)?;
+                    if !self.debugger.breakpoints.remove(&address) {
+                        writeln!(out, "No breakpoint at {:04X}", address)?;
+                    }
+                }
+                None => self.debugger.breakpoints.clear(),
+            },
+            "breakpoints" => {
+                for &address in &self.debugger.breakpoints {
+                    match self.symbols.locate(address) {
+                        Some(location) => writeln!(out, "{:04X}  {}", address, describe(location))?,
+                        None => writeln!(out, "{:04X}", address)?,
+                    }
+                }
+            }
+            "r" | "regs" => self.show_registers(out)?,
+            "d" | "disasm" => {
+                let start = match arg(0) {
+                    Some(address) => self.parse_address(address)?,
+                    None => self.chip_8.pc,
+                };
+                let count = parse_count(arg(1), 10)?;
+                let mut address = start;
+                for _ in 0..count {
+                    for name in self.symbols.labels_at(address) {
+                        writeln!(out, "{}:", name)?;
+                    }
+                    if !self.show_instruction(address, out)? {
+                        break;
+                    }
+                    address += 2;
+                }
+            }
+            "m" | "mem" => {
+                let start = self.parse_address(arg(0).ok_or("mem needs an address")?)? as usize;
+                let len = parse_count(arg(1), 64)? as usize;
+                let end = start.saturating_add(len).min(self.chip_8.memory.len());
+                // Rows also break where a label starts, so each one is in a single region
+                let mut row = start;
+                while row < end {
+                    let next_label = self
+                        .symbols
+                        .labels
+                        .values()
+                        .map(|&address| address as usize)
+                        .filter(|&address| address > row)
+                        .min()
+                        .unwrap_or(usize::MAX);
+                    let row_end = (row + 16).min(end).min(next_label);
+                    let hex: Vec<String> = self.chip_8.memory[row..row_end]
+                        .iter()
+                        .map(|byte| format!("{:02X}", byte))
+                        .collect();
+                    match self.symbols.locate(row as u16) {
+                        Some(location) => writeln!(
+                            out,
+                            "{:04X}  {:<47}  {}",
+                            row,
+                            hex.join(" "),
+                            describe(location)
+                        )?,
+                        None => writeln!(out, "{:04X}  {}", row, hex.join(" "))?,
+                    }
+                    row = row_end;
+                }
+            }
+            "view" => self.view(arg(0), out)?,
+            "poke" => {
+                let address = self.parse_address(arg(0).ok_or("poke needs an address")?)?;
+                let hex = arg(1).ok_or("poke needs bytes to write")?;
+                let bytes = cheat::parse_bytes(hex)
+                    .ok_or_else(|| format!("`{}` isn't hex bytes, e.g. 05 or 1238", hex))?;
+                let start = address as usize;
+                self.chip_8
+                    .memory
+                    .get_mut(start..start + bytes.len())
+                    .ok_or("That's past the end of memory")?
+                    .copy_from_slice(&bytes);
+            }
+            "sprite" => {
+                let address = match arg(0) {
+                    Some(address) => self.parse_address(address)?,
+                    None => self.chip_8.i,
+                };
+                let len = parse_count(arg(1), 15)? as usize;
+                memory_view::show_sprite(&self.chip_8.memory, address, len, out)?;
+            }
+            "screen" => self.show_screen(out)?,
+            "keys" => {
+                self.held_keys = args
+                    .iter()
+                    .map(|key| match u8::from_str_radix(key, 16) {
+                        Ok(key) if key < 16 => Ok(key),
+                        _ => Err(format!("`{}` isn't a key from 0 to F", key)),
+                    })
+                    .collect::<Result<_, _>>()?;
+            }
+            "profile" => {
+                let report = match arg(0) {
+                    Some("json") => self.profile.profiler.report_json(),
+                    _ => self.profile.profiler.report_text(),
+                };
+                writeln!(out, "{}", report.trim_end())?;
+            }
+            "search" => self.search(arg(0), out)?,
+            "cheat" => self.cheat(&args, out)?,
+            "h" | "help" => writeln!(out, "{}", HELP)?,
+            "q" | "quit" => return Ok(false),
+            _ => writeln!(out, "Unknown command `{}`, try `help`", name)?,
+        }
+        Ok(true)
+    }
+
+    /// Run frames until a breakpoint or fault. Returns whether it stopped at one.
+    fn run_frames(&mut self, frames: u64, out: &mut impl Write) -> Result<bool, Err> {
+        for _ in 0..frames {
+            self.start_frame();
+            let stop = self.debugger.run_frame(
+                &mut self.chip_8,
+                &self.held_keys,
+                &mut (&mut self.profile, &mut self.observers),
+            );
+            match stop {
+                Stop::Breakpoint(address) => writeln!(out, "Breakpoint at {:04X}", address)?,
+                Stop::Fault(address) => {
+                    let fault = self.chip_8.fault().unwrap_or_default();
+                    writeln!(out, "Stopped at {:04X}: {}", address, fault)?
+                }
+                Stop::FrameEnd | Stop::Watchpoint(..) => continue,
+            }
+            self.show_location(out)?;
+            return Ok(true);
+        }
+        self.show_location(out)?;
+        Ok(false)
+    }
+
+    /// Apply the cheats that are on and remember memory for `view`, if a frame is
+    /// about to start
+    fn start_frame(&mut self) {
+        if self.debugger.cycle() == 0 {
+            for cheat in self.cheat_file.cheats(&self.rom) {
+                cheat.apply(&mut self.chip_8);
+            }
+            self.frame_start_memory.copy_from_slice(&self.chip_8.memory);
+        }
+    }
+
+    fn view(&mut self, arg: Option<&str>, out: &mut impl Write) -> Result<(), Err> {
+        let start = match arg {
+            None => None,
+            Some("off") => {
+                self.view = None;
+                return Ok(());
+            }
+            Some("i" | "I") => Some(None),
+            Some(address) => Some(Some(self.parse_address(address)?)),
+        };
+        let view = self.view.get_or_insert(MemoryView {
+            start: None,
+            rows: VIEW_ROWS,
+            colors: self.colors,
+        });
+        if let Some(start) = start {
+            view.start = start;
+        }
+        self.show_view(out)
+    }
+
+    fn show_view(&self, out: &mut impl Write) -> Result<(), Err> {
+        match &self.view {
+            Some(view) => view.show(
+                &self.chip_8.memory,
+                &self.frame_start_memory,
+                self.chip_8.i,
+                out,
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn search(&mut self, filter: Option<&str>, out: &mut impl Write) -> Result<(), Err> {
+        let memory = &self.chip_8.memory;
+        let search = match (filter, &mut self.search) {
+            (Some(filter), Some(search)) => {
+                search.filter(memory, filter.parse::<SearchFilter>()?);
+                search
+            }
+            (Some(filter), None) => {
+                let search = self.search.insert(Search::new(memory));
+                search.filter(memory, filter.parse::<SearchFilter>()?);
+                search
+            }
+            (None, _) => self.search.insert(Search::new(memory)),
+        };
+        let candidates = search.candidates();
+        writeln!(out, "{} candidates", candidates.len())?;
+        if candidates.len() <= MAX_LISTED_CANDIDATES {
+            for (address, value) in candidates {
+                writeln!(out, "{:04X}  {:02X}", address, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn cheat(&mut self, args: &[&str], out: &mut impl Write) -> Result<(), Err> {
+        let cheats = self.cheat_file.cheats_mut(&self.rom);
+        match args {
+            [] => {
+                for (index, cheat) in cheats.iter().enumerate() {
+                    let bytes: Vec<String> = cheat
+                        .bytes
+                        .iter()
+                        .map(|byte| format!("{:02X}", byte))
+                        .collect();
+                    let state = if cheat.enabled { "on" } else { "off" };
+                    writeln!(
+                        out,
+                        "{:>2}  {:<3}  {:04X}  {}  {}",
+                        index + 1,
+                        state,
+                        cheat.address,
+                        bytes.join(" "),
+                        cheat.name
+                    )?;
+                }
+                return Ok(());
+            }
+            ["add", address, bytes, name @ ..] => {
+                let address = self.parse_address(address)?;
+                let bytes = cheat::parse_bytes(bytes)
+                    .ok_or_else(|| format!("`{}` isn't hex bytes, e.g. 05 or 1238", bytes))?;
+                self.cheat_file.cheats_mut(&self.rom).push(Cheat {
+                    address,
+                    bytes,
+                    enabled: true,
+                    name: name.join(" "),
+                });
+            }
+            [command @ ("on" | "off" | "delete"), number] => {
+                let index = number
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&number| (1..=cheats.len()).contains(&number))
+                    .ok_or_else(|| format!("There's no cheat {}", number))?
+                    - 1;
+                match *command {
+                    "delete" => {
+                        cheats.remove(index);
+                    }
+                    command => cheats[index].enabled = command == "on",
+                }
+            }
+            _ => return Err(
+                "Try `cheat`, `cheat add <addr> <hex bytes> [name]` or `cheat on|off|delete <n>`"
+                    .into(),
+            ),
+        }
+        match &self.cheats_path {
+            Some(path) => cheats::save(path, &self.cheat_file),
+            None => Ok(()),
+        }
+    }
+
+    fn show_location(&self, out: &mut impl Write) -> Result<(), Err> {
+        write!(
+            out,
+            "Frame {}, instruction {}: ",
+            self.debugger.frames(),
+            self.debugger.cycle()
+        )?;
+        self.show_instruction(self.chip_8.pc, out)?;
+        let pc = self.chip_8.pc;
+        if let Some(location) = self.symbols.locate(pc) {
+            writeln!(out, "    in {}", describe(location))?;
+        }
+        if let Some(&line) = self.symbols.lines.get(&pc) {
+            let text = self.source.get(line - 1).map_or("", |text| text.trim());
+            writeln!(out, "    {:>4} | {}", line, text)?;
+        }
+        self.show_view(out)
+    }
+
+    /// Disassemble the instruction at `address`. Returns false past the end of memory.
+    fn show_instruction(&self, address: u16, out: &mut impl Write) -> Result<bool, Err> {
+        let Some(&[high, low]) = self
+            .chip_8
+            .memory
+            .get(address as usize..address as usize + 2)
+        else {
+            writeln!(out, "{:04X} is past the end of memory", address)?;
+            return Ok(false);
+        };
+        let opcode = u16::from_be_bytes([high, low]);
+        let instruction = Instruction::decode(opcode);
+        // Name the address a jump, call or I load refers to
+        let target = match instruction {
+            Instruction::Jp { nnn }
+            | Instruction::Call { nnn }
+            | Instruction::LdI { nnn }
+            | Instruction::JpV0 { nnn } => self.symbols.labels_at(nnn).next(),
+            _ => None,
+        };
+        let marker = if self.debugger.breakpoints.contains(&address) {
+            '*'
+        } else {
+            ' '
+        };
+        write!(
+            out,
+            "{}{:04X}  {:04X}  {}",
+            marker, address, opcode, instruction
+        )?;
+        match target {
+            Some(name) => writeln!(out, "  ; {}", name)?,
+            None => writeln!(out)?,
+        }
+        Ok(true)
+    }
+
+    /// A label or constant name, or a hex address
+    fn parse_address(&self, s: &str) -> Result<u16, Err> {
+        match self
+            .symbols
+            .labels
+            .get(s)
+            .or_else(|| self.symbols.constants.get(s))
+        {
+            Some(&address) => Ok(address),
+            None => parse_address(s),
+        }
+    }
+
+    fn show_registers(&self, out: &mut impl Write) -> Result<(), Err> {
+        let chip_8 = &self.chip_8;
+        for (row, registers) in chip_8.v.chunks(8).enumerate() {
+            let registers: Vec<String> = registers
+                .iter()
+                .enumerate()
+                .map(|(x, value)| format!("V{:X}={:02X}", row * 8 + x, value))
+                .collect();
+            writeln!(out, "{}", registers.join(" "))?;
+        }
+        writeln!(
+            out,
+            "PC={:04X} I={:04X} DT={:02X} ST={:02X}",
+            chip_8.pc, chip_8.i, chip_8.dt, chip_8.st
+        )?;
+        let stack: Vec<String> = chip_8
+            .stack
+            .iter()
+            .map(|address| format!("{:04X}", address))
+            .collect();
+        writeln!(out, "Stack: [{}]", stack.join(" "))?;
+        Ok(())
+    }
+
+    /// Print the display with half blocks, two rows of pixels to a line
+    fn show_screen(&self, out: &mut impl Write) -> Result<(), Err> {
+        let lit = |x, y| self.chip_8.display.contains(&(x, y));
+        for y in (0..globals::DISPLAY_HEIGHT).step_by(2) {
+            let line: String = (0..globals::DISPLAY_WIDTH)
+                .map(|x| match (lit(x, y), lit(x, y + 1)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                })
+                .collect();
+            writeln!(out, "|{}|", line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Read a symbol map file, e.g. from `chip_8_octo --symbols`
+pub fn read_symbol_map(path: &Path) -> Result<SymbolMap, Err> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("can't read symbol map {}: {}", path.display(), err))?;
+    SymbolMap::parse(&text)
+        .map_err(|err| format!("invalid symbol map {}: {}", path.display(), err).into())
+}
+
+/// The symbol map's source file. A relative path is looked up next to the symbol
+/// map if it isn't found from the working directory.
+pub fn find_source(symbols: &SymbolMap, map_path: &Path) -> Option<PathBuf> {
+    let source = Path::new(symbols.source.as_ref()?);
+    let beside = map_path.parent().unwrap_or(Path::new("")).join(source);
+    if source.exists() {
+        Some(source.to_path_buf())
+    } else {
+        Some(beside)
+    }
+}
+
+/// `label` or `label+offset`
+fn describe((name, offset): (&str, u16)) -> String {
+    match offset {
+        0 => name.to_string(),
+        offset => format!("{}+{}", name, offset),
+    }
+}
+
+fn parse_address(s: &str) -> Result<u16, Err> {
+    let hex = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    u16::from_str_radix(hex, 16).map_err(|_| format!("`{}` isn't a hex address", s).into())
+}
+
+fn parse_count(s: Option<&str>, default: u64) -> Result<u64, Err> {
+    match s {
+        Some(s) => s
+            .parse()
+            .map_err(|_| format!("`{}` isn't a count", s).into()),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chip_8_core::{profiler::ProfileFormat, Chip8};
+
+    use chip_8_octo::SymbolMap;
+
+    use super::Repl;
+    use crate::profile::ProfileReport;
+
+    fn run(repl: &mut Repl, command: &str) -> String {
+        let mut out = Vec::new();
+        assert!(repl.execute(command, &mut out).unwrap());
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn steps_and_breaks() {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0x60, 0x2A, // 200: LD V0, 0x2A
+            0x70, 0x01, // 202: ADD V0, 1
+            0x12, 0x02, // 204: JP 0x202
+        ]).unwrap();
+        let mut repl = Repl::new(
+            chip_8,
+            ProfileReport::new(None, ProfileFormat::Text),
+            Vec::new(),
+        );
+
+        assert_eq!(
+            run(&mut repl, "step"),
+            "Frame 1, instruction 1:  0202  7001  ADD V0, 0x01\n"
+        );
+        run(&mut repl, "break 204");
+        assert_eq!(
+            run(&mut repl, "continue"),
+            "Breakpoint at 0204\nFrame 1, instruction 2: *0204  1202  JP 0x202\n"
+        );
+        assert!(run(&mut repl, "regs").starts_with("V0=2B V1=00"));
+        assert!(run(&mut repl, "profile json").starts_with("{\"instructions\":2,"));
+        assert_eq!(run(&mut repl, "mem 0x200 4"), "0200  60 2A 70 01\n");
+        assert_eq!(
+            run(&mut repl, "mem FFE 18446744073709551615"),
+            "0FFE  00 00\n"
+        );
+        assert!(run(&mut repl, "disasm 200 2").ends_with(" 0202  7001  ADD V0, 0x01\n"));
+
+        let mut out = Vec::new();
+        assert!(repl.execute("break", &mut out).is_err());
+        assert!(!repl.execute("quit", &mut out).unwrap());
+    }
+
+    #[test]
+    fn uses_symbols() {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0x60, 0x2A, // 200: main: v0 := 42
+            0x70, 0x01, // 202: loop: v0 += 1
+            0x12, 0x02, // 204: jump loop
+            0xFF, 0x81, // 206: table
+        ]).unwrap();
+        let mut repl = Repl::new(
+            chip_8,
+            ProfileReport::new(None, ProfileFormat::Text),
+            Vec::new(),
+        );
+        repl.symbols =
+            SymbolMap::parse("label 0200 main\nlabel 0202 loop\nlabel 0206 table\nline 0202 3\n")
+                .unwrap();
+        repl.source = vec![
+            ": main".into(),
+            "  v0 := 42".into(),
+            "  loop v0 += 1".into(),
+        ];
+
+        assert_eq!(
+            run(&mut repl, "step"),
+            "Frame 1, instruction 1:  0202  7001  ADD V0, 0x01\n    in loop\n       3 | loop v0 += 1\n"
+        );
+        run(&mut repl, "break loop");
+        assert_eq!(run(&mut repl, "breakpoints"), "0202  loop\n");
+        assert_eq!(
+            run(&mut repl, "disasm loop 2"),
+            "loop:\n*0202  7001  ADD V0, 0x01\n 0204  1202  JP 0x202  ; loop\n"
+        );
+        assert_eq!(
+            run(&mut repl, "mem 203 5"),
+            format!(
+                "0203  {:<47}  loop+1\n0206  {:<47}  table\n",
+                "01 12 02", "FF 81"
+            )
+        );
+    }
+
+    #[test]
+    fn searches_and_cheats() {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0xA3, 0x00, // 200: LD I, 0x300
+            0xF0, 0x65, // 202: LD V0, [I]
+            0x70, 0xFF, // 204: ADD V0, 0xFF
+            0xA3, 0x00, // 206: LD I, 0x300
+            0xF0, 0x55, // 208: LD [I], V0
+            0x61, 0x00, // 20A: LD V1, 0x00
+            0x61, 0x00, // 20C: LD V1, 0x00
+            0x12, 0x00, // 20E: JP 0x200
+        ]).unwrap();
+        chip_8.memory[0x300] = 10;
+        let mut repl = Repl::new(
+            chip_8,
+            ProfileReport::new(None, ProfileFormat::Text),
+            Vec::new(),
+        );
+
+        assert_eq!(run(&mut repl, "search"), "4096 candidates\n");
+        run(&mut repl, "frame");
+        assert_eq!(
+            run(&mut repl, "search decreased"),
+            "1 candidates\n0300  08\n"
+        );
+
+        run(&mut repl, "cheat add 300 0A lives");
+        assert_eq!(run(&mut repl, "cheat"), " 1  on   0300  0A  lives\n");
+        run(&mut repl, "frame");
+        assert_eq!(run(&mut repl, "mem 300 1"), "0300  08\n");
+        run(&mut repl, "cheat off 1");
+        run(&mut repl, "frame");
+        assert_eq!(run(&mut repl, "mem 300 1"), "0300  06\n");
+
+        let mut out = Vec::new();
+        assert!(repl.execute("cheat delete 2", &mut out).is_err());
+        assert!(repl.execute("search sideways", &mut out).is_err());
+    }
+
+    #[test]
+    fn views_and_edits_memory() {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0x60, 0x2A, // 200: LD V0, 0x2A
+            0xA3, 0x01, // 202: LD I, 0x301
+            0xF0, 0x55, // 204: LD [I], V0
+            0x12, 0x06, // 206: JP 0x206
+        ]).unwrap();
+        let mut repl = Repl::new(
+            chip_8,
+            ProfileReport::new(None, ProfileFormat::Text),
+            Vec::new(),
+        );
+
+        let view = run(&mut repl, "view 300");
+        assert_eq!(view.lines().count(), 9);
+        assert_eq!(
+            view.lines().next(),
+            Some("               0  1  2  3  4  5  6  7  8  9  A  B  C  D  E  F")
+        );
+        let stop = run(&mut repl, "step 3");
+        let mut lines = stop.lines();
+        assert_eq!(
+            lines.next(),
+            Some("Frame 1, instruction 3:  0206  1206  JP 0x206")
+        );
+        assert_eq!(
+            lines.nth(1),
+            Some("0300  program  00*2A>00 00 00 00 00 00 00 00 00 00 00 00 00 00")
+        );
+
+        run(&mut repl, "poke 300 F090");
+        assert_eq!(
+            run(&mut repl, "sprite 300 3"),
+            "0300  F0  ████....\n0301  90  █..█....\n0302  00  ........\n"
+        );
+        assert_eq!(
+            run(&mut repl, "sprite FFF 18446744073709551615"),
+            "0FFF  00  ........\n"
+        );
+        assert_eq!(run(&mut repl, "sprite FFFF"), "");
+        assert_eq!(
+            run(&mut repl, "view 55").lines().nth(1),
+            Some("0050  font     F0 90 90 90 F0 20 60 20 20 70 F0 10 F0 80 F0 F0")
+        );
+        run(&mut repl, "view off");
+        assert_eq!(
+            run(&mut repl, "step"),
+            "Frame 1, instruction 4:  0206  1206  JP 0x206\n"
+        );
+
+        let mut out = Vec::new();
+        assert!(repl.execute("poke FFF 0102", &mut out).is_err());
+    }
+}
@@ -0,0 +1,528 @@
+//! A GDB remote serial protocol server, so GDB-compatible clients can debug a ROM.
+//!
+//! Registers are numbered V0-VF (0-15), I (16), PC (17), SP (18), DT (19) and ST (20).
+//! I and PC are two bytes, big endian like the rest of the CHIP-8, and the others
+//! are one byte. SP is the depth of the stack. Clients can fetch this layout as
+//! `target.xml`. `monitor keys 4 6` holds keys down while the ROM runs.
+//!
+//! Stepping or continuing into an instruction that would stop the emulator, like a
+//! RET with nothing on the stack, stops before it with SIGILL instead.
+
+use chip_8_core::{
+    debugger::{Stop, Watch},
+    globals::Err,
+    Chip8, Debugger, Observer,
+};
+use std::{
+    collections::BTreeSet,
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" regnum="0"/>
+    <reg name="v1" bitsize="8"/>
+    <reg name="v2" bitsize="8"/>
+    <reg name="v3" bitsize="8"/>
+    <reg name="v4" bitsize="8"/>
+    <reg name="v5" bitsize="8"/>
+    <reg name="v6" bitsize="8"/>
+    <reg name="v7" bitsize="8"/>
+    <reg name="v8" bitsize="8"/>
+    <reg name="v9" bitsize="8"/>
+    <reg name="va" bitsize="8"/>
+    <reg name="vb" bitsize="8"/>
+    <reg name="vc" bitsize="8"/>
+    <reg name="vd" bitsize="8"/>
+    <reg name="ve" bitsize="8"/>
+    <reg name="vf" bitsize="8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8"/>
+    <reg name="dt" bitsize="8"/>
+    <reg name="st" bitsize="8"/>
+  </feature>
+</target>
+"#;
+
+// Register numbers past the V registers
+const I: usize = 16;
+const PC: usize = 17;
+const SP: usize = 18;
+const DT: usize = 19;
+const ST: usize = 20;
+const REGISTERS: usize = 21;
+
+// The deepest stack a client can set through SP
+const MAX_STACK: usize = 16;
+
+/// Serves one GDB client at a time, running the Chip8 only when it asks
+pub struct GdbServer {
+    chip_8: Chip8,
+    debugger: Debugger,
+    held_keys: BTreeSet<u8>,
+    observers: Vec<Box<dyn Observer>>,
+}
+
+impl GdbServer {
+    pub fn new(chip_8: Chip8, observers: Vec<Box<dyn Observer>>) -> Self {
+        GdbServer {
+            chip_8,
+            debugger: Debugger::new(),
+            held_keys: BTreeSet::new(),
+            observers,
+        }
+    }
+
+    /// Wait for a client on a local port, then debug until it kills or detaches
+    pub fn serve(mut self, port: u16) -> Result<(), Err> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        println!("Waiting for GDB on {}", listener.local_addr()?);
+        let (stream, address) = listener.accept()?;
+        println!("GDB connected from {}", address);
+        self.session(stream)?;
+        self.observers.finish()
+    }
+
+    fn session(&mut self, mut stream: TcpStream) -> Result<(), Err> {
+        stream.set_nodelay(true)?;
+        while let Some(packet) = read_packet(&mut stream)? {
+            stream.write_all(b"+")?;
+            let reply = match packet.as_str() {
+                "k" => return Ok(()),
+                "D" => {
+                    send_packet(&mut stream, "OK")?;
+                    return Ok(());
+                }
+                "s" if self.chip_8.fault().is_some() => "S04".to_string(),
+                "s" => {
+                    self.step();
+                    "S05".to_string()
+                }
+                "c" => self.resume(&mut stream)?,
+                packet => self.handle(packet),
+            };
+            send_packet(&mut stream, &reply)?;
+        }
+        Ok(())
+    }
+
+    /// Reply to a packet that doesn't run the Chip8. An empty reply means unsupported.
+    fn handle(&mut self, packet: &str) -> String {
+        let (command, args) = packet.split_at(1.min(packet.len()));
+        let reply = match command {
+            "?" => Some("S05".to_string()),
+            "g" => Some(
+                (0..REGISTERS)
+                    .map(|register| self.read_register(register))
+                    .collect(),
+            ),
+            "G" => self.write_registers(args),
+            "p" => usize::from_str_radix(args, 16)
+                .ok()
+                .filter(|&register| register < REGISTERS)
+                .map(|register| self.read_register(register)),
+            "P" => args.split_once('=').and_then(|(register, value)| {
+                let register = usize::from_str_radix(register, 16).ok()?;
+                self.write_register(register, &decode_hex(value)?)
+            }),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "Z" | "z" => self.set_point(command == "Z", args),
+            "H" => Some("OK".to_string()),
+            _ => return self.query(packet),
+        };
+        reply.unwrap_or_else(|| "E01".to_string())
+    }
+
+    /// Reply to the `q` packets clients send while connecting
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;qXfer:features:read+".to_string()
+        } else if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            read_chunk(TARGET_XML, range).unwrap_or_else(|| "E01".to_string())
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if let Some(command) = packet.strip_prefix("qRcmd,") {
+            match decode_hex(command).and_then(|command| String::from_utf8(command).ok()) {
+                Some(command) => self.monitor(&command),
+                None => "E01".to_string(),
+            }
+        } else {
+            String::new()
+        }
+    }
+
+    /// Run a `monitor` command, replying with hex encoded output
+    fn monitor(&mut self, command: &str) -> String {
+        let mut words = command.split_whitespace();
+        if words.next() != Some("keys") {
+            return encode_hex(b"Unknown monitor command, try `monitor keys 4 6`\n");
+        }
+        let keys: Option<BTreeSet<u8>> = words
+            .map(|key| u8::from_str_radix(key, 16).ok().filter(|&key| key < 16))
+            .collect();
+        match keys {
+            Some(keys) => {
+                self.held_keys = keys;
+                "OK".to_string()
+            }
+            None => encode_hex(b"Keys are hex digits from 0 to F\n"),
+        }
+    }
+
+    fn step(&mut self) {
+        self.debugger
+            .step(&mut self.chip_8, &self.held_keys, &mut self.observers);
+    }
+
+    /// Run until a breakpoint, a watchpoint or the client interrupts
+    fn resume(&mut self, stream: &mut TcpStream) -> Result<String, Err> {
+        loop {
+            let stop =
+                self.debugger
+                    .run_frame(&mut self.chip_8, &self.held_keys, &mut self.observers);
+            match stop {
+                Stop::FrameEnd if interrupted(stream)? => return Ok("S02".to_string()),
+                Stop::FrameEnd => {}
+                Stop::Breakpoint(_) => return Ok("S05".to_string()),
+                Stop::Fault(_) => return Ok("S04".to_string()),
+                Stop::Watchpoint(address, watch) => {
+                    let kind = match watch {
+                        Watch::Read => "rwatch",
+                        Watch::Write => "watch",
+                        Watch::Access => "awatch",
+                    };
+                    return Ok(format!("T05{}:{:x};", kind, address));
+                }
+            }
+        }
+    }
+
+    fn read_register(&self, register: usize) -> String {
+        let chip_8 = &self.chip_8;
+        match register {
+            I => format!("{:04x}", chip_8.i),
+            PC => format!("{:04x}", chip_8.pc),
+            SP => format!("{:02x}", chip_8.stack.len()),
+            DT => format!("{:02x}", chip_8.dt),
+            ST => format!("{:02x}", chip_8.st),
+            x => format!("{:02x}", chip_8.v[x]),
+        }
+    }
+
+    fn write_registers(&mut self, hex: &str) -> Option<String> {
+        let bytes = decode_hex(hex)?;
+        let mut rest = bytes.as_slice();
+        for register in 0..REGISTERS {
+            let size = if register == I || register == PC {
+                2
+            } else {
+                1
+            };
+            if rest.len() < size {
+                return None;
+            }
+            let (value, tail) = rest.split_at(size);
+            self.write_register(register, value)?;
+            rest = tail;
+        }
+        Some("OK".to_string())
+    }
+
+    fn write_register(&mut self, register: usize, value: &[u8]) -> Option<String> {
+        let chip_8 = &mut self.chip_8;
+        // I and the PC have to leave room for an instruction or a couple of bytes
+        let last_address = chip_8.memory.len() - 2;
+        let address = |high, low| {
+            Some(u16::from_be_bytes([high, low]))
+                .filter(|&address| address as usize <= last_address)
+        };
+        match (register, value) {
+            (I, &[high, low]) => chip_8.i = address(high, low)?,
+            (PC, &[high, low]) => chip_8.pc = address(high, low)?,
+            (SP, &[depth]) if (depth as usize) <= MAX_STACK => {
+                chip_8.stack.resize(depth as usize, 0)
+            }
+            (DT, &[value]) => chip_8.dt = value,
+            (ST, &[value]) => chip_8.st = value,
+            (x, &[value]) if x < 16 => chip_8.v[x] = value,
+            _ => return None,
+        }
+        Some("OK".to_string())
+    }
+
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (address, len) = parse_range(args)?;
+        let bytes = self.chip_8.memory.get(address..address.checked_add(len)?)?;
+        Some(encode_hex(bytes))
+    }
+
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (address, len) = parse_range(range)?;
+        let data = decode_hex(data).filter(|data| data.len() == len)?;
+        self.chip_8
+            .memory
+            .get_mut(address..address.checked_add(len)?)?
+            .copy_from_slice(&data);
+        Some("OK".to_string())
+    }
+
+    /// `Z`/`z` packets: 0 and 1 are breakpoints, 2 to 4 write, read and access watchpoints
+    fn set_point(&mut self, insert: bool, args: &str) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let address = u16::from_str_radix(fields.next()?, 16).ok()?;
+        let watch = match kind {
+            "0" | "1" => {
+                if insert {
+                    self.debugger.breakpoints.insert(address);
+                } else {
+                    self.debugger.breakpoints.remove(&address);
+                }
+                return Some("OK".to_string());
+            }
+            "2" => Watch::Write,
+            "3" => Watch::Read,
+            "4" => Watch::Access,
+            _ => return Some(String::new()),
+        };
+        // A watchpoint covers `len` bytes from its address
+        let len = u16::from_str_radix(fields.next()?, 16).ok()?;
+        for address in address..address.saturating_add(len.max(1)) {
+            if insert {
+                self.debugger.watchpoints.insert(address, watch);
+            } else {
+                self.debugger.watchpoints.remove(&address);
+            }
+        }
+        Some("OK".to_string())
+    }
+}
+
+/// Read the next `$data#checksum` packet, skipping acks. Returns None once the
+/// client disconnects.
+fn read_packet(stream: &mut impl Read) -> Result<Option<String>, Err> {
+    let mut byte = [0];
+    loop {
+        if stream.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'$' {
+            break;
+        }
+    }
+    let mut data = Vec::new();
+    loop {
+        if stream.read(&mut byte)? == 0 {
+            return Ok(None);
+        }
+        if byte[0] == b'#' {
+            break;
+        }
+        data.push(byte[0]);
+    }
+    // Loopback is reliable enough not to bother checking the checksum
+    let mut checksum = [0; 2];
+    stream.read_exact(&mut checksum)?;
+    Ok(Some(String::from_utf8(data)?))
+}
+
+fn send_packet(stream: &mut impl Write, data: &str) -> io::Result<()> {
+    let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+    write!(stream, "${}#{:02x}", data, checksum)?;
+    stream.flush()
+}
+
+/// Whether the client sent a ^C to interrupt the running ROM
+fn interrupted(stream: &mut TcpStream) -> io::Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = match stream.read(&mut byte) {
+        Ok(1) => Ok(byte[0] == 0x03),
+        // A disconnected client can't ask to stop, so stop for it
+        Ok(_) => Ok(true),
+        Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(err) => Err(err),
+    };
+    stream.set_nonblocking(false)?;
+    result
+}
+
+/// The part of `text` an `offset,length` request asks for, prefixed with `m` if
+/// there's more to come or `l` if it's the last part
+fn read_chunk(text: &str, range: &str) -> Option<String> {
+    let (offset, len) = parse_range(range)?;
+    let start = offset.min(text.len());
+    let end = offset.saturating_add(len).min(text.len());
+    let marker = if end < text.len() { 'm' } else { 'l' };
+    Some(format!("{}{}", marker, &text[start..end]))
+}
+
+/// A hex `address,length` pair
+fn parse_range(s: &str) -> Option<(usize, usize)> {
+    let (address, len) = s.split_once(',')?;
+    Some((
+        usize::from_str_radix(address, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use chip_8_core::Chip8;
+    use std::{
+        io::Write,
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::{read_packet, send_packet, GdbServer};
+
+    /// Send each packet and collect the replies, like a minimal GDB
+    fn client(port: u16, packets: &'static [&'static str]) -> Vec<String> {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let mut replies = Vec::new();
+        for packet in packets {
+            send_packet(&mut stream, packet).unwrap();
+            if *packet != "k" {
+                replies.push(read_packet(&mut stream).unwrap().unwrap());
+                stream.write_all(b"+").unwrap();
+            }
+        }
+        replies
+    }
+
+    #[test]
+    fn serves_a_client() {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0x60, 0x2A, // 200: LD V0, 0x2A
+            0xA3, 0x00, // 202: LD I, 0x300
+            0xF0, 0x55, // 204: LD [I], V0
+            0x12, 0x06, // 206: JP 0x206
+        ]).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            client(
+                port,
+                &[
+                    "qSupported:swbreak+",
+                    "s",
+                    "p0",
+                    "p11",
+                    "Z2,300,1",
+                    "c",
+                    "m300,2",
+                    "M300,2:beef",
+                    "m2ff,3",
+                    "P10=0123",
+                    "g",
+                    "Z0,206,2",
+                    "c",
+                    "m1000,1",
+                    "k",
+                ],
+            )
+        });
+        let mut server = GdbServer::new(chip_8, Vec::new());
+        let (stream, _) = listener.accept().unwrap();
+        server.session(stream).unwrap();
+
+        assert_eq!(
+            client.join().unwrap(),
+            [
+                "PacketSize=1000;qXfer:features:read+",
+                "S05",
+                "2a",
+                "0202",
+                "OK",
+                "T05watch:300;",
+                "2a00",
+                "OK",
+                "00beef",
+                "OK",
+                "2a00000000000000000000000000000001230206000000",
+                "OK",
+                "S05",
+                "E01",
+            ]
+        );
+    }
+
+    #[test]
+    fn refuses_to_crash() {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0x60, 0x2A, // 200: LD V0, 0x2A
+            0x00, 0xEE, // 202: RET
+        ]).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            client(
+                port,
+                &["P11=ffff", "P10=fffe", "s", "p11", "s", "c", "p11", "k"],
+            )
+        });
+        let mut server = GdbServer::new(chip_8, Vec::new());
+        let (stream, _) = listener.accept().unwrap();
+        server.session(stream).unwrap();
+
+        assert_eq!(
+            client.join().unwrap(),
+            ["E01", "E01", "S05", "0202", "S04", "S04", "0202"]
+        );
+    }
+
+    #[test]
+    fn refuses_to_access_past_memory() {
+        let mut chip_8 = Chip8::new();
+        #[rustfmt::skip]
+        chip_8.load_rom(&[
+            0xF2, 0x65, // 200: LD V2, [I]
+            0xF0, 0x33, // 202: LD B, V0
+        ]).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = thread::spawn(move || {
+            client(
+                port,
+                &[
+                    "P10=0ffe", "s", "c", "p11", "P10=0ffd", "s", "p11", "s", "P10=0300", "s",
+                    "p11", "k",
+                ],
+            )
+        });
+        let mut server = GdbServer::new(chip_8, Vec::new());
+        let (stream, _) = listener.accept().unwrap();
+        server.session(stream).unwrap();
+
+        assert_eq!(
+            client.join().unwrap(),
+            ["OK", "S04", "S04", "0200", "OK", "S05", "0202", "S04", "OK", "S05", "0204"]
+        );
+    }
+}
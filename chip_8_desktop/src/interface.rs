@@ -1,4 +1,168 @@
 mod terminal;
-pub use terminal::Terminal;
+pub use terminal::{InputBackend, RenderMode, Terminal};
 mod graphical;
 pub use graphical::Graphical;
+mod headless;
+pub use headless::Headless;
+
+use chip_8_core::{
+    cheat::Cheat,
+    globals::{self, Err},
+    recording::{Recorder, VideoFormat},
+    screenshot::{ImageFormat, Screenshot},
+    Chip8, Palette, Persistence, Quirks,
+};
+use log::info;
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::PathBuf,
+};
+
+/// Load `rom` into CPU memory, and set the CPU's quirks for it
+pub fn load_rom(chip_8: &mut Chip8, rom: &[u8], quirks: Quirks) -> Result<(), Err> {
+    chip_8.load_rom(rom)?;
+    chip_8.quirks = quirks;
+    chip_8.memory[0x1FF] = 5;
+    chip_8.memory[0x1FE] = 2;
+    Ok(())
+}
+
+/// Settings shared by the front-ends
+pub struct Settings {
+    pub rom: PathBuf,
+    pub palette: Palette,
+    pub persistence: Persistence,
+    pub quirks: Quirks,
+    pub screenshot_format: ImageFormat,
+    /// Image pixels per chip8 pixel
+    pub screenshot_scale: u32,
+    /// Start recording as soon as the ROM starts
+    pub record: bool,
+    pub record_format: VideoFormat,
+    /// Video pixels per chip8 pixel
+    pub record_scale: u32,
+    /// The ROM's cheats from the cheat file
+    pub cheats: Vec<Cheat>,
+    /// Whether the cheats that are on get applied, toggled by a hotkey
+    pub cheats_enabled: bool,
+}
+
+impl Settings {
+    /// Load the ROM into CPU memory, and set the CPU's quirks for it
+    pub fn load_rom(&self, chip_8: &mut Chip8) -> Result<(), Err> {
+        load_rom(chip_8, &self.read_rom()?, self.quirks)?;
+        self.apply_cheats(chip_8);
+
+        Ok(())
+    }
+
+    /// Write the cheats into memory, before the ROM loads and after every frame
+    pub fn apply_cheats(&self, chip_8: &mut Chip8) {
+        if self.cheats_enabled {
+            for cheat in &self.cheats {
+                cheat.apply(chip_8);
+            }
+        }
+    }
+
+    fn toggle_cheats(&mut self) {
+        self.cheats_enabled = !self.cheats_enabled;
+        info!("Cheats {}", if self.cheats_enabled { "on" } else { "off" });
+    }
+
+    pub fn read_rom(&self) -> Result<Vec<u8>, Err> {
+        fs::read(&self.rom)
+            .map_err(|err| format!("can't read ROM file {}: {}", self.rom.display(), err).into())
+    }
+
+    /// A path in the working directory named after the ROM and the time,
+    /// e.g. `brick-20230601-142501.123.png`
+    fn output_path(&self, extension: &str) -> PathBuf {
+        let rom_name = self
+            .rom
+            .file_stem()
+            .map_or("chip_8".into(), |stem| stem.to_string_lossy());
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S%.3f");
+        PathBuf::from(format!("{}-{}.{}", rom_name, timestamp, extension))
+    }
+
+    /// Save the display to a file in the working directory
+    fn screenshot(&self, chip_8: &Chip8) -> Result<PathBuf, Err> {
+        let path = self.output_path(self.screenshot_format.extension());
+        Screenshot {
+            display: &chip_8.display,
+            width: globals::DISPLAY_WIDTH,
+            height: globals::DISPLAY_HEIGHT,
+            palette: self.palette,
+            scale: self.screenshot_scale,
+        }
+        .save(&path, self.screenshot_format)?;
+        info!("Saved screenshot {}", path.display());
+        Ok(path)
+    }
+}
+
+/// Screenshots and recordings requested by hotkeys, shared by the front-ends.
+/// Hotkeys are read before the frame runs, so requests wait for `frame`.
+#[derive(Default)]
+struct Capture {
+    screenshot_requested: bool,
+    recording_toggled: bool,
+    recorder: Option<Recorder<BufWriter<File>>>,
+}
+
+impl Capture {
+    fn new(settings: &Settings) -> Self {
+        Capture {
+            recording_toggled: settings.record,
+            ..Capture::default()
+        }
+    }
+
+    fn request_screenshot(&mut self) {
+        self.screenshot_requested = true;
+    }
+
+    fn toggle_recording(&mut self) {
+        self.recording_toggled = !self.recording_toggled;
+    }
+
+    /// Handle requests, and record the frame that just ran
+    fn frame(&mut self, settings: &Settings, chip_8: &Chip8) -> Result<(), Err> {
+        if self.screenshot_requested {
+            self.screenshot_requested = false;
+            settings.screenshot(chip_8)?;
+        }
+        if self.recording_toggled {
+            self.recording_toggled = false;
+            match self.recorder {
+                Some(_) => self.stop_recording()?,
+                None => {
+                    let path = settings.output_path(settings.record_format.extension());
+                    self.recorder = Some(Recorder::create(
+                        &path,
+                        settings.record_format,
+                        globals::DISPLAY_WIDTH,
+                        globals::DISPLAY_HEIGHT,
+                        settings.palette,
+                        settings.record_scale,
+                    )?);
+                    info!("Recording to {}", path.display());
+                }
+            }
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.record_frame(&chip_8.display)?;
+        }
+        Ok(())
+    }
+
+    fn stop_recording(&mut self) -> Result<(), Err> {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish()?;
+            info!("Stopped recording");
+        }
+        Ok(())
+    }
+}
@@ -1,25 +1,33 @@
+mod screen;
+
+use super::{Capture, Settings};
 use chip_8_core::{
+    cheat::Cheat,
     chip_8,
     globals::{self, Err, Keys},
+    palette::Rgb,
+    persistence::PersistenceFilter,
     Chip8, Interface,
 };
 use lazy_static::lazy_static;
+use screen::Screen;
 use sdl2::{
     event::Event,
-    keyboard::Keycode,
+    hint,
+    keyboard::{Keycode, Mod},
     pixels::Color,
-    rect::Rect,
-    render::{Canvas, Texture, TextureCreator},
-    video::{Window, WindowContext},
+    render::Canvas,
+    video::{FullscreenType, Window},
     Sdl,
 };
 use std::{
-    collections::{HashMap, HashSet},
-    fs, thread,
+    collections::{BTreeSet, HashMap},
+    thread,
     time::{Duration, Instant},
 };
 
-const SQUARE_SIZE: i32 = 16;
+// Initial window size, in window pixels per chip8 pixel
+const WINDOW_SCALE: i32 = 16;
 
 lazy_static! {
     static ref KEY_CODE_TO_CHIP_8_KEY: HashMap<Keycode, u8> = HashMap::from([
@@ -45,12 +53,17 @@ lazy_static! {
 pub struct Graphical {
     sdl_context: Sdl,
     canvas: Canvas<Window>,
+    // Created on the first draw, and recreated if the display size changes
+    screen: Option<Screen>,
+    filter: PersistenceFilter,
+    settings: Settings,
+    capture: Capture,
 
-    held_keys: HashSet<u8>,
+    held_keys: BTreeSet<u8>,
 }
 
 impl Graphical {
-    pub fn new() -> Result<Self, Err> {
+    pub fn new(settings: Settings) -> Result<Self, Err> {
         // Initialize SDL2
         let sdl_context = sdl2::init()?;
         let video_subsystem = sdl_context.video()?;
@@ -58,82 +71,99 @@ impl Graphical {
         let window = video_subsystem
             .window(
                 "chip_8",
-                (globals::DISPLAY_WIDTH * SQUARE_SIZE) as u32,
-                (globals::DISPLAY_HEIGHT * SQUARE_SIZE) as u32,
+                (globals::DISPLAY_WIDTH * WINDOW_SCALE) as u32,
+                (globals::DISPLAY_HEIGHT * WINDOW_SCALE) as u32,
             )
             .position_centered()
+            .resizable()
             .build()?;
 
-        let canvas = window
-            .into_canvas()
-            .target_texture()
-            .present_vsync()
-            .build()?;
+        let canvas = window.into_canvas().present_vsync().build()?;
 
+        // Scale the screen texture up with nearest-neighbour filtering, to keep pixels sharp
+        hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+
+        let capture = Capture::new(&settings);
         let graphical = Graphical {
             sdl_context,
             canvas,
+            screen: None,
+            filter: PersistenceFilter::new(
+                settings.persistence,
+                globals::DISPLAY_WIDTH,
+                globals::DISPLAY_HEIGHT,
+            ),
+            settings,
+            capture,
 
-            held_keys: HashSet::new(),
+            held_keys: BTreeSet::new(),
         };
 
         Ok(graphical)
     }
+
+    /// Draw a display of any resolution.
+    /// It's scaled by the largest whole number that fits the window, and letterboxed.
+    fn draw_display(
+        &mut self,
+        display: &chip_8::Display,
+        width: u32,
+        height: u32,
+    ) -> Result<(), Err> {
+        if self.screen.as_ref().map(Screen::size) != Some((width, height)) {
+            if let Some(screen) = self.screen.take() {
+                screen.destroy();
+            }
+            self.screen = Some(Screen::new(&mut self.canvas, width, height)?);
+            self.canvas.set_logical_size(width, height)?;
+            self.canvas.set_integer_scale(true)?;
+        }
+        if self.filter.size() != (width as i32, height as i32) {
+            self.filter =
+                PersistenceFilter::new(self.filter.persistence(), width as i32, height as i32);
+        }
+        self.filter.update(display);
+        let screen = self.screen.as_mut().unwrap();
+        screen.update(&self.filter, &self.settings.palette)?;
+
+        // The letterbox bars match the background
+        self.canvas
+            .set_draw_color(sdl_color(self.settings.palette.off()));
+        self.canvas.clear();
+        screen.copy_to(&mut self.canvas)?;
+        self.canvas.present();
+        Ok(())
+    }
+
+    fn toggle_fullscreen(&mut self) -> Result<(), Err> {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        window.set_fullscreen(fullscreen)?;
+        Ok(())
+    }
+}
+
+fn sdl_color(rgb: Rgb) -> Color {
+    Color::RGB(rgb.r, rgb.g, rgb.b)
 }
 
 impl Interface for Graphical {
     fn run(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
-        // Make a little texture for our white square
-        let texture_creator = self.canvas.texture_creator();
-        let white_square_texture = dummy_texture(&mut self.canvas, &texture_creator)?;
-
-        // Game loop
-        let mut event_pump = self.sdl_context.event_pump()?;
-        let mut held_keys: HashSet<u8> = HashSet::new();
+        self.setup()?;
 
         let ns_per_frame: u64 = hertz::fps_to_ns_per_frame(globals::FRAMES_PER_SECOND as usize);
         let mut last_frame = Instant::now();
-        'running: loop {
-            // Get inputs
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => break 'running,
-                    Event::KeyDown {
-                        keycode: Some(keycode),
-                        repeat: false,
-                        ..
-                    } => {
-                        // Add chip_8-relevant keys to held_keys
-                        if let Some(&chip_8_key) = KEY_CODE_TO_CHIP_8_KEY.get(&keycode) {
-                            held_keys.insert(chip_8_key);
-                        }
-                    }
-                    Event::KeyUp {
-                        keycode: Some(keycode),
-                        repeat: false,
-                        ..
-                    } => {
-                        if let Some(&chip_8_key) = KEY_CODE_TO_CHIP_8_KEY.get(&keycode) {
-                            held_keys.remove(&chip_8_key);
-                        }
-                    }
-                    _ => {}
-                }
-            }
+        loop {
+            let Keys::Keys(held_keys) = self.read_keys()? else {
+                break;
+            };
 
-            // Decrement counters
-            chip_8.decrement_counters();
-
-            // Run n cycles
-            for _ in 0..globals::INSTRUCTIONS_PER_FRAME {
-                chip_8.run_cycle(&held_keys);
-            }
+            chip_8.run_frame(&held_keys);
 
-            draw(&mut self.canvas, &white_square_texture, &chip_8.display)?;
+            self.draw(chip_8)?;
 
             let time_remaining =
                 Duration::from_nanos(ns_per_frame).saturating_sub(last_frame.elapsed());
@@ -142,25 +172,23 @@ impl Interface for Graphical {
 
             last_frame = Instant::now();
         }
-        Ok(())
+        self.cleanup()
     }
 
     fn setup(&mut self) -> Result<(), Err> {
-        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas
+            .set_draw_color(sdl_color(self.settings.palette.off()));
         self.canvas.clear();
         self.canvas.present();
         Ok(())
     }
 
     fn load_rom(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
-        // Load ROM into CPU memory
-        let rom =
-            fs::read("roms/brick.ch8").expect("should be able to read ROM file at roms/brick.ch8");
-        chip_8.memory[0x200..0x200 + rom.len()].clone_from_slice(&rom[..]);
-        chip_8.memory[0x1FF] = 5;
-        chip_8.memory[0x1FE] = 2;
+        self.settings.load_rom(chip_8)
+    }
 
-        Ok(())
+    fn set_cheats(&mut self, cheats: Vec<Cheat>) {
+        self.settings.cheats = cheats;
     }
 
     fn read_keys(&mut self) -> Result<Keys, Err> {
@@ -174,6 +202,41 @@ impl Interface for Graphical {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => return Ok(Keys::Break),
+                // F11 or Alt+Enter toggles fullscreen
+                Event::KeyDown {
+                    keycode: Some(Keycode::F11),
+                    repeat: false,
+                    ..
+                } => self.toggle_fullscreen()?,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => self.toggle_fullscreen()?,
+                // F2 cycles through the built-in palettes
+                Event::KeyDown {
+                    keycode: Some(Keycode::F2),
+                    repeat: false,
+                    ..
+                } => self.settings.palette = self.settings.palette.next(),
+                // F5 turns cheats on or off
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => self.settings.toggle_cheats(),
+                // F12 saves a screenshot, and F9 starts or stops recording
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } => self.capture.request_screenshot(),
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => self.capture.toggle_recording(),
                 Event::KeyDown {
                     keycode: Some(keycode),
                     repeat: false,
@@ -200,55 +263,16 @@ impl Interface for Graphical {
     }
 
     fn draw(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
-        // Make a little texture for our white square
-        let texture_creator = self.canvas.texture_creator();
-        let white_square_texture = dummy_texture(&mut self.canvas, &texture_creator)?;
-
-        // Draw with that texture
-        draw(&mut self.canvas, &white_square_texture, &chip_8.display)
+        self.settings.apply_cheats(chip_8);
+        self.capture.frame(&self.settings, chip_8)?;
+        self.draw_display(
+            &chip_8.display,
+            globals::DISPLAY_WIDTH as u32,
+            globals::DISPLAY_HEIGHT as u32,
+        )
     }
 
     fn cleanup(&mut self) -> Result<(), Err> {
-        Ok(())
-    }
-}
-
-fn dummy_texture<'a>(
-    canvas: &mut Canvas<Window>,
-    texture_creator: &'a TextureCreator<WindowContext>,
-) -> Result<Texture<'a>, Err> {
-    let mut white_square =
-        texture_creator.create_texture_target(None, SQUARE_SIZE as u32, SQUARE_SIZE as u32)?;
-
-    canvas.with_texture_canvas(&mut white_square, |texture_canvas| {
-        texture_canvas.set_draw_color(Color::RGB(255, 255, 255));
-        texture_canvas.clear();
-    })?;
-
-    Ok(white_square)
-}
-
-fn draw(
-    canvas: &mut Canvas<Window>,
-    square_texture: &Texture,
-    display: &chip_8::Display,
-) -> Result<(), Err> {
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-
-    for (x, y) in display.iter() {
-        canvas.copy(
-            square_texture,
-            None,
-            Rect::new(
-                x * SQUARE_SIZE as i32,
-                y * SQUARE_SIZE as i32,
-                SQUARE_SIZE as u32,
-                SQUARE_SIZE as u32,
-            ),
-        )?;
+        self.capture.stop_recording()
     }
-
-    canvas.present();
-    Ok(())
 }
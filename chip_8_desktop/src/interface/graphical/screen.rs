@@ -0,0 +1,130 @@
+use chip_8_core::{
+    globals::Err,
+    palette::Rgb,
+    persistence::{PersistenceFilter, FULL},
+    Palette,
+};
+use sdl2::{
+    pixels::PixelFormatEnum,
+    render::{Canvas, Texture},
+    video::Window,
+};
+
+const BYTES_PER_PIXEL: usize = 3;
+
+/// The chip8 display as a streaming texture, one texel per pixel.
+/// The whole display is uploaded every frame and copied to the canvas in one go.
+pub struct Screen {
+    texture: Texture,
+    width: u32,
+    height: u32,
+    // RGB24 pixels, reused between frames
+    pixels: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new(canvas: &mut Canvas<Window>, width: u32, height: u32) -> Result<Screen, Err> {
+        let texture = canvas.create_texture_streaming(PixelFormatEnum::RGB24, width, height)?;
+        Ok(Screen {
+            texture,
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * BYTES_PER_PIXEL],
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Upload the filtered display to the texture
+    pub fn update(&mut self, filter: &PersistenceFilter, palette: &Palette) -> Result<(), Err> {
+        fill_pixels(filter, self.width, palette, &mut self.pixels);
+        self.texture
+            .update(None, &self.pixels, self.width as usize * BYTES_PER_PIXEL)?;
+        Ok(())
+    }
+
+    /// Copy the texture to the canvas's logical area
+    pub fn copy_to(&self, canvas: &mut Canvas<Window>) -> Result<(), Err> {
+        canvas.copy(&self.texture, None, None)?;
+        Ok(())
+    }
+
+    /// Free the texture. Textures aren't freed on drop, only with their canvas.
+    pub fn destroy(self) {
+        // Safety: the canvas that created the texture is still alive, since
+        // screens are only replaced while the Graphical interface holds it
+        unsafe { self.texture.destroy() }
+    }
+}
+
+/// Fill an RGB24 buffer from the filtered display.
+/// Fading pixels are blended between the background and lit colors.
+fn fill_pixels(filter: &PersistenceFilter, width: u32, palette: &Palette, pixels: &mut [u8]) {
+    let (off, on) = (palette.off(), palette.on());
+
+    for (index, pixel) in pixels.chunks_exact_mut(BYTES_PER_PIXEL).enumerate() {
+        let x = (index % width as usize) as i32;
+        let y = (index / width as usize) as i32;
+        let color = blend(off, on, filter.brightness(x, y));
+        pixel.copy_from_slice(&[color.r, color.g, color.b]);
+    }
+}
+
+fn blend(off: Rgb, on: Rgb, brightness: u8) -> Rgb {
+    let channel = |off: u8, on: u8| {
+        let (off, on, brightness) = (off as u32, on as u32, brightness as u32);
+        ((off * (FULL as u32 - brightness) + on * brightness) / FULL as u32) as u8
+    };
+    Rgb::new(
+        channel(off.r, on.r),
+        channel(off.g, on.g),
+        channel(off.b, on.b),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use chip_8_core::{persistence::PersistenceFilter, Palette, Persistence};
+
+    use super::fill_pixels;
+
+    #[test]
+    fn fills_rgb_pixels() {
+        let display = BTreeSet::from([(1, 0), (0, 1)]);
+        let mut filter = PersistenceFilter::new(Persistence::Off, 2, 2);
+        filter.update(&display);
+        let mut pixels = vec![0xAA; 2 * 2 * 3];
+
+        fill_pixels(&filter, 2, &Palette::CLASSIC, &mut pixels);
+
+        #[rustfmt::skip]
+        assert_eq!(pixels, vec![
+            0, 0, 0,        255, 255, 255,
+            255, 255, 255,  0, 0, 0,
+        ]);
+
+        fill_pixels(&filter, 2, &Palette::OCTO, &mut pixels);
+
+        #[rustfmt::skip]
+        assert_eq!(pixels, vec![
+            0x99, 0x66, 0x00,  0xFF, 0xCC, 0x00,
+            0xFF, 0xCC, 0x00,  0x99, 0x66, 0x00,
+        ]);
+    }
+
+    #[test]
+    fn blends_fading_pixels() {
+        let mut filter = PersistenceFilter::new(Persistence::Decay(2), 1, 1);
+        filter.update(&BTreeSet::from([(0, 0)]));
+        filter.update(&BTreeSet::new());
+        let mut pixels = vec![0; 3];
+
+        fill_pixels(&filter, 1, &Palette::CLASSIC, &mut pixels);
+
+        assert_eq!(pixels, vec![127, 127, 127]);
+    }
+}
@@ -0,0 +1,79 @@
+use super::{Capture, Settings};
+use chip_8_core::{
+    cheat::Cheat,
+    globals::{Err, Keys},
+    Chip8, Interface,
+};
+use std::collections::BTreeSet;
+
+/// Runs a ROM as fast as possible with no display and no keys held,
+/// to capture screenshots and recordings of ROMs in CI
+pub struct Headless {
+    settings: Settings,
+    capture: Capture,
+    // Frames to run before stopping
+    frames: u64,
+    frames_run: u64,
+    screenshot_after: Option<u64>,
+}
+
+impl Headless {
+    pub fn new(settings: Settings, frames: u64, screenshot_after: Option<u64>) -> Self {
+        Headless {
+            capture: Capture::new(&settings),
+            settings,
+            frames,
+            frames_run: 0,
+            screenshot_after,
+        }
+    }
+}
+
+impl Interface for Headless {
+    fn run(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
+        self.setup()?;
+        while let Keys::Keys(held_keys) = self.read_keys()? {
+            chip_8.run_frame(&held_keys);
+            self.draw(chip_8)?;
+        }
+        self.cleanup()
+    }
+
+    fn setup(&mut self) -> Result<(), Err> {
+        Ok(())
+    }
+
+    fn load_rom(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
+        self.settings.load_rom(chip_8)
+    }
+
+    fn set_cheats(&mut self, cheats: Vec<Cheat>) {
+        self.settings.cheats = cheats;
+    }
+
+    fn read_keys(&mut self) -> Result<Keys, Err> {
+        if self.frames_run >= self.frames {
+            return Ok(Keys::Break);
+        }
+        Ok(Keys::Keys(BTreeSet::new()))
+    }
+
+    fn draw(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
+        self.frames_run += 1;
+        self.settings.apply_cheats(chip_8);
+        self.capture.frame(&self.settings, chip_8)?;
+        if self.screenshot_after == Some(self.frames_run) {
+            let path = self.settings.screenshot(chip_8)?;
+            println!("{}", path.display());
+        }
+        Ok(())
+    }
+
+    fn cleanup(&mut self) -> Result<(), Err> {
+        self.capture.stop_recording()
+    }
+
+    fn real_time(&self) -> bool {
+        false
+    }
+}
@@ -1,97 +1,140 @@
+mod color;
+mod input;
+pub use input::InputBackend;
+mod render;
+pub use render::RenderMode;
+
+use super::{Capture, Settings};
 use chip_8_core::globals::{Err, Keys};
-use chip_8_core::{chip_8, globals, Chip8, Interface};
+use chip_8_core::{cheat::Cheat, globals, persistence::PersistenceFilter, Chip8, Interface};
+use color::ColorSupport;
 use crossterm::{
     cursor,
-    style::Print,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    style::{Colors, Print, ResetColor, SetColors},
     terminal::{self, ClearType},
     QueueableCommand,
 };
-use device_query::{DeviceQuery, DeviceState, Keycode};
-use lazy_static::lazy_static;
+use input::Input;
 use log::debug;
+use render::Frame;
 use std::{
-    collections::{HashMap, HashSet},
-    fs,
     io::{stdout, Stdout, Write},
     thread,
     time::{Duration, Instant},
 };
 
-lazy_static! {
-    static ref KEY_CODE_TO_CHIP_8_KEY: HashMap<Keycode, u8> = HashMap::from([
-        (Keycode::Key1, 1),
-        (Keycode::Key2, 2),
-        (Keycode::Key3, 3),
-        (Keycode::Key4, 0xC),
-        (Keycode::Q, 4),
-        (Keycode::W, 5),
-        (Keycode::E, 6),
-        (Keycode::R, 0xD),
-        (Keycode::A, 7),
-        (Keycode::S, 8),
-        (Keycode::D, 9),
-        (Keycode::F, 0xE),
-        (Keycode::Z, 0xA),
-        (Keycode::X, 0),
-        (Keycode::C, 0xB),
-        (Keycode::V, 0xF),
-    ]);
-}
-
 pub struct Terminal {
     stdout: Stdout,
-    device_state: DeviceState,
-
-    // Keep track of the last frame of the display, for (hopefully) faster rendering.
-    last_frame: Option<chip_8::Display>,
+    input: Input,
+
+    render_mode: RenderMode,
+    // Terminal size in columns and rows, kept up to date by resize events
+    size: (u16, u16),
+    // Keep track of the last frame drawn, so only changed cells need to be printed
+    last_frame: Option<Frame>,
+    filter: PersistenceFilter,
+    color_support: ColorSupport,
+
+    settings: Settings,
+    capture: Capture,
 }
 
 impl Terminal {
-    pub fn new() -> Self {
+    /// `key_hold_timeout` is how long a key counts as held after it's pressed,
+    /// for terminals that don't report key releases
+    pub fn new(
+        settings: Settings,
+        render_mode: RenderMode,
+        input_backend: InputBackend,
+        key_hold_timeout: Duration,
+    ) -> Self {
         let stdout = stdout();
-        let device_state = DeviceState::new();
+        let input = Input::new(input_backend, key_hold_timeout);
+        let capture = Capture::new(&settings);
         Terminal {
             stdout,
-            device_state,
+            input,
+            render_mode,
+            size: terminal::size().unwrap_or((80, 24)),
             last_frame: None,
+            filter: PersistenceFilter::new(
+                settings.persistence,
+                globals::DISPLAY_WIDTH,
+                globals::DISPLAY_HEIGHT,
+            ),
+            color_support: ColorSupport::detect(),
+            settings,
+            capture,
+        }
+    }
+
+    /// Colors to print cells in, or None if the terminal can't show them
+    fn colors(&self) -> Option<Colors> {
+        let foreground = self.color_support.color(self.settings.palette.on())?;
+        let background = self.color_support.color(self.settings.palette.off())?;
+        Some(Colors::new(foreground, background))
+    }
+
+    /// Handle pending terminal events.
+    /// On resize, clear the screen so the next draw starts from scratch.
+    /// F2 cycles through the built-in palettes, F5 turns cheats on or off,
+    /// F12 saves a screenshot, and F9 starts or stops recording.
+    fn handle_events(&mut self) -> Result<(), Err> {
+        while event::poll(Duration::ZERO)? {
+            match event::read()? {
+                Event::Resize(columns, rows) => {
+                    self.size = (columns, rows);
+                    self.last_frame = None;
+                    self.stdout
+                        .queue(ResetColor)?
+                        .queue(terminal::Clear(ClearType::All))?;
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(2),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => {
+                    self.settings.palette = self.settings.palette.next();
+                    self.last_frame = None;
+                }
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(5),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => self.settings.toggle_cheats(),
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(12),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => self.capture.request_screenshot(),
+                Event::Key(KeyEvent {
+                    code: KeyCode::F(9),
+                    kind: KeyEventKind::Press,
+                    ..
+                }) => self.capture.toggle_recording(),
+                Event::Key(key_event) => self.input.key_event(key_event),
+                _ => {}
+            }
         }
+        Ok(())
     }
 }
 
 impl Interface for Terminal {
     fn run(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
-        // Terminal setup
-        terminal::enable_raw_mode()?;
-        self.stdout
-            .queue(terminal::EnterAlternateScreen)?
-            .queue(terminal::Clear(ClearType::All))?
-            .queue(cursor::Hide)?;
+        self.setup()?;
 
         let ns_per_frame: u64 = hertz::fps_to_ns_per_frame(globals::FRAMES_PER_SECOND as usize);
 
         // Game loop
         let mut last_frame_end = Instant::now();
-        let mut last_frame_display = chip_8.display.clone();
         loop {
             let frame_start = Instant::now();
 
-            let mut held_keys = HashSet::new();
-
-            // Read keys
-            let keys = self.device_state.get_keys();
-
-            // Break out if ESC or CTRL-C are pressed
-            if keys.contains(&Keycode::Escape)
-                || (keys.contains(&Keycode::LControl) && keys.contains(&Keycode::C))
-            {
+            let Keys::Keys(held_keys) = self.read_keys()? else {
                 break;
-            }
-
-            for key_code in keys {
-                if let Some(&chip_8_key) = KEY_CODE_TO_CHIP_8_KEY.get(&key_code) {
-                    held_keys.insert(chip_8_key);
-                }
-            }
+            };
 
             // Decrement counters
             chip_8.decrement_counters();
@@ -101,7 +144,7 @@ impl Interface for Terminal {
                 chip_8.run_cycle(&held_keys);
             }
 
-            draw(&chip_8.display, &last_frame_display, &mut self.stdout)?;
+            Interface::draw(self, chip_8)?;
 
             let time_remaining =
                 Duration::from_nanos(ns_per_frame).saturating_sub(last_frame_end.elapsed());
@@ -115,28 +158,18 @@ impl Interface for Terminal {
                 (frame_end - frame_start).as_millis()
             );
 
-            last_frame_display = chip_8.display.clone();
             last_frame_end = frame_end;
         }
 
-        // Cleanup
-        self.stdout
-            .queue(cursor::Show)?
-            .queue(terminal::LeaveAlternateScreen)?;
-        terminal::disable_raw_mode()?;
-
-        Ok(())
+        self.cleanup()
     }
 
     fn load_rom(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
-        // Load ROM into CPU memory
-        let rom =
-            fs::read("roms/brick.ch8").expect("should be able to read ROM file at roms/brick.ch8");
-        chip_8.memory[0x200..0x200 + rom.len()].clone_from_slice(&rom[..]);
-        chip_8.memory[0x1FF] = 5;
-        chip_8.memory[0x1FE] = 2;
+        self.settings.load_rom(chip_8)
+    }
 
-        Ok(())
+    fn set_cheats(&mut self, cheats: Vec<Cheat>) {
+        self.settings.cheats = cheats;
     }
 
     fn setup(&mut self) -> Result<(), Err> {
@@ -145,87 +178,46 @@ impl Interface for Terminal {
             .queue(terminal::EnterAlternateScreen)?
             .queue(terminal::Clear(ClearType::All))?
             .queue(cursor::Hide)?;
+        self.input.setup(&mut self.stdout)?;
         Ok(())
     }
 
     fn read_keys(&mut self) -> Result<Keys, Err> {
-        let term_keys = self.device_state.get_keys();
-
-        // Break out if ESC or CTRL-C are pressed
-        if term_keys.contains(&Keycode::Escape)
-            || (term_keys.contains(&Keycode::LControl) && term_keys.contains(&Keycode::C))
-        {
-            return Ok(Keys::Break);
-        }
-
-        // Convert term_keys to Keys hashset
-        let keys_set: HashSet<u8> = term_keys
-            .into_iter()
-            .filter_map(|key_code| KEY_CODE_TO_CHIP_8_KEY.get(&key_code).map(|key| key.clone()))
-            .collect();
-        Ok(Keys::Keys(keys_set))
+        self.handle_events()?;
+        Ok(self.input.read_keys())
     }
 
     fn draw(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
-        let last_frame = self.last_frame.clone().unwrap_or_default();
-        draw(&chip_8.display, &last_frame, &mut self.stdout)?;
-        self.last_frame = Some(chip_8.display.clone());
+        self.settings.apply_cheats(chip_8);
+        self.capture.frame(&self.settings, chip_8)?;
+
+        let (width, height) = (globals::DISPLAY_WIDTH, globals::DISPLAY_HEIGHT);
+        let mode = self.render_mode.resolve(width, height, self.size);
+        self.filter.update(&chip_8.display);
+        let frame = Frame::new(&self.filter, mode);
+
+        if let Some(colors) = self.colors() {
+            self.stdout.queue(SetColors(colors))?;
+        }
+        for (column, row, text) in frame.changes(self.last_frame.as_ref(), self.size) {
+            self.stdout
+                .queue(cursor::MoveTo(column, row))?
+                .queue(Print(text))?;
+        }
+        self.stdout.queue(ResetColor)?.flush()?;
+
+        self.last_frame = Some(frame);
         Ok(())
     }
 
     fn cleanup(&mut self) -> Result<(), Err> {
+        self.capture.stop_recording()?;
+        self.input.cleanup(&mut self.stdout)?;
         self.stdout
+            .queue(ResetColor)?
             .queue(cursor::Show)?
             .queue(terminal::LeaveAlternateScreen)?;
         terminal::disable_raw_mode()?;
         Ok(())
     }
 }
-
-fn draw(
-    display: &chip_8::Display,
-    last_frame_display: &chip_8::Display,
-    stdout: &mut Stdout,
-) -> Result<(), Err> {
-    // Get a diff between last_frame_display and display,
-    // And execute changes to get to the current frame
-
-    #[derive(Clone, Copy, Debug)]
-    enum Op {
-        Add(i32, i32),
-        Remove(i32, i32),
-    }
-
-    let removed = last_frame_display - display;
-    let removed = removed.iter().map(|&(x, y)| Op::Remove(x, y));
-
-    let added = display - last_frame_display;
-    let added = added.iter().map(|&(x, y)| Op::Add(x, y));
-
-    let mut ops: Vec<_> = removed.chain(added).collect();
-    ops.sort_by_key(|&op| match op {
-        Op::Add(_, y) => y,
-        Op::Remove(_, y) => y,
-    });
-
-    debug!("{:?}", ops);
-
-    for op in ops.into_iter() {
-        match op {
-            Op::Add(x, y) => {
-                stdout
-                    .queue(cursor::MoveTo(x as u16, y as u16))?
-                    .queue(Print("█"))?;
-            }
-            Op::Remove(x, y) => {
-                stdout
-                    .queue(cursor::MoveTo(x as u16, y as u16))?
-                    .queue(Print(" "))?;
-            }
-        }
-    }
-
-    stdout.flush()?;
-
-    Ok(())
-}
@@ -0,0 +1,115 @@
+use chip_8_core::palette::Rgb;
+use crossterm::style::Color;
+use std::env;
+
+// Channel levels of the 6x6x6 color cube in the xterm 256-color palette
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The colors a terminal can show
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ColorSupport {
+    TrueColor,
+    Ansi256,
+    /// Leave the terminal's own colors alone
+    None,
+}
+
+impl ColorSupport {
+    /// Guess from `COLORTERM` and `TERM`, the way most terminal programs do
+    pub fn detect() -> ColorSupport {
+        ColorSupport::from_env(
+            env::var("COLORTERM").ok().as_deref(),
+            env::var("TERM").ok().as_deref(),
+        )
+    }
+
+    fn from_env(colorterm: Option<&str>, term: Option<&str>) -> ColorSupport {
+        if matches!(colorterm, Some("truecolor" | "24bit")) {
+            ColorSupport::TrueColor
+        } else if term.is_some_and(|term| term.contains("256color")) {
+            ColorSupport::Ansi256
+        } else {
+            ColorSupport::None
+        }
+    }
+
+    /// The closest color the terminal can show, if it can show colors at all
+    pub fn color(self, rgb: Rgb) -> Option<Color> {
+        match self {
+            ColorSupport::TrueColor => Some(Color::Rgb {
+                r: rgb.r,
+                g: rgb.g,
+                b: rgb.b,
+            }),
+            ColorSupport::Ansi256 => Some(Color::AnsiValue(ansi_256(rgb))),
+            ColorSupport::None => None,
+        }
+    }
+}
+
+/// The nearest color in the xterm 256-color palette, from the color cube or the grey ramp.
+/// The first 16 colors are skipped, since terminals are free to theme them.
+fn ansi_256(rgb: Rgb) -> u8 {
+    let distance = |other: Rgb| {
+        let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        channel(rgb.r, other.r) + channel(rgb.g, other.g) + channel(rgb.b, other.b)
+    };
+    let nearest_level = |value: u8| {
+        (0..CUBE_LEVELS.len())
+            .min_by_key(|&index| (CUBE_LEVELS[index] as i32 - value as i32).abs())
+            .unwrap()
+    };
+
+    let (r, g, b) = (
+        nearest_level(rgb.r),
+        nearest_level(rgb.g),
+        nearest_level(rgb.b),
+    );
+    let cube = Rgb::new(CUBE_LEVELS[r], CUBE_LEVELS[g], CUBE_LEVELS[b]);
+    let cube_index = 16 + 36 * r + 6 * g + b;
+
+    // The grey ramp runs from 8 to 238 in steps of 10
+    let average = (rgb.r as i32 + rgb.g as i32 + rgb.b as i32) / 3;
+    let grey_step = ((average - 8 + 5) / 10).clamp(0, 23);
+    let grey_level = (8 + 10 * grey_step) as u8;
+    let grey = Rgb::new(grey_level, grey_level, grey_level);
+    let grey_index = 232 + grey_step as usize;
+
+    if distance(grey) < distance(cube) {
+        grey_index as u8
+    } else {
+        cube_index as u8
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chip_8_core::palette::Rgb;
+
+    use super::{ansi_256, ColorSupport};
+
+    #[test]
+    fn detects_color_support() {
+        assert_eq!(
+            ColorSupport::from_env(Some("truecolor"), Some("xterm-256color")),
+            ColorSupport::TrueColor
+        );
+        assert_eq!(
+            ColorSupport::from_env(None, Some("screen-256color")),
+            ColorSupport::Ansi256
+        );
+        assert_eq!(
+            ColorSupport::from_env(None, Some("xterm")),
+            ColorSupport::None
+        );
+        assert_eq!(ColorSupport::from_env(None, None), ColorSupport::None);
+    }
+
+    #[test]
+    fn picks_nearest_ansi_color() {
+        assert_eq!(ansi_256(Rgb::new(0, 0, 0)), 16);
+        assert_eq!(ansi_256(Rgb::new(255, 255, 255)), 231);
+        assert_eq!(ansi_256(Rgb::new(0xFF, 0xCC, 0x00)), 220);
+        assert_eq!(ansi_256(Rgb::new(0x80, 0x80, 0x80)), 244);
+    }
+}
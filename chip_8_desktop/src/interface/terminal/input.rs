@@ -0,0 +1,267 @@
+use chip_8_core::globals::{Err, Keys};
+use clap::ValueEnum;
+use crossterm::{
+    event::{
+        KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
+    terminal, QueueableCommand,
+};
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use lazy_static::lazy_static;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::Stdout,
+    time::{Duration, Instant},
+};
+
+lazy_static! {
+    static ref KEY_CODE_TO_CHIP_8_KEY: HashMap<Keycode, u8> = HashMap::from([
+        (Keycode::Key1, 1),
+        (Keycode::Key2, 2),
+        (Keycode::Key3, 3),
+        (Keycode::Key4, 0xC),
+        (Keycode::Q, 4),
+        (Keycode::W, 5),
+        (Keycode::E, 6),
+        (Keycode::R, 0xD),
+        (Keycode::A, 7),
+        (Keycode::S, 8),
+        (Keycode::D, 9),
+        (Keycode::F, 0xE),
+        (Keycode::Z, 0xA),
+        (Keycode::X, 0),
+        (Keycode::C, 0xB),
+        (Keycode::V, 0xF),
+    ]);
+    static ref CHAR_TO_CHIP_8_KEY: HashMap<char, u8> = HashMap::from([
+        ('1', 1),
+        ('2', 2),
+        ('3', 3),
+        ('4', 0xC),
+        ('q', 4),
+        ('w', 5),
+        ('e', 6),
+        ('r', 0xD),
+        ('a', 7),
+        ('s', 8),
+        ('d', 9),
+        ('f', 0xE),
+        ('z', 0xA),
+        ('x', 0),
+        ('c', 0xB),
+        ('v', 0xF),
+    ]);
+}
+
+/// Where the terminal interface reads keys from
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum InputBackend {
+    /// Poll the local keyboard. Needs an X11 session on this machine.
+    Device,
+    /// Read key events from the terminal. Works over SSH and in tmux.
+    Events,
+}
+
+pub enum Input {
+    Device(DeviceState),
+    Events(EventInput),
+}
+
+impl Input {
+    pub fn new(backend: InputBackend, hold_timeout: Duration) -> Self {
+        match backend {
+            InputBackend::Device => Input::Device(DeviceState::new()),
+            InputBackend::Events => Input::Events(EventInput::new(hold_timeout)),
+        }
+    }
+
+    /// Ask the terminal for key release events, if we read keys from it and it supports them
+    pub fn setup(&mut self, stdout: &mut Stdout) -> Result<(), Err> {
+        if let Input::Events(input) = self {
+            // Terminals that don't understand the query may not answer at all,
+            // which crossterm reports as an error; fall back to the hold timeout then
+            if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+                stdout.queue(PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES
+                        | KeyboardEnhancementFlags::REPORT_EVENT_TYPES,
+                ))?;
+                input.reports_releases = true;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn cleanup(&mut self, stdout: &mut Stdout) -> Result<(), Err> {
+        if let Input::Events(EventInput {
+            reports_releases: true,
+            ..
+        }) = self
+        {
+            stdout.queue(PopKeyboardEnhancementFlags)?;
+        }
+        Ok(())
+    }
+
+    /// Handle a key event read from the terminal
+    pub fn key_event(&mut self, event: KeyEvent) {
+        if let Input::Events(input) = self {
+            input.key_event(event, Instant::now());
+        }
+    }
+
+    /// Read held chip8 keys, or a break signal
+    pub fn read_keys(&mut self) -> Keys {
+        match self {
+            Input::Device(device_state) => {
+                let term_keys = device_state.get_keys();
+
+                // Break out if ESC or CTRL-C are pressed
+                if term_keys.contains(&Keycode::Escape)
+                    || (term_keys.contains(&Keycode::LControl) && term_keys.contains(&Keycode::C))
+                {
+                    return Keys::Break;
+                }
+
+                // Convert term_keys to Keys hashset
+                let keys_set: BTreeSet<u8> = term_keys
+                    .into_iter()
+                    .filter_map(|key_code| KEY_CODE_TO_CHIP_8_KEY.get(&key_code).copied())
+                    .collect();
+                Keys::Keys(keys_set)
+            }
+            Input::Events(input) => input.read_keys(Instant::now()),
+        }
+    }
+}
+
+/// Keys read from terminal key events.
+///
+/// Terminals that speak the kitty keyboard protocol report releases, so keys
+/// are held from press to release. Other terminals only send presses (and
+/// autorepeated presses while a key is held), so a key counts as held until
+/// `hold_timeout` passes without it being pressed again.
+pub struct EventInput {
+    reports_releases: bool,
+    hold_timeout: Duration,
+    // Held chip8 keys, and when each was last pressed
+    held_keys: HashMap<u8, Instant>,
+    quit: bool,
+}
+
+impl EventInput {
+    fn new(hold_timeout: Duration) -> Self {
+        EventInput {
+            reports_releases: false,
+            hold_timeout,
+            held_keys: HashMap::new(),
+            quit: false,
+        }
+    }
+
+    fn key_event(&mut self, event: KeyEvent, now: Instant) {
+        let ctrl_c =
+            event.code == KeyCode::Char('c') && event.modifiers.contains(KeyModifiers::CONTROL);
+        if (event.code == KeyCode::Esc || ctrl_c) && event.kind == KeyEventKind::Press {
+            self.quit = true;
+            return;
+        }
+
+        let KeyCode::Char(c) = event.code else {
+            return;
+        };
+        let Some(&chip_8_key) = CHAR_TO_CHIP_8_KEY.get(&c.to_ascii_lowercase()) else {
+            return;
+        };
+        match event.kind {
+            KeyEventKind::Press | KeyEventKind::Repeat => {
+                self.held_keys.insert(chip_8_key, now);
+            }
+            KeyEventKind::Release => {
+                self.held_keys.remove(&chip_8_key);
+            }
+        }
+    }
+
+    fn read_keys(&mut self, now: Instant) -> Keys {
+        if self.quit {
+            return Keys::Break;
+        }
+        if !self.reports_releases {
+            let hold_timeout = self.hold_timeout;
+            self.held_keys
+                .retain(|_, &mut pressed| now.duration_since(pressed) < hold_timeout);
+        }
+        Keys::Keys(self.held_keys.keys().copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeSet,
+        time::{Duration, Instant},
+    };
+
+    use chip_8_core::globals::Keys;
+    use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyEventState, KeyModifiers};
+
+    use super::EventInput;
+
+    fn key(code: KeyCode, kind: KeyEventKind) -> KeyEvent {
+        KeyEvent {
+            code,
+            modifiers: KeyModifiers::NONE,
+            kind,
+            state: KeyEventState::NONE,
+        }
+    }
+
+    fn held(input: &mut EventInput, now: Instant) -> BTreeSet<u8> {
+        match input.read_keys(now) {
+            Keys::Keys(keys) => keys,
+            Keys::Break => panic!("unexpected break"),
+        }
+    }
+
+    #[test]
+    fn holds_until_release() {
+        let start = Instant::now();
+        let mut input = EventInput::new(Duration::from_millis(100));
+        input.reports_releases = true;
+
+        input.key_event(key(KeyCode::Char('w'), KeyEventKind::Press), start);
+        input.key_event(key(KeyCode::Char('V'), KeyEventKind::Press), start);
+        let later = start + Duration::from_secs(1);
+        assert_eq!(held(&mut input, later), BTreeSet::from([5, 0xF]));
+
+        input.key_event(key(KeyCode::Char('w'), KeyEventKind::Release), later);
+        assert_eq!(held(&mut input, later), BTreeSet::from([0xF]));
+    }
+
+    #[test]
+    fn holds_until_timeout_without_releases() {
+        let start = Instant::now();
+        let mut input = EventInput::new(Duration::from_millis(100));
+
+        input.key_event(key(KeyCode::Char('1'), KeyEventKind::Press), start);
+        assert_eq!(held(&mut input, start), BTreeSet::from([1]));
+
+        // Autorepeat keeps the key held
+        let repeat = start + Duration::from_millis(80);
+        input.key_event(key(KeyCode::Char('1'), KeyEventKind::Press), repeat);
+        assert_eq!(
+            held(&mut input, start + Duration::from_millis(150)),
+            BTreeSet::from([1])
+        );
+
+        assert!(held(&mut input, repeat + Duration::from_millis(100)).is_empty());
+    }
+
+    #[test]
+    fn escape_breaks() {
+        let mut input = EventInput::new(Duration::from_millis(100));
+        input.key_event(key(KeyCode::Esc, KeyEventKind::Press), Instant::now());
+        assert!(matches!(input.read_keys(Instant::now()), Keys::Break));
+    }
+}
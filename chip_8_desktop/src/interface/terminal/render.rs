@@ -0,0 +1,272 @@
+use chip_8_core::persistence::{PersistenceFilter, FULL};
+use clap::ValueEnum;
+
+/// How chip8 pixels are packed into terminal cells.
+/// Terminal cells are about twice as tall as they are wide,
+/// so every mode keeps pixels roughly square.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RenderMode {
+    /// Use the largest mode that fits in the terminal
+    Auto,
+    /// One pixel per two columns: `██`, shading fading pixels `▓▓` `▒▒` `░░`
+    Block,
+    /// Two pixels per cell, one above the other: `▀`, `▄`, `█`
+    HalfBlock,
+    /// Eight pixels per cell, 2 wide by 4 tall, using braille characters
+    Braille,
+}
+
+impl RenderMode {
+    // Largest first, for Auto
+    const FIXED_MODES: [RenderMode; 3] = [
+        RenderMode::Block,
+        RenderMode::HalfBlock,
+        RenderMode::Braille,
+    ];
+
+    /// Pixels packed into each cell, horizontally and vertically
+    fn pixels_per_cell(self) -> (i32, i32) {
+        match self {
+            RenderMode::Auto | RenderMode::Block => (1, 1),
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Braille => (2, 4),
+        }
+    }
+
+    /// Terminal columns taken up by each cell
+    fn columns_per_cell(self) -> u16 {
+        match self {
+            RenderMode::Auto | RenderMode::Block => 2,
+            RenderMode::HalfBlock | RenderMode::Braille => 1,
+        }
+    }
+
+    /// Columns and rows needed to show a display of the given size
+    pub fn size(self, width: i32, height: i32) -> (u16, u16) {
+        let (cells_x, cells_y) = self.cells(width, height);
+        (cells_x as u16 * self.columns_per_cell(), cells_y as u16)
+    }
+
+    fn cells(self, width: i32, height: i32) -> (i32, i32) {
+        let (pixels_x, pixels_y) = self.pixels_per_cell();
+        (
+            (width + pixels_x - 1) / pixels_x,
+            (height + pixels_y - 1) / pixels_y,
+        )
+    }
+
+    /// Resolve Auto to the largest mode that fits in a terminal of `columns` x `rows`.
+    /// If nothing fits, use the smallest mode and let the edges be cut off.
+    pub fn resolve(self, width: i32, height: i32, (columns, rows): (u16, u16)) -> RenderMode {
+        if self != RenderMode::Auto {
+            return self;
+        }
+        RenderMode::FIXED_MODES
+            .into_iter()
+            .find(|mode| {
+                let (needed_columns, needed_rows) = mode.size(width, height);
+                needed_columns <= columns && needed_rows <= rows
+            })
+            .unwrap_or(RenderMode::Braille)
+    }
+}
+
+/// A display packed into terminal cells
+#[derive(Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub mode: RenderMode,
+    cells_x: i32,
+    cells: Vec<char>,
+}
+
+impl Frame {
+    pub fn new(filter: &PersistenceFilter, mode: RenderMode) -> Frame {
+        let (width, height) = filter.size();
+        let (pixels_x, pixels_y) = mode.pixels_per_cell();
+        let (cells_x, cells_y) = mode.cells(width, height);
+
+        let mut cells = Vec::with_capacity((cells_x * cells_y) as usize);
+        for cell_y in 0..cells_y {
+            for cell_x in 0..cells_x {
+                let brightness = |dx: i32, dy: i32| {
+                    filter.brightness(cell_x * pixels_x + dx, cell_y * pixels_y + dy)
+                };
+                cells.push(cell_char(mode, brightness));
+            }
+        }
+
+        Frame {
+            mode,
+            cells_x,
+            cells,
+        }
+    }
+
+    /// Cells to print to get from `last` to this frame, as (column, row, text).
+    /// Everything is printed if there's no last frame, or it was packed differently.
+    /// Cells outside of `terminal_size` are skipped, so the terminal never scrolls.
+    pub fn changes(
+        &self,
+        last: Option<&Frame>,
+        terminal_size: (u16, u16),
+    ) -> Vec<(u16, u16, String)> {
+        let last = last.filter(|last| last.mode == self.mode && last.cells_x == self.cells_x);
+        let columns_per_cell = self.mode.columns_per_cell();
+        let (columns, rows) = terminal_size;
+
+        let mut changes = Vec::new();
+        for (index, &cell) in self.cells.iter().enumerate() {
+            if last.is_some_and(|last| last.cells.get(index) == Some(&cell)) {
+                continue;
+            }
+            let column = (index as i32 % self.cells_x) as u16 * columns_per_cell;
+            let row = (index as i32 / self.cells_x) as u16;
+            if column + columns_per_cell > columns || row >= rows {
+                continue;
+            }
+            let text = std::iter::repeat_n(cell, columns_per_cell as usize).collect();
+            changes.push((column, row, text));
+        }
+        changes
+    }
+}
+
+/// The character for one cell, given the brightness of its pixels.
+/// Only Block mode has room for shades; the others show fading pixels as lit.
+fn cell_char(mode: RenderMode, brightness: impl Fn(i32, i32) -> u8) -> char {
+    let lit = |dx: i32, dy: i32| brightness(dx, dy) > 0;
+    match mode {
+        RenderMode::Auto | RenderMode::Block => match brightness(0, 0) {
+            FULL => '█',
+            170.. => '▓',
+            85.. => '▒',
+            1.. => '░',
+            0 => ' ',
+        },
+        RenderMode::HalfBlock => match (lit(0, 0), lit(0, 1)) {
+            (true, true) => '█',
+            (true, false) => '▀',
+            (false, true) => '▄',
+            (false, false) => ' ',
+        },
+        RenderMode::Braille => {
+            // Braille dot numbering, as bits of the offset from U+2800
+            const DOTS: [(i32, i32, u32); 8] = [
+                (0, 0, 0x01),
+                (0, 1, 0x02),
+                (0, 2, 0x04),
+                (1, 0, 0x08),
+                (1, 1, 0x10),
+                (1, 2, 0x20),
+                (0, 3, 0x40),
+                (1, 3, 0x80),
+            ];
+            let bits = DOTS
+                .iter()
+                .filter(|&&(dx, dy, _)| lit(dx, dy))
+                .fold(0, |bits, &(_, _, bit)| bits | bit);
+            if bits == 0 {
+                ' '
+            } else {
+                char::from_u32(0x2800 + bits).unwrap()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use chip_8_core::{chip_8::Display, persistence::PersistenceFilter, Persistence};
+
+    use super::{Frame, RenderMode};
+
+    fn frame(display: &Display, width: i32, height: i32, mode: RenderMode) -> Frame {
+        let mut filter = PersistenceFilter::new(Persistence::Off, width, height);
+        filter.update(display);
+        Frame::new(&filter, mode)
+    }
+
+    #[test]
+    fn auto_picks_largest_fitting_mode() {
+        assert_eq!(
+            RenderMode::Auto.resolve(64, 32, (200, 50)),
+            RenderMode::Block
+        );
+        assert_eq!(
+            RenderMode::Auto.resolve(64, 32, (80, 24)),
+            RenderMode::HalfBlock
+        );
+        assert_eq!(
+            RenderMode::Auto.resolve(64, 32, (40, 10)),
+            RenderMode::Braille
+        );
+        assert_eq!(
+            RenderMode::Auto.resolve(128, 64, (80, 24)),
+            RenderMode::Braille
+        );
+        assert_eq!(
+            RenderMode::Auto.resolve(64, 32, (10, 5)),
+            RenderMode::Braille
+        );
+        assert_eq!(
+            RenderMode::HalfBlock.resolve(64, 32, (200, 50)),
+            RenderMode::HalfBlock
+        );
+    }
+
+    #[test]
+    fn packs_pixels_into_cells() {
+        let display = BTreeSet::from([(0, 0), (1, 1), (0, 3), (2, 0)]);
+
+        let half_block = frame(&display, 4, 4, RenderMode::HalfBlock);
+        assert_eq!(
+            half_block.cells,
+            vec!['▀', '▄', '▀', ' ', '▄', ' ', ' ', ' ']
+        );
+        let braille = frame(&display, 4, 4, RenderMode::Braille);
+        assert_eq!(braille.cells, vec!['⡑', '⠁']);
+        let block = frame(&display, 4, 1, RenderMode::Block);
+        assert_eq!(block.cells, vec!['█', ' ', '█', ' ']);
+    }
+
+    #[test]
+    fn shades_fading_pixels() {
+        let mut filter = PersistenceFilter::new(Persistence::Decay(4), 1, 2);
+        filter.update(&BTreeSet::from([(0, 0)]));
+        filter.update(&BTreeSet::from([(0, 1)]));
+
+        let mut shades = vec![];
+        for _ in 0..4 {
+            shades.push(Frame::new(&filter, RenderMode::Block).cells[0]);
+            filter.update(&BTreeSet::new());
+        }
+        assert_eq!(shades, vec!['▓', '▒', '░', ' ']);
+
+        // Half blocks can't shade, so fading pixels stay lit until they're dark
+        let mut filter = PersistenceFilter::new(Persistence::Decay(4), 1, 2);
+        filter.update(&BTreeSet::from([(0, 0)]));
+        filter.update(&BTreeSet::from([(0, 1)]));
+        assert_eq!(Frame::new(&filter, RenderMode::HalfBlock).cells, vec!['█']);
+    }
+
+    #[test]
+    fn only_prints_changed_cells() {
+        let last = frame(&BTreeSet::from([(0, 0)]), 4, 2, RenderMode::Block);
+        let next = frame(&BTreeSet::from([(1, 1)]), 4, 2, RenderMode::Block);
+
+        assert_eq!(
+            next.changes(Some(&last), (80, 24)),
+            vec![(0, 0, "  ".to_string()), (2, 1, "██".to_string())]
+        );
+        // Cells off the edge of the terminal are skipped
+        assert_eq!(
+            next.changes(Some(&last), (2, 24)),
+            vec![(0, 0, "  ".to_string())]
+        );
+        // A frame in another mode is redrawn entirely
+        let half_block = frame(&BTreeSet::new(), 4, 2, RenderMode::HalfBlock);
+        assert_eq!(next.changes(Some(&half_block), (80, 24)).len(), 8);
+    }
+}
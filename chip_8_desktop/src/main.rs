@@ -1,9 +1,49 @@
+mod cheats;
+mod config;
+mod control;
+mod coverage;
+mod dap;
+mod debugger;
+mod gdb;
 mod interface;
+mod memory_view;
+mod netplay;
+mod profile;
+mod script;
 
-use chip_8_core::{globals::Err, runner, Interface};
-use clap::{command, Parser, ValueEnum};
-use interface::{Graphical, Terminal};
+use chip_8_core::{
+    analysis::Analysis,
+    compat::{self, CompatFormat, CompatOptions},
+    coverage::CoverageFormat,
+    globals::Err,
+    profiler::ProfileFormat,
+    recording::VideoFormat,
+    runner,
+    screenshot::ImageFormat,
+    sprite_sheet::SpriteSheet,
+    trace::{self, TraceFilter, TraceFormat, Tracer},
+    Chip8, Interface, Observer, Palette, Persistence, Quirks,
+};
+use clap::{Parser, ValueEnum};
+use config::Config;
+use control::ControlServer;
+use coverage::CoverageReport;
+use dap::DapServer;
+use debugger::Repl;
+use gdb::GdbServer;
+use interface::{Graphical, Headless, InputBackend, RenderMode, Settings, Terminal};
 use log::LevelFilter;
+use netplay::Netplay;
+use profile::ProfileReport;
+use script::Script;
+use std::{
+    fs, io,
+    net::{TcpListener, TcpStream},
+    ops::RangeInclusive,
+    panic,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 #[derive(Copy, Clone, ValueEnum)]
 enum InterfaceType {
@@ -11,6 +51,18 @@ enum InterfaceType {
     Terminal,
     /// Display in new window
     Graphical,
+    /// Run without a display, as fast as possible, e.g. to take screenshots in CI
+    Headless,
+    /// Step through the ROM from a command prompt
+    Debug,
+    /// Print what the ROM uses and which quirks it likely needs, without running it
+    Analyze,
+    /// Save the sprites the ROM draws to a sprite sheet, or write an edited sheet
+    /// back into the ROM with --import-sprites
+    Sprites,
+    /// Run every ROM in --compat-dir under each quirks preset and write a report of
+    /// crashes, unknown opcodes, stuck loops and blank screens
+    Compat,
 }
 
 /// Chip8 emulator
@@ -20,6 +72,188 @@ struct Args {
     /// Interface type
     #[arg(value_enum, default_value_t = InterfaceType::Terminal)]
     interface: InterfaceType,
+
+    /// ROM file to run
+    #[arg(long, default_value = "roms/brick.ch8")]
+    rom: PathBuf,
+
+    /// How the terminal interface packs pixels into characters
+    #[arg(long, value_enum, default_value_t = RenderMode::Auto)]
+    render: RenderMode,
+
+    /// Where the terminal interface reads keys from
+    #[arg(long, value_enum, default_value_t = InputBackend::Device)]
+    input: InputBackend,
+
+    /// With `--input events`, how long a key counts as held after it's pressed,
+    /// in terminals that don't report key releases
+    #[arg(long, default_value_t = 250)]
+    key_hold_ms: u64,
+
+    /// Display colors: classic, amber, green, octo, or comma-separated hex colors
+    /// (background and lit pixels, or all four XO-CHIP colors). F2 cycles palettes.
+    #[arg(long)]
+    palette: Option<Palette>,
+
+    /// Anti-flicker persistence: off, blend (show pixels lit in this frame or the last),
+    /// or a number of frames for erased pixels to fade out over
+    #[arg(long)]
+    persistence: Option<Persistence>,
+
+    /// Interpreter behaviors to copy where they disagree: vip, schip or xochip.
+    /// `analyze` suggests one. Defaults to xochip.
+    #[arg(long)]
+    quirks: Option<Quirks>,
+
+    /// Screenshot file format. F12 saves a screenshot.
+    #[arg(long, value_enum, default_value_t = ImageFormat::Png)]
+    screenshot_format: ImageFormat,
+
+    /// Screenshot and sprite sheet pixels per chip8 pixel
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u32).range(1..))]
+    screenshot_scale: u32,
+
+    /// With the headless interface, save a screenshot after this many frames
+    #[arg(long)]
+    screenshot_after: Option<u64>,
+
+    /// Record from the start. F9 starts and stops recording.
+    #[arg(long)]
+    record: bool,
+
+    /// Recording file format
+    #[arg(long, value_enum, default_value_t = VideoFormat::Gif)]
+    record_format: VideoFormat,
+
+    /// Recording pixels per chip8 pixel
+    #[arg(long, default_value_t = 4, value_parser = clap::value_parser!(u32).range(1..))]
+    record_scale: u32,
+
+    /// With the headless interface, how many frames to run.
+    /// Defaults to stopping after the screenshot, or 600 with `compat`.
+    #[arg(long)]
+    frames: Option<u64>,
+
+    /// Write a trace of every instruction run to this file
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Trace file format
+    #[arg(long, value_enum, default_value_t = TraceFormat::Text)]
+    trace_format: TraceFormat,
+
+    /// Only trace instructions at these addresses, e.g. `0x200-0x2FF`. Can be repeated.
+    #[arg(long, value_parser = trace::parse_pc_range)]
+    trace_pc: Vec<RangeInclusive<u16>>,
+
+    /// Only trace these opcode classes, e.g. `DXYN`, or `8` for all 8XY_ instructions.
+    /// Can be repeated.
+    #[arg(long)]
+    trace_op: Vec<String>,
+
+    /// Only trace these frames, counting from 1, e.g. `100-200`
+    #[arg(long, value_parser = trace::parse_frame_range)]
+    trace_frames: Option<RangeInclusive<u64>>,
+
+    /// Write an execution profile to this file at exit: hot addresses, opcode classes,
+    /// subroutine costs, draws per frame and busy-wait loops
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    /// Profile file format
+    #[arg(long, value_enum, default_value_t = ProfileFormat::Text)]
+    profile_format: ProfileFormat,
+
+    /// Add the addresses this session executed, read and wrote to this coverage data
+    /// file, creating it if needed, so coverage builds up over several runs
+    #[arg(long)]
+    coverage: Option<PathBuf>,
+
+    /// Write an annotated disassembly of the ROM's coverage, including any earlier
+    /// runs in the `--coverage` file, to this file
+    #[arg(long)]
+    coverage_report: Option<PathBuf>,
+
+    /// Coverage report format
+    #[arg(long, value_enum, default_value_t = CoverageFormat::Text)]
+    coverage_format: CoverageFormat,
+
+    /// With `--interface debug`, a symbol map from `chip_8_octo --symbols`, to show
+    /// labels and source lines and break on labels
+    #[arg(long)]
+    symbols: Option<PathBuf>,
+
+    /// Instead of running the interface, wait for a GDB remote protocol client on
+    /// this local port and run the ROM as it asks
+    #[arg(long)]
+    gdb: Option<u16>,
+
+    /// Run a Rhai script with hooks on frames, instructions, memory writes and draws,
+    /// that can change the machine, press keys, take screenshots and stop the run.
+    /// Headless runs with a script don't need --frames.
+    #[arg(long, conflicts_with_all = ["host", "join", "control"])]
+    script: Option<PathBuf>,
+
+    /// Host two-player netplay on this port: both players' keys are held on both
+    /// machines, which run in lockstep
+    #[arg(long, conflicts_with_all = ["join", "control"])]
+    host: Option<u16>,
+
+    /// Join netplay hosted at this address, e.g. `192.168.1.20:7000`, with the same
+    /// ROM and quirks
+    #[arg(long, conflicts_with = "control")]
+    join: Option<String>,
+
+    /// With --host, frames between reading keys and using them, so they have time to
+    /// reach the other player
+    #[arg(long, default_value_t = 2)]
+    input_delay: u64,
+
+    /// Instead of running the interface, serve the Debug Adapter Protocol on stdin
+    /// and stdout, or on this local port, for editors to launch and debug ROMs
+    #[arg(long, num_args = 0..=1)]
+    dap: Option<Option<u16>>,
+
+    /// Take JSON-RPC requests to load ROMs, pause, step, press keys, read and write
+    /// memory, take screenshots and save states, on this local port or Unix socket
+    /// path. Headless runs with a control socket don't need --frames.
+    #[arg(long)]
+    control: Option<String>,
+
+    /// Cheat file, with cheats for each ROM by hash. The debugger's `cheat` command
+    /// adds to it, and F5 turns cheats on or off while playing.
+    #[arg(long, default_value = "chip_8.cheats")]
+    cheats: PathBuf,
+
+    /// With `sprites`, the sprite sheet PNG to save or import. Each sprite is under
+    /// its address, and reads back as lit where it's nearer the lit palette color.
+    #[arg(long, default_value = "sprites.png")]
+    sprite_sheet: PathBuf,
+
+    /// With `sprites`, write the sprites in the sprite sheet back into the ROM file
+    #[arg(long)]
+    import_sprites: bool,
+
+    /// With `compat`, a directory of ROMs to run. Can be given more than once.
+    #[arg(long, default_value = "roms")]
+    compat_dir: Vec<PathBuf>,
+
+    /// With `compat`, the report file to write
+    #[arg(long, default_value = "compat.md")]
+    compat_report: PathBuf,
+
+    /// With `compat`, the report format
+    #[arg(long, value_enum, default_value_t = CompatFormat::Markdown)]
+    compat_format: CompatFormat,
+
+    /// With `compat`, where to save each run's final screen, named after the ROM's
+    /// place in the list, the ROM and the quirks preset
+    #[arg(long, default_value = "compat")]
+    compat_screenshots: PathBuf,
+
+    /// Config file
+    #[arg(long, default_value = "chip_8.toml")]
+    config: PathBuf,
 }
 
 fn main() -> Result<(), Err> {
@@ -28,11 +262,231 @@ fn main() -> Result<(), Err> {
     // Init logger
     simple_logging::log_to_file("test.log", LevelFilter::Debug)?;
 
+    let config = Config::load(&args.config)?;
+    let mut settings = Settings {
+        palette: match args.palette {
+            Some(palette) => palette,
+            None => config.palette(&args.rom)?.unwrap_or_default(),
+        },
+        persistence: match args.persistence {
+            Some(persistence) => persistence,
+            None => config.persistence(&args.rom)?.unwrap_or_default(),
+        },
+        quirks: match args.quirks {
+            Some(quirks) => quirks,
+            None => config.quirks(&args.rom)?.unwrap_or_default(),
+        },
+        rom: args.rom,
+        screenshot_format: args.screenshot_format,
+        screenshot_scale: args.screenshot_scale,
+        record: args.record,
+        record_format: args.record_format,
+        record_scale: args.record_scale,
+        cheats: Vec::new(),
+        cheats_enabled: true,
+    };
+
+    if let InterfaceType::Analyze = args.interface {
+        print!("{}", Analysis::analyze(&settings.read_rom()?));
+        return Ok(());
+    }
+    if let InterfaceType::Sprites = args.interface {
+        let mut rom = settings.read_rom()?;
+        let mut sheet = SpriteSheet::find(&rom, settings.palette, settings.screenshot_scale);
+        let path = &args.sprite_sheet;
+        if args.import_sprites {
+            let image = fs::read(path)
+                .map_err(|err| format!("can't read sprite sheet {}: {}", path.display(), err))?;
+            sheet.read_png(&image)?;
+            sheet.write_to_rom(&mut rom)?;
+            fs::write(&settings.rom, rom)?;
+            println!(
+                "Wrote {} sprites from {} into {}",
+                sheet.sprites.len(),
+                path.display(),
+                settings.rom.display()
+            );
+        } else {
+            sheet.save(path)?;
+            for sprite in &sheet.sprites {
+                println!("{:04X}  8x{}", sprite.address, sprite.rows.len());
+            }
+            println!(
+                "Saved {} sprites to {}",
+                sheet.sprites.len(),
+                path.display()
+            );
+        }
+        return Ok(());
+    }
+    if let InterfaceType::Compat = args.interface {
+        let roms = compat::find_roms(&args.compat_dir)?;
+        if roms.is_empty() {
+            return Err("no .ch8 or .c8 ROMs in --compat-dir".into());
+        }
+        fs::create_dir_all(&args.compat_screenshots)?;
+        let options = CompatOptions {
+            frames: args.frames.unwrap_or(600),
+            screenshot_dir: Some(args.compat_screenshots),
+            palette: settings.palette,
+            screenshot_scale: settings.screenshot_scale,
+        };
+        // Crashes are reported per run, not printed as they happen
+        panic::set_hook(Box::new(|_| {}));
+        let results = compat::run_all(&roms, &options);
+        let report_dir = args.compat_report.parent().unwrap_or(Path::new(""));
+        let report = compat::report(&results, options.frames, args.compat_format, report_dir);
+        fs::write(&args.compat_report, report)?;
+        let failed = results
+            .iter()
+            .filter(|result| result.error.is_some())
+            .count();
+        println!(
+            "Ran {} ROMs under {} quirks presets, {} runs failed. Wrote {}",
+            roms.len(),
+            Quirks::PRESETS.len(),
+            failed,
+            args.compat_report.display()
+        );
+        return Ok(());
+    }
+    let cheat_file = cheats::load(&args.cheats)?;
+    // A missing ROM is reported when it's loaded, by the modes that use it
+    if let Ok(rom) = settings.read_rom() {
+        settings.cheats = cheat_file.cheats(&rom).to_vec();
+    }
+
+    let mut observers: Vec<Box<dyn Observer>> = Vec::new();
+    if let Some(path) = &args.trace {
+        let filter = TraceFilter {
+            pcs: args.trace_pc,
+            classes: args.trace_op,
+            frames: args.trace_frames,
+        };
+        observers.push(Box::new(Tracer::create(path, args.trace_format, filter)?));
+    }
+
+    if args.coverage.is_some() || args.coverage_report.is_some() {
+        observers.push(Box::new(CoverageReport::new(
+            &settings.read_rom()?,
+            args.coverage,
+            args.coverage_report,
+            args.coverage_format,
+        )));
+    }
+    let profile = ProfileReport::new(args.profile.clone(), args.profile_format);
+
+    if let Some(port) = args.dap {
+        if args.profile.is_some() {
+            observers.push(Box::new(profile));
+        }
+        let server = DapServer::new(observers);
+        return match port {
+            Some(port) => {
+                let listener = TcpListener::bind(("127.0.0.1", port))?;
+                let (stream, _) = listener.accept()?;
+                server.serve(stream.try_clone()?, stream)
+            }
+            None => server.serve(io::stdin(), io::stdout()),
+        };
+    }
+    if let Some(port) = args.gdb {
+        let mut chip_8 = Chip8::new();
+        settings.load_rom(&mut chip_8)?;
+        if args.profile.is_some() {
+            observers.push(Box::new(profile));
+        }
+        return GdbServer::new(chip_8, observers).serve(port);
+    }
+    if let InterfaceType::Debug = args.interface {
+        let mut chip_8 = Chip8::new();
+        settings.load_rom(&mut chip_8)?;
+        let mut repl = Repl::new(chip_8, profile, observers);
+        if let Some(path) = &args.symbols {
+            repl.load_symbols(path)?;
+        }
+        repl.use_cheats(cheat_file, args.cheats, settings.read_rom()?);
+        return repl.run();
+    }
+    if args.profile.is_some() {
+        observers.push(Box::new(profile));
+    }
+    let mut script = match &args.script {
+        Some(path) => Some(Script::load(
+            path,
+            settings.palette,
+            settings.screenshot_scale,
+        )?),
+        None => None,
+    };
+    let mut control = match &args.control {
+        Some(address) => Some(ControlServer::bind(
+            address,
+            settings.palette,
+            settings.screenshot_scale,
+            cheat_file,
+        )?),
+        None => None,
+    };
+    let mut netplay = match (args.host, &args.join) {
+        (Some(port), _) => {
+            println!("Waiting for the other player to join on port {}", port);
+            let listener = TcpListener::bind(("0.0.0.0", port))?;
+            let rom = settings.read_rom()?;
+            Some(Netplay::host(
+                listener,
+                &rom,
+                settings.quirks,
+                args.input_delay,
+            )?)
+        }
+        (None, Some(address)) => {
+            let stream = TcpStream::connect(address)?;
+            Some(Netplay::join(
+                stream,
+                &settings.read_rom()?,
+                settings.quirks,
+            )?)
+        }
+        (None, None) => None,
+    };
+    if netplay.is_some() {
+        // Cheats only one player has would desync the machines
+        settings.cheats.clear();
+    }
+
     let mut interface: Box<dyn Interface> = match args.interface {
-        InterfaceType::Graphical => Box::new(Graphical::new().unwrap()),
-        InterfaceType::Terminal => Box::new(Terminal::new()),
+        InterfaceType::Graphical => Box::new(Graphical::new(settings).unwrap()),
+        InterfaceType::Terminal => Box::new(Terminal::new(
+            settings,
+            args.render,
+            args.input,
+            Duration::from_millis(args.key_hold_ms),
+        )),
+        InterfaceType::Headless => {
+            let frames = args
+                .frames
+                .or(args.screenshot_after)
+                .or((script.is_some() || control.is_some()).then_some(u64::MAX))
+                .ok_or(
+                    "the headless interface needs --frames, --screenshot-after, --script or --control",
+                )?;
+            Box::new(Headless::new(settings, frames, args.screenshot_after))
+        }
+        InterfaceType::Debug
+        | InterfaceType::Analyze
+        | InterfaceType::Sprites
+        | InterfaceType::Compat => unreachable!(),
     };
-    runner::run(&mut interface)?;
+    if let Some(script) = &mut script {
+        script.run(&mut interface, &mut observers)?;
+    } else if let Some(netplay) = &mut netplay {
+        netplay.run(&mut interface, &mut observers)?;
+    } else if let Some(control) = &mut control {
+        control.run(&mut interface, &mut observers)?;
+    } else {
+        runner::run(&mut interface, &mut observers)?;
+    }
 
     Ok(())
 }
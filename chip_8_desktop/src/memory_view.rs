@@ -0,0 +1,110 @@
+use chip_8_core::{
+    chip_8::{FONT, FONT_START_LOCATION, PROGRAM_START_LOCATION},
+    globals::Err,
+};
+use crossterm::style::Stylize;
+use std::io::Write;
+
+const BYTES_PER_ROW: usize = 16;
+
+/// A hex view of memory that the debugger shows again every time it stops
+pub struct MemoryView {
+    /// Where the view starts, or None to follow I
+    pub start: Option<u16>,
+    pub rows: usize,
+    /// Highlight with terminal colors as well as markers
+    pub colors: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Region {
+    Reserved,
+    Font,
+    Program,
+}
+
+impl Region {
+    fn of(address: usize) -> Region {
+        if (FONT_START_LOCATION..FONT_START_LOCATION + FONT.len()).contains(&address) {
+            Region::Font
+        } else if address >= PROGRAM_START_LOCATION {
+            Region::Program
+        } else {
+            Region::Reserved
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Region::Reserved => "",
+            Region::Font => "font",
+            Region::Program => "program",
+        }
+    }
+}
+
+impl MemoryView {
+    /// Print rows of 16 bytes, marking the byte at I with `>` and bytes that differ
+    /// from `previous`, memory at the start of the frame, with `*`
+    pub fn show(
+        &self,
+        memory: &[u8],
+        previous: &[u8],
+        i: u16,
+        out: &mut impl Write,
+    ) -> Result<(), Err> {
+        let start = self.start.unwrap_or(i) as usize / BYTES_PER_ROW * BYTES_PER_ROW;
+        let end = (start + self.rows * BYTES_PER_ROW).min(memory.len());
+        let columns: String = (0..BYTES_PER_ROW)
+            .map(|column| format!(" {:X} ", column))
+            .collect();
+        writeln!(out, "{:14}{}", "", columns.trim_end())?;
+        for row in (start..end).step_by(BYTES_PER_ROW) {
+            write!(out, "{:04X}  {:<8}", row, Region::of(row).name())?;
+            let row_end = (row + BYTES_PER_ROW).min(end);
+            for (address, &byte) in (row..row_end).zip(&memory[row..row_end]) {
+                let at_i = address == i as usize;
+                let changed = previous.get(address).is_some_and(|&before| before != byte);
+                let marker = match (at_i, changed) {
+                    (true, _) => '>',
+                    (false, true) => '*',
+                    (false, false) => ' ',
+                };
+                write!(out, "{}", marker)?;
+                let hex = format!("{:02X}", byte);
+                if !self.colors {
+                    write!(out, "{}", hex)?;
+                    continue;
+                }
+                let mut hex = match Region::of(address) {
+                    Region::Reserved => hex.dark_grey(),
+                    Region::Font => hex.cyan(),
+                    Region::Program => hex.stylize(),
+                };
+                if changed {
+                    hex = hex.red().bold();
+                }
+                if at_i {
+                    hex = hex.reverse();
+                }
+                write!(out, "{}", hex)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print memory as sprite rows, a byte of 8 pixels to a line
+pub fn show_sprite(memory: &[u8], start: u16, len: usize, out: &mut impl Write) -> Result<(), Err> {
+    let start = (start as usize).min(memory.len());
+    let end = start.saturating_add(len).min(memory.len());
+    for (address, &byte) in (start..end).zip(&memory[start..end]) {
+        let pixels: String = (0..8)
+            .rev()
+            .map(|bit| if byte >> bit & 1 == 1 { '█' } else { '.' })
+            .collect();
+        writeln!(out, "{:04X}  {:02X}  {}", address, byte, pixels)?;
+    }
+    Ok(())
+}
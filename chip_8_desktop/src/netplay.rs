@@ -0,0 +1,506 @@
+//! Two-player netplay: two machines run the same ROM in lockstep, each frame
+//! holding the keys both players held.
+//!
+//! The host picks the random seed and the input delay, and checks the guest has
+//! the same ROM and quirks. Each side sends the keys it reads at frame `f` for
+//! frame `f + delay`, so there's time for them to arrive, and waits for the other
+//! side's keys before running a frame. Every second both send a hash of their
+//! machine's state, random number generator included, and any frame where the
+//! hashes differ is reported as a desync.
+//!
+//! Messages are lines of text:
+//!
+//! ```text
+//! chip8-netplay 1 <seed> <delay> <quirks> <ROM length> <ROM hash>    host hello
+//! ok | error <reason>                                                guest reply
+//! keys <frame> <held keys as a 16 bit mask in hex>
+//! hash <frame> <state hash>
+//! quit
+//! ```
+
+use chip_8_core::{
+    coverage::fnv1a,
+    globals::{self, Err, Keys},
+    Chip8, Interface, Observer, Quirks,
+};
+use log::{info, warn};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::{BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+const HELLO: &str = "chip8-netplay 1";
+
+// Frames between state hashes, a second of play
+const HASH_INTERVAL: u64 = globals::FRAMES_PER_SECOND as u64;
+
+enum Message {
+    Keys(u64, BTreeSet<u8>),
+    Hash(u64, u64),
+    /// The other player quit or the connection closed
+    Quit,
+}
+
+/// One side of a netplay session
+pub struct Netplay {
+    stream: TcpStream,
+    messages: Receiver<Result<Message, String>>,
+    seed: u64,
+    delay: u64,
+    local_keys: BTreeMap<u64, BTreeSet<u8>>,
+    remote_keys: BTreeMap<u64, BTreeSet<u8>>,
+    local_hashes: BTreeMap<u64, u64>,
+    remote_hashes: BTreeMap<u64, u64>,
+    /// Frames where the machines' states differed
+    pub desyncs: Vec<u64>,
+}
+
+impl Netplay {
+    /// Wait for a guest to connect, and agree on a seed and `delay` frames of input delay
+    pub fn host(
+        listener: TcpListener,
+        rom: &[u8],
+        quirks: Quirks,
+        delay: u64,
+    ) -> Result<Self, Err> {
+        let (stream, address) = listener.accept()?;
+        info!("Netplay guest connected from {}", address);
+        let seed: u64 = rand::random();
+        let mut netplay = Netplay::new(stream, seed, delay)?;
+        writeln!(
+            netplay.stream,
+            "{} {:016x} {} {} {} {:016x}",
+            HELLO,
+            seed,
+            delay,
+            quirks,
+            rom.len(),
+            fnv1a(rom)
+        )?;
+        let mut reader = BufReader::new(netplay.stream.try_clone()?);
+        let reply = read_line(&mut reader)?;
+        if let Some(reason) = reply.strip_prefix("error ") {
+            return Err(format!("the guest can't play: {}", reason).into());
+        }
+        if reply != "ok" {
+            return Err(format!("unexpected reply from the guest: `{}`", reply).into());
+        }
+        netplay.listen(reader);
+        Ok(netplay)
+    }
+
+    /// Join a host, with the same ROM and quirks, using its seed and input delay
+    pub fn join(stream: TcpStream, rom: &[u8], quirks: Quirks) -> Result<Self, Err> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let hello = read_line(&mut reader)?;
+        let fields = hello
+            .strip_prefix(HELLO)
+            .map(|fields| fields.split_whitespace().collect::<Vec<_>>());
+        let Some([seed, delay, host_quirks, rom_len, rom_hash]) = fields.as_deref() else {
+            return Err(format!("not a chip8 netplay host: `{}`", hello).into());
+        };
+        let (seed, delay) = match (u64::from_str_radix(seed, 16), delay.parse()) {
+            (Ok(seed), Ok(delay)) => (seed, delay),
+            _ => return Err(format!("invalid netplay hello: `{}`", hello).into()),
+        };
+        let mut netplay = Netplay::new(stream, seed, delay)?;
+        let problem =
+            if *rom_len != rom.len().to_string() || *rom_hash != format!("{:016x}", fnv1a(rom)) {
+                Some("the host is running a different ROM".to_string())
+            } else if *host_quirks != quirks.to_string() {
+                Some(format!(
+                    "the host uses --quirks {}, and this side {}",
+                    host_quirks, quirks
+                ))
+            } else {
+                None
+            };
+        if let Some(problem) = problem {
+            writeln!(netplay.stream, "error {}", problem)?;
+            return Err(problem.into());
+        }
+        writeln!(netplay.stream, "ok")?;
+        netplay.listen(reader);
+        Ok(netplay)
+    }
+
+    fn new(stream: TcpStream, seed: u64, delay: u64) -> Result<Self, Err> {
+        // Keys are sent every frame, and shouldn't wait to fill a packet
+        stream.set_nodelay(true)?;
+        Ok(Netplay {
+            stream,
+            messages: mpsc::channel().1,
+            seed,
+            delay,
+            local_keys: BTreeMap::new(),
+            remote_keys: BTreeMap::new(),
+            local_hashes: BTreeMap::new(),
+            remote_hashes: BTreeMap::new(),
+            desyncs: Vec::new(),
+        })
+    }
+
+    /// Read the other side's messages in the background
+    fn listen(&mut self, mut reader: BufReader<TcpStream>) {
+        let (sender, messages) = mpsc::channel();
+        self.messages = messages;
+        thread::spawn(move || loop {
+            let message = match read_line(&mut reader) {
+                Ok(line) => parse_message(&line),
+                Err(_) => Ok(Message::Quit),
+            };
+            let done = !matches!(message, Ok(Message::Keys(..) | Message::Hash(..)));
+            if sender.send(message).is_err() || done {
+                break;
+            }
+        });
+    }
+
+    /// Run the ROM the interface loads like `runner::run`, in lockstep with the other
+    /// side, until either player breaks out. Desyncs are errors once the run ends.
+    pub fn run(
+        &mut self,
+        interface: &mut Box<dyn Interface>,
+        observer: &mut dyn Observer,
+    ) -> Result<(), Err> {
+        let mut chip_8 = Chip8::with_seed(self.seed);
+        interface.load_rom(&mut chip_8)?;
+        interface.setup()?;
+        let result = self.play(&mut chip_8, interface, observer);
+        interface.cleanup()?;
+        result?;
+        observer.finish()?;
+
+        match self.desyncs.as_slice() {
+            [] => Ok(()),
+            [frame] => Err(format!("netplay desynced at frame {}", frame).into()),
+            [frame, rest @ ..] => Err(format!(
+                "netplay desynced at frame {}, and {} more times",
+                frame,
+                rest.len()
+            )
+            .into()),
+        }
+    }
+
+    fn play(
+        &mut self,
+        chip_8: &mut Chip8,
+        interface: &mut Box<dyn Interface>,
+        observer: &mut dyn Observer,
+    ) -> Result<(), Err> {
+        let ns_per_frame: u64 = hertz::fps_to_ns_per_frame(globals::FRAMES_PER_SECOND as usize);
+        let mut last_frame_end = Instant::now();
+        for frame in 0.. {
+            let Keys::Keys(keys) = interface.read_keys()? else {
+                break;
+            };
+            // If the other side has gone, that's found out waiting for its keys
+            let _ = writeln!(
+                self.stream,
+                "keys {} {:04x}",
+                frame + self.delay,
+                key_mask(&keys)
+            );
+            self.local_keys.insert(frame + self.delay, keys);
+            let Some(remote_keys) = self.remote_keys(frame)? else {
+                info!("The other netplay player left at frame {}", frame);
+                break;
+            };
+            let mut held_keys = self.local_keys.remove(&frame).unwrap_or_default();
+            held_keys.extend(remote_keys);
+
+            chip_8.run_frame_observed(&held_keys, observer);
+            interface.draw(chip_8)?;
+
+            if frame.is_multiple_of(HASH_INTERVAL) {
+                let hash = fnv1a(&chip_8.save_state()?);
+                let _ = writeln!(self.stream, "hash {} {:016x}", frame, hash);
+                self.local_hashes.insert(frame, hash);
+                self.compare_hashes();
+            }
+
+            if !interface.real_time() {
+                continue;
+            }
+            let time_remaining =
+                Duration::from_nanos(ns_per_frame).saturating_sub(last_frame_end.elapsed());
+            thread::sleep(time_remaining);
+            last_frame_end = Instant::now();
+        }
+        self.leave();
+        Ok(())
+    }
+
+    /// Tell the other side this one's leaving, and wait for it to leave too, so
+    /// neither closes the connection on messages the other hasn't read
+    fn leave(&mut self) {
+        let _ = writeln!(self.stream, "quit");
+        let _ = self.stream.shutdown(Shutdown::Write);
+        while let Ok(Ok(message)) = self.messages.recv() {
+            if let Message::Hash(frame, hash) = message {
+                self.remote_hashes.insert(frame, hash);
+                self.compare_hashes();
+            }
+        }
+    }
+
+    /// The other side's keys for a frame, waiting for them if they haven't arrived.
+    /// None if the other side has left.
+    fn remote_keys(&mut self, frame: u64) -> Result<Option<BTreeSet<u8>>, Err> {
+        // Nobody's pressed anything in time for the first frames
+        if frame < self.delay {
+            return Ok(Some(BTreeSet::new()));
+        }
+        while !self.remote_keys.contains_key(&frame) {
+            match self.messages.recv() {
+                Ok(Ok(Message::Keys(frame, keys))) => {
+                    self.remote_keys.insert(frame, keys);
+                }
+                Ok(Ok(Message::Hash(frame, hash))) => {
+                    self.remote_hashes.insert(frame, hash);
+                    self.compare_hashes();
+                }
+                Ok(Ok(Message::Quit)) | Err(_) => return Ok(None),
+                Ok(Err(err)) => return Err(err.into()),
+            }
+        }
+        Ok(self.remote_keys.remove(&frame))
+    }
+
+    /// Check the frames both sides have hashed
+    fn compare_hashes(&mut self) {
+        let frames: Vec<u64> = self
+            .local_hashes
+            .keys()
+            .filter(|frame| self.remote_hashes.contains_key(frame))
+            .copied()
+            .collect();
+        for frame in frames {
+            if self.local_hashes.remove(&frame) != self.remote_hashes.remove(&frame) {
+                warn!("Netplay desynced at frame {}", frame);
+                self.desyncs.push(frame);
+            }
+        }
+    }
+}
+
+fn read_line(reader: &mut impl BufRead) -> Result<String, Err> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err("the other netplay player disconnected".into());
+    }
+    Ok(line.trim_end().to_string())
+}
+
+fn parse_message(line: &str) -> Result<Message, String> {
+    let invalid = || format!("invalid netplay message `{}`", line);
+    let fields: Vec<&str> = line.split(' ').collect();
+    let number = |field: &str, radix| u64::from_str_radix(field, radix).map_err(|_| invalid());
+    match fields.as_slice() {
+        ["keys", frame, mask] => {
+            let mask = u16::from_str_radix(mask, 16).map_err(|_| invalid())?;
+            let keys = (0..16).filter(|key| mask >> key & 1 == 1).collect();
+            Ok(Message::Keys(number(frame, 10)?, keys))
+        }
+        ["hash", frame, hash] => Ok(Message::Hash(number(frame, 10)?, number(hash, 16)?)),
+        ["quit"] => Ok(Message::Quit),
+        _ => Err(invalid()),
+    }
+}
+
+fn key_mask(keys: &BTreeSet<u8>) -> u16 {
+    keys.iter()
+        .filter(|&&key| key < 16)
+        .fold(0, |mask, key| mask | 1 << key)
+}
+
+#[cfg(test)]
+mod test {
+    use chip_8_core::{
+        globals::{Err, Keys},
+        Chip8, Interface, Quirks,
+    };
+    use std::{
+        collections::BTreeSet,
+        net::{TcpListener, TcpStream},
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use super::{fnv1a, Netplay};
+
+    #[rustfmt::skip]
+    const ROM: [u8; 14] = [
+        0xC1, 0xFF, // 200: RND V1, 0xFF
+        0x80, 0x14, // 202: ADD V0, V1
+        0x62, 0x05, // 204: LD V2, 5
+        0xE2, 0x9E, // 206: SKP V2
+        0x12, 0x00, // 208: JP 0x200
+        0x73, 0x01, // 20A: ADD V3, 1
+        0x12, 0x00, // 20C: JP 0x200
+    ];
+
+    /// Holds 5 on some frames, and keeps the final state
+    struct Player {
+        presses: bool,
+        frames: u64,
+        poke: Option<(usize, u8)>,
+        state: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl Interface for Player {
+        // Netplay runs the frames itself, and only calls the other methods
+        fn run(&mut self, _: &mut Chip8) -> Result<(), Err> {
+            Ok(())
+        }
+
+        fn setup(&mut self) -> Result<(), Err> {
+            Ok(())
+        }
+
+        fn load_rom(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
+            chip_8.load_rom(&ROM)?;
+            if let Some((address, value)) = self.poke {
+                chip_8.memory[address] = value;
+            }
+            Ok(())
+        }
+
+        fn read_keys(&mut self) -> Result<Keys, Err> {
+            if self.frames == 0 {
+                return Ok(Keys::Break);
+            }
+            let mut keys = BTreeSet::new();
+            if self.presses && self.frames.is_multiple_of(3) {
+                keys.insert(5);
+            }
+            Ok(Keys::Keys(keys))
+        }
+
+        fn draw(&mut self, chip_8: &mut Chip8) -> Result<(), Err> {
+            self.frames -= 1;
+            *self.state.lock().unwrap() = chip_8.save_state()?;
+            Ok(())
+        }
+
+        fn cleanup(&mut self) -> Result<(), Err> {
+            Ok(())
+        }
+
+        fn real_time(&self) -> bool {
+            false
+        }
+    }
+
+    /// Play a session on loopback. Returns each side's result and final state.
+    fn play(guest_poke: Option<(usize, u8)>) -> [(Result<(), String>, Vec<u8>); 2] {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let player = move |presses, poke, connect: Box<dyn FnOnce() -> Netplay + Send>| {
+            thread::spawn(move || {
+                let state = Arc::new(Mutex::new(Vec::new()));
+                let mut interface: Box<dyn Interface> = Box::new(Player {
+                    presses,
+                    frames: 200,
+                    poke,
+                    state: state.clone(),
+                });
+                let result = connect()
+                    .run(&mut interface, &mut ())
+                    .map_err(|err| err.to_string());
+                let state = state.lock().unwrap().clone();
+                (result, state)
+            })
+        };
+        let host = player(
+            true,
+            None,
+            Box::new(move || Netplay::host(listener, &ROM, Quirks::VIP, 3).unwrap()),
+        );
+        let guest = player(
+            false,
+            guest_poke,
+            Box::new(move || {
+                let stream = TcpStream::connect(address).unwrap();
+                Netplay::join(stream, &ROM, Quirks::VIP).unwrap()
+            }),
+        );
+        [host.join().unwrap(), guest.join().unwrap()]
+    }
+
+    #[test]
+    fn plays_in_lockstep() {
+        let [(host, host_state), (guest, guest_state)] = play(None);
+        assert_eq!(host, Ok(()));
+        assert_eq!(guest, Ok(()));
+        assert_eq!(host_state, guest_state);
+        // V3 counts the frames the host held 5 on both machines
+        let v3 = host_state[4 + 1 + 4096 + 3];
+        assert!(v3 > 0);
+    }
+
+    #[test]
+    fn reports_desyncs() {
+        let [(host, _), (guest, _)] = play(Some((0x300, 1)));
+        assert_eq!(
+            host,
+            Err("netplay desynced at frame 0, and 3 more times".into())
+        );
+        assert!(guest
+            .unwrap_err()
+            .starts_with("netplay desynced at frame 0"));
+    }
+
+    #[test]
+    fn draws_the_same_numbers_everywhere() {
+        // Both players seed with the host's seed, so CXNN has to give the same
+        // numbers on every target, 32-bit wasm included
+        let mut chip_8 = Chip8::with_seed(0);
+        chip_8.load_rom(&ROM).unwrap();
+        let numbers: Vec<u8> = (0..4)
+            .map(|_| {
+                chip_8.pc = 0x200;
+                chip_8.run_cycle(&BTreeSet::new());
+                chip_8.v[1]
+            })
+            .collect();
+        assert_eq!(numbers, [0xE2, 0x6E, 0x06, 0xF8]);
+    }
+
+    #[test]
+    fn hashes_random_numbers() {
+        let mut chip_8 = Chip8::with_seed(1);
+        chip_8.load_rom(&ROM).unwrap();
+        let fresh = fnv1a(&chip_8.save_state().unwrap());
+        // Draw a number, then put back everything the program can see
+        chip_8.run_cycle(&BTreeSet::new());
+        (chip_8.pc, chip_8.v[1]) = (0x200, 0);
+        assert_ne!(fnv1a(&chip_8.save_state().unwrap()), fresh);
+    }
+
+    #[test]
+    fn rejects_other_roms() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let host = thread::spawn(move || {
+            Netplay::host(listener, &ROM, Quirks::VIP, 2)
+                .err()
+                .map(|err| err.to_string())
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        let guest = Netplay::join(stream, &ROM[..12], Quirks::VIP);
+        assert_eq!(
+            guest.err().map(|err| err.to_string()),
+            Some("the host is running a different ROM".to_string())
+        );
+        assert_eq!(
+            host.join().unwrap(),
+            Some("the guest can't play: the host is running a different ROM".to_string())
+        );
+    }
+}
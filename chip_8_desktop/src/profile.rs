@@ -0,0 +1,51 @@
+use chip_8_core::{
+    globals::Err,
+    profiler::{ProfileFormat, Profiler},
+    Chip8, Observer,
+};
+use log::info;
+use std::{fs, path::PathBuf};
+
+/// Profiles a run, and writes the report to a file once it's over
+pub struct ProfileReport {
+    pub profiler: Profiler,
+    path: Option<PathBuf>,
+    format: ProfileFormat,
+}
+
+impl ProfileReport {
+    /// Profile without writing a report if there's no `path`
+    pub fn new(path: Option<PathBuf>, format: ProfileFormat) -> Self {
+        ProfileReport {
+            profiler: Profiler::new(),
+            path,
+            format,
+        }
+    }
+}
+
+impl Observer for ProfileReport {
+    fn frame_start(&mut self, chip_8: &Chip8) {
+        self.profiler.frame_start(chip_8);
+    }
+
+    fn before_cycle(&mut self, chip_8: &Chip8) {
+        self.profiler.before_cycle(chip_8);
+    }
+
+    fn after_cycle(&mut self, chip_8: &Chip8) {
+        self.profiler.after_cycle(chip_8);
+    }
+
+    fn frame_end(&mut self, chip_8: &Chip8) {
+        self.profiler.frame_end(chip_8);
+    }
+
+    fn finish(&mut self) -> Result<(), Err> {
+        if let Some(path) = &self.path {
+            fs::write(path, self.profiler.report(self.format))?;
+            info!("Wrote profile {}", path.display());
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,423 @@
+//! Rhai scripts that hook into a run, to automate testing and experiments.
+//!
+//! Scripts register hooks when they're loaded:
+//!
+//! ```text
+//! on_frame_start(|| ...)                  // before each frame's timers tick
+//! on_frame_end(|| ...)                    // after each frame's instructions
+//! on_exec(0x210, |pc| ...)                // before the instruction at 0x210 runs
+//! on_write(0x300, |address, value| ...)   // after an instruction writes 0x300
+//! on_draw(|| ...)                         // after CLS or DRW
+//! ```
+//!
+//! Hooks can call `v(x)`, `set_v(x, value)`, `i()`, `set_i(value)`, `pc()`,
+//! `set_pc(address)`, `dt()`, `set_dt(value)`, `st()`, `set_st(value)`,
+//! `peek(address)`, `poke(address, value)`, `press(key)`, `release(key)`, `frame()`,
+//! `screenshot(path)` and `stop()`. Pressed keys are held from the next frame on.
+
+use chip_8_core::{
+    globals::{self, Err, Keys},
+    instruction::MemoryAccess,
+    screenshot::{ImageFormat, Screenshot},
+    Chip8, Debugger, Instruction, Interface, Observer, Palette,
+};
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, AST};
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+    rc::Rc,
+    thread,
+    time::{Duration, Instant},
+};
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+#[derive(Default)]
+struct Hooks {
+    frame_start: Vec<FnPtr>,
+    frame_end: Vec<FnPtr>,
+    exec: BTreeMap<u16, Vec<FnPtr>>,
+    write: BTreeMap<u16, Vec<FnPtr>>,
+    draw: Vec<FnPtr>,
+}
+
+/// What the script can see and change
+struct State {
+    chip_8: Chip8,
+    pressed: BTreeSet<u8>,
+    frames: u64,
+    stopped: bool,
+    palette: Palette,
+    screenshot_scale: u32,
+}
+
+/// A loaded script, ready to run a ROM
+pub struct Script {
+    name: String,
+    engine: Engine,
+    ast: AST,
+    hooks: Rc<RefCell<Hooks>>,
+    state: Rc<RefCell<State>>,
+}
+
+impl Script {
+    /// Load a script and run its top level, which registers its hooks.
+    /// Screenshots it takes use `palette` and `screenshot_scale`.
+    pub fn load(path: &Path, palette: Palette, screenshot_scale: u32) -> Result<Self, Err> {
+        let source = fs::read_to_string(path)
+            .map_err(|err| format!("can't read script {}: {}", path.display(), err))?;
+        Script::compile(
+            &path.display().to_string(),
+            &source,
+            palette,
+            screenshot_scale,
+        )
+    }
+
+    fn compile(
+        name: &str,
+        source: &str,
+        palette: Palette,
+        screenshot_scale: u32,
+    ) -> Result<Self, Err> {
+        let hooks = Rc::new(RefCell::new(Hooks::default()));
+        let state = Rc::new(RefCell::new(State {
+            chip_8: Chip8::new(),
+            pressed: BTreeSet::new(),
+            frames: 0,
+            stopped: false,
+            palette,
+            screenshot_scale,
+        }));
+        let mut engine = Engine::new();
+        register_hooks(&mut engine, &hooks);
+        register_api(&mut engine, &state);
+
+        let ast = engine
+            .compile(source)
+            .map_err(|err| format!("{}: {}", name, err))?;
+        engine
+            .run_ast(&ast)
+            .map_err(|err| format!("{}: {}", name, err))?;
+        Ok(Script {
+            name: name.to_string(),
+            engine,
+            ast,
+            hooks,
+            state,
+        })
+    }
+
+    /// Run the ROM the interface loads like `runner::run`, calling the script's hooks,
+    /// until the interface breaks out or the script stops it
+    pub fn run(
+        &mut self,
+        interface: &mut Box<dyn Interface>,
+        observer: &mut dyn Observer,
+    ) -> Result<(), Err> {
+        interface.load_rom(&mut self.state.borrow_mut().chip_8)?;
+        interface.setup()?;
+
+        let ns_per_frame: u64 = hertz::fps_to_ns_per_frame(globals::FRAMES_PER_SECOND as usize);
+        let mut debugger = Debugger::new();
+        let mut last_frame_end = Instant::now();
+        loop {
+            let Keys::Keys(mut held_keys) = interface.read_keys()? else {
+                break;
+            };
+            held_keys.extend(&self.state.borrow().pressed);
+
+            self.run_frame(&mut debugger, &held_keys, observer)?;
+            interface.draw(&mut self.state.borrow_mut().chip_8)?;
+            if self.state.borrow().stopped {
+                break;
+            }
+
+            if !interface.real_time() {
+                continue;
+            }
+            let time_remaining =
+                Duration::from_nanos(ns_per_frame).saturating_sub(last_frame_end.elapsed());
+            thread::sleep(time_remaining);
+            last_frame_end = Instant::now();
+        }
+
+        interface.cleanup()?;
+        observer.finish()
+    }
+
+    /// Run a frame an instruction at a time, calling hooks in between. Stops early
+    /// if a hook stops the run.
+    fn run_frame(
+        &self,
+        debugger: &mut Debugger,
+        held_keys: &BTreeSet<u8>,
+        observer: &mut dyn Observer,
+    ) -> Result<(), Err> {
+        self.state.borrow_mut().frames += 1;
+        let hooks = self.hooks.borrow().frame_start.clone();
+        self.call(&hooks, || ())?;
+
+        loop {
+            let pc = self.state.borrow().chip_8.pc;
+            let hooks = self.hooks.borrow().exec.get(&pc).cloned();
+            self.call(&hooks.unwrap_or_default(), || (pc as i64,))?;
+            if self.state.borrow().stopped {
+                return Ok(());
+            }
+
+            // Hooks can change the PC, I and memory, so decode what will actually run
+            let (i, instruction) = {
+                let chip_8 = &self.state.borrow().chip_8;
+                let pc = chip_8.pc as usize;
+                let Some(&[high, low]) = chip_8.memory.get(pc..pc + 2) else {
+                    return Err(format!("the PC {:#X} is past the end of memory", pc).into());
+                };
+                if let Some(fault) = chip_8.fault() {
+                    return Err(format!("stopped at {:#X}: {}", pc, fault).into());
+                }
+                (
+                    chip_8.i,
+                    Instruction::decode(u16::from_be_bytes([high, low])),
+                )
+            };
+
+            let frame_end = debugger.step(&mut self.state.borrow_mut().chip_8, held_keys, observer);
+
+            if let Some((MemoryAccess::Write, len)) = instruction.memory_access() {
+                for address in i..i.saturating_add(len) {
+                    let hooks = self.hooks.borrow().write.get(&address).cloned();
+                    let value = self.state.borrow().chip_8.memory[address as usize];
+                    self.call(&hooks.unwrap_or_default(), || {
+                        (address as i64, value as i64)
+                    })?;
+                }
+            }
+            if let Instruction::Cls | Instruction::Drw { .. } = instruction {
+                let hooks = self.hooks.borrow().draw.clone();
+                self.call(&hooks, || ())?;
+            }
+            if self.state.borrow().stopped {
+                return Ok(());
+            }
+            if frame_end {
+                break;
+            }
+        }
+
+        let hooks = self.hooks.borrow().frame_end.clone();
+        self.call(&hooks, || ())
+    }
+
+    fn call<A: FuncArgs>(&self, hooks: &[FnPtr], args: impl Fn() -> A) -> Result<(), Err> {
+        for hook in hooks {
+            // Whatever the hook returns is ignored
+            let _ = hook
+                .call::<Dynamic>(&self.engine, &self.ast, args())
+                .map_err(|err| format!("{}: {}", self.name, err))?;
+        }
+        Ok(())
+    }
+}
+
+fn register_hooks(engine: &mut Engine, hooks: &Rc<RefCell<Hooks>>) {
+    let on = hooks.clone();
+    engine.register_fn("on_frame_start", move |hook: FnPtr| {
+        on.borrow_mut().frame_start.push(hook)
+    });
+    let on = hooks.clone();
+    engine.register_fn("on_frame_end", move |hook: FnPtr| {
+        on.borrow_mut().frame_end.push(hook)
+    });
+    let on = hooks.clone();
+    engine.register_fn(
+        "on_exec",
+        move |address: i64, hook: FnPtr| -> ScriptResult<()> {
+            let address = to_address(address)?;
+            on.borrow_mut().exec.entry(address).or_default().push(hook);
+            Ok(())
+        },
+    );
+    let on = hooks.clone();
+    engine.register_fn(
+        "on_write",
+        move |address: i64, hook: FnPtr| -> ScriptResult<()> {
+            let address = to_address(address)?;
+            on.borrow_mut().write.entry(address).or_default().push(hook);
+            Ok(())
+        },
+    );
+    let on = hooks.clone();
+    engine.register_fn("on_draw", move |hook: FnPtr| {
+        on.borrow_mut().draw.push(hook)
+    });
+}
+
+fn register_api(engine: &mut Engine, state: &Rc<RefCell<State>>) {
+    let s = state.clone();
+    engine.register_fn("v", move |x: i64| -> ScriptResult<i64> {
+        Ok(s.borrow().chip_8.v[to_register(x)?] as i64)
+    });
+    let s = state.clone();
+    engine.register_fn("set_v", move |x: i64, value: i64| -> ScriptResult<()> {
+        s.borrow_mut().chip_8.v[to_register(x)?] = to_byte(value)?;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("i", move || s.borrow().chip_8.i as i64);
+    let s = state.clone();
+    engine.register_fn("set_i", move |value: i64| -> ScriptResult<()> {
+        s.borrow_mut().chip_8.i = to_address(value)?;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("pc", move || s.borrow().chip_8.pc as i64);
+    let s = state.clone();
+    engine.register_fn("set_pc", move |address: i64| -> ScriptResult<()> {
+        s.borrow_mut().chip_8.pc = to_address(address)?;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("dt", move || s.borrow().chip_8.dt as i64);
+    let s = state.clone();
+    engine.register_fn("set_dt", move |value: i64| -> ScriptResult<()> {
+        s.borrow_mut().chip_8.dt = to_byte(value)?;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("st", move || s.borrow().chip_8.st as i64);
+    let s = state.clone();
+    engine.register_fn("set_st", move |value: i64| -> ScriptResult<()> {
+        s.borrow_mut().chip_8.st = to_byte(value)?;
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("peek", move |address: i64| -> ScriptResult<i64> {
+        Ok(s.borrow().chip_8.memory[to_address(address)? as usize] as i64)
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "poke",
+        move |address: i64, value: i64| -> ScriptResult<()> {
+            s.borrow_mut().chip_8.memory[to_address(address)? as usize] = to_byte(value)?;
+            Ok(())
+        },
+    );
+    let s = state.clone();
+    engine.register_fn("press", move |key: i64| -> ScriptResult<()> {
+        s.borrow_mut().pressed.insert(to_key(key)?);
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("release", move |key: i64| -> ScriptResult<()> {
+        s.borrow_mut().pressed.remove(&to_key(key)?);
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("frame", move || s.borrow().frames as i64);
+    let s = state.clone();
+    engine.register_fn("screenshot", move |path: &str| -> ScriptResult<()> {
+        let state = s.borrow();
+        let format = match Path::new(path).extension() {
+            Some(extension) if extension == "pbm" => ImageFormat::Pbm,
+            _ => ImageFormat::Png,
+        };
+        Screenshot {
+            display: &state.chip_8.display,
+            width: globals::DISPLAY_WIDTH,
+            height: globals::DISPLAY_HEIGHT,
+            palette: state.palette,
+            scale: state.screenshot_scale,
+        }
+        .save(Path::new(path), format)
+        .map_err(|err| format!("can't save screenshot {}: {}", path, err).into())
+    });
+    let s = state.clone();
+    engine.register_fn("stop", move || s.borrow_mut().stopped = true);
+}
+
+fn to_register(x: i64) -> ScriptResult<usize> {
+    match x {
+        0..=15 => Ok(x as usize),
+        _ => Err(format!("{} isn't a register from 0 to 15", x).into()),
+    }
+}
+
+fn to_byte(value: i64) -> ScriptResult<u8> {
+    u8::try_from(value).map_err(|_| format!("{} doesn't fit in a byte", value).into())
+}
+
+fn to_address(address: i64) -> ScriptResult<u16> {
+    match address {
+        0..=0xFFF => Ok(address as u16),
+        _ => Err(format!("{:#X} isn't an address in memory", address).into()),
+    }
+}
+
+fn to_key(key: i64) -> ScriptResult<u8> {
+    match key {
+        0..=15 => Ok(key as u8),
+        _ => Err(format!("{} isn't a key from 0 to 15", key).into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chip_8_core::{Debugger, Palette};
+    use std::collections::BTreeSet;
+
+    use super::Script;
+
+    #[test]
+    fn runs_hooks() {
+        let source = "
+            on_frame_start(|| poke(0x310, frame()));
+            on_exec(0x204, |pc| set_v(1, 0x33));
+            on_write(0x301, |address, value| if value == 0x34 { stop() });
+            on_draw(|| throw \"nothing draws\");
+        ";
+        let script = Script::compile("test.rhai", source, Palette::default(), 1).unwrap();
+        #[rustfmt::skip]
+        script.state.borrow_mut().chip_8.load_rom(&[
+            0x60, 0x2A, // 200: LD V0, 0x2A
+            0xA3, 0x00, // 202: LD I, 0x300
+            0x71, 0x01, // 204: ADD V1, 0x01
+            0xF1, 0x55, // 206: LD [I], V1
+            0x12, 0x08, // 208: JP 0x208
+        ]).unwrap();
+
+        script
+            .run_frame(&mut Debugger::new(), &BTreeSet::new(), &mut ())
+            .unwrap();
+        let state = script.state.borrow();
+        assert!(state.stopped);
+        assert_eq!(state.chip_8.pc, 0x208);
+        assert_eq!(state.chip_8.memory[0x300..0x302], [0x2A, 0x34]);
+        assert_eq!(state.chip_8.memory[0x310], 1);
+
+        assert!(Script::compile("bad.rhai", "set_v(16, 0)", Palette::default(), 1).is_err());
+    }
+
+    #[test]
+    fn runs_what_hooks_leave() {
+        let source = "
+            on_exec(0x200, |pc| set_i(0x300));
+            on_exec(0x202, |pc| set_pc(0xFFF));
+        ";
+        let script = Script::compile("test.rhai", source, Palette::default(), 1).unwrap();
+        #[rustfmt::skip]
+        script.state.borrow_mut().chip_8.load_rom(&[
+            0xF0, 0x55, // 200: LD [I], V0
+            0x12, 0x02, // 202: JP 0x202
+        ]).unwrap();
+        script.state.borrow_mut().chip_8.v[0] = 0x2A;
+
+        let result = script.run_frame(&mut Debugger::new(), &BTreeSet::new(), &mut ());
+        assert!(result.is_err());
+        let state = script.state.borrow();
+        assert_eq!(state.chip_8.memory[0x300], 0x2A);
+        assert_eq!(state.chip_8.pc, 0xFFF);
+    }
+}
@@ -0,0 +1,149 @@
+//! `:calc` expressions. Like Octo, operators have no precedence and are applied
+//! right to left, so `2 * 3 + 1` is 8; use parentheses to group.
+
+const BINARY: [&str; 18] = [
+    "+", "-", "*", "/", "%", "&", "|", "^", "<<", ">>", "pow", "min", "max", "<", ">", "<=", ">=",
+    "==",
+];
+const UNARY: [&str; 12] = [
+    "-", "~", "!", "sin", "cos", "tan", "exp", "log", "abs", "sqrt", "sign", "ceil",
+];
+
+/// Evaluate an expression, looking up names with `lookup`
+pub fn evaluate(tokens: &[String], lookup: &dyn Fn(&str) -> Option<f64>) -> Result<f64, String> {
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        lookup,
+    };
+    let value = parser.expression()?;
+    match parser.tokens.get(parser.position) {
+        Some(token) => Err(format!("Unexpected '{}' in expression.", token)),
+        None => Ok(value),
+    }
+}
+
+struct Parser<'a> {
+    tokens: &'a [String],
+    position: usize,
+    lookup: &'a dyn Fn(&str) -> Option<f64>,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Result<&str, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .ok_or("Unexpected end of expression.")?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expression(&mut self) -> Result<f64, String> {
+        let left = self.term()?;
+        let Some(operator) = self.tokens.get(self.position) else {
+            return Ok(left);
+        };
+        if !BINARY.contains(&operator.as_str()) {
+            return Ok(left);
+        }
+        let operator = operator.clone();
+        self.position += 1;
+        let right = self.expression()?;
+        let (a, b) = (left as i64, right as i64);
+        let shift = |shift: fn(i64, u32) -> Option<i64>| {
+            u32::try_from(b)
+                .ok()
+                .and_then(|b| shift(a, b))
+                .ok_or_else(|| format!("The shift {} {} {} is out of range.", a, operator, b))
+        };
+        Ok(match operator.as_str() {
+            "+" => left + right,
+            "-" => left - right,
+            "*" => left * right,
+            "/" => left / right,
+            "%" => left % right,
+            "&" => (a & b) as f64,
+            "|" => (a | b) as f64,
+            "^" => (a ^ b) as f64,
+            "<<" => shift(i64::checked_shl)? as f64,
+            ">>" => shift(i64::checked_shr)? as f64,
+            "pow" => left.powf(right),
+            "min" => left.min(right),
+            "max" => left.max(right),
+            "<" => (left < right) as i64 as f64,
+            ">" => (left > right) as i64 as f64,
+            "<=" => (left <= right) as i64 as f64,
+            ">=" => (left >= right) as i64 as f64,
+            _ => (left == right) as i64 as f64,
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, String> {
+        let token = self.next()?.to_string();
+        if token == "(" {
+            let value = self.expression()?;
+            return match self.next()? {
+                ")" => Ok(value),
+                other => Err(format!("Expected ')', got '{}'.", other)),
+            };
+        }
+        if UNARY.contains(&token.as_str()) || token == "floor" {
+            let value = self.term()?;
+            return Ok(match token.as_str() {
+                "-" => -value,
+                "~" => !(value as i64) as f64,
+                "!" => (value == 0.0) as i64 as f64,
+                "sin" => value.sin(),
+                "cos" => value.cos(),
+                "tan" => value.tan(),
+                "exp" => value.exp(),
+                "log" => value.ln(),
+                "abs" => value.abs(),
+                "sqrt" => value.sqrt(),
+                "sign" => value.signum(),
+                "ceil" => value.ceil(),
+                _ => value.floor(),
+            });
+        }
+        match token.as_str() {
+            "PI" => return Ok(std::f64::consts::PI),
+            "E" => return Ok(std::f64::consts::E),
+            _ => {}
+        }
+        if let Some(number) = crate::compiler::parse_number(&token) {
+            return Ok(number as f64);
+        }
+        if let Ok(number) = token.parse::<f64>() {
+            return Ok(number);
+        }
+        (self.lookup)(&token).ok_or_else(|| format!("Undefined name '{}'.", token))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::evaluate;
+
+    fn calc(expression: &str) -> Result<f64, String> {
+        let tokens: Vec<String> = expression.split_whitespace().map(String::from).collect();
+        evaluate(&tokens, &|name| (name == "WIDTH").then_some(64.0))
+    }
+
+    #[test]
+    fn evaluates_right_to_left() {
+        assert_eq!(calc("2 * 3 + 1"), Ok(8.0));
+        assert_eq!(calc("( 2 * 3 ) + 1"), Ok(7.0));
+        assert_eq!(calc("WIDTH / 2 - - 8"), Ok(6.4));
+        assert_eq!(calc("0xF0 >> 4 & 3"), Ok(240.0));
+        assert_eq!(calc("( 0xF0 >> 4 ) & 3"), Ok(3.0));
+        assert_eq!(calc("floor ( 7 / 2 )"), Ok(3.0));
+        assert_eq!(calc("HEIGHT"), Err("Undefined name 'HEIGHT'.".to_string()));
+        assert!(calc("1 +").is_err());
+        assert_eq!(
+            calc("1 << 64"),
+            Err("The shift 1 << 64 is out of range.".to_string())
+        );
+        assert!(calc("1 >> -1").is_err());
+    }
+}
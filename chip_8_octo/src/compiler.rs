@@ -0,0 +1,1027 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt,
+};
+
+use crate::{
+    calc,
+    token::{tokenize, Token},
+    SymbolMap,
+};
+
+const PROGRAM_START: u16 = 0x200;
+
+/// How deeply macros can expand inside each other, to stop ones that call themselves
+const MAX_MACRO_DEPTH: usize = 256;
+
+/// Words that can't be used as names
+#[rustfmt::skip]
+const KEYWORDS: [&str; 52] = [
+    ":=", "+=", "-=", "=-", "|=", "&=", "^=", ">>=", "<<=",
+    "==", "!=", "<", ">", "<=", ">=", "key", "-key",
+    "hex", "bighex", "long", "random", "delay", "buzzer", "pitch", "i",
+    "if", "then", "begin", "else", "end", "loop", "again", "while",
+    "jump", "jump0", "native", "return", ";",
+    "clear", "bcd", "save", "load", "sprite",
+    "hires", "lores", "scroll-up", "scroll-down", "scroll-left", "scroll-right", "exit",
+    "plane", "audio",
+];
+
+/// A compiled program
+#[derive(Debug)]
+pub struct Program {
+    /// The binary, to load at 0x200
+    pub rom: Vec<u8>,
+    pub symbols: SymbolMap,
+}
+
+/// Why a program didn't compile, and where
+#[derive(Debug, PartialEq, Eq)]
+pub struct CompileError {
+    /// Line number, counting from 1
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for CompileError {}
+
+/// Compile Octo source to a chip8 binary
+pub fn compile(source: &str) -> Result<Program, CompileError> {
+    let mut compiler = Compiler {
+        tokens: tokenize(source).into(),
+        line: 1,
+        depth: 0,
+        rom: Vec::new(),
+        here: PROGRAM_START,
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        control: Vec::new(),
+        symbols: SymbolMap::default(),
+        main_jump: true,
+    };
+    // Octo starts with a jump to main, dropped again if main comes first
+    compiler.fixup(Fixup::Address, "main");
+    compiler.emit(&[0x10, 0x00])?;
+
+    while !compiler.tokens.is_empty() {
+        compiler.statement()?;
+    }
+    compiler.finish()
+}
+
+/// Parse a decimal, `0x` hex or `0b` binary number, possibly negative
+pub(crate) fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn is_name(text: &str) -> bool {
+    !text.is_empty()
+        && !text.starts_with(|c: char| c.is_ascii_digit() || "-:\"{}()".contains(c))
+        && !KEYWORDS.contains(&text)
+}
+
+fn register_number(text: &str) -> Option<u8> {
+    let digit = text.strip_prefix(['v', 'V'])?;
+    if digit.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digit, 16).ok()
+}
+
+/// How a label's address is filled in once it's known
+#[derive(Copy, Clone, Debug)]
+enum Fixup {
+    /// The low 12 bits of an instruction
+    Address,
+    /// A 16 bit word, for `i := long` and `:pointer`
+    Word,
+    /// The low nibble of `:unpack`'s `v0 :=`
+    UnpackHigh,
+    /// The byte of `:unpack`'s `v1 :=`
+    UnpackLow,
+}
+
+struct Macro {
+    arguments: Vec<String>,
+    body: Vec<Token>,
+}
+
+/// An open `begin` or `loop`
+enum Control {
+    /// `begin` or `else`, and the address of the jump past it
+    Branch { keyword: &'static str, jump: u16 },
+    /// `loop`, and the addresses of the jumps out of it from `while`
+    Loop { start: u16, exits: Vec<u16> },
+}
+
+/// A condition for `if` and `while`
+#[derive(Copy, Clone)]
+enum Condition {
+    Equal(u8, Operand),
+    NotEqual(u8, Operand),
+    Less(u8, Operand),
+    Greater(u8, Operand),
+    LessOrEqual(u8, Operand),
+    GreaterOrEqual(u8, Operand),
+    Key(u8),
+    NotKey(u8),
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        match self {
+            Condition::Equal(x, rhs) => Condition::NotEqual(x, rhs),
+            Condition::NotEqual(x, rhs) => Condition::Equal(x, rhs),
+            Condition::Less(x, rhs) => Condition::GreaterOrEqual(x, rhs),
+            Condition::Greater(x, rhs) => Condition::LessOrEqual(x, rhs),
+            Condition::LessOrEqual(x, rhs) => Condition::Greater(x, rhs),
+            Condition::GreaterOrEqual(x, rhs) => Condition::Less(x, rhs),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Operand {
+    Register(u8),
+    Byte(u8),
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    // Line of the last token read, for errors
+    line: usize,
+    // Macro expansions the last token came from
+    depth: usize,
+    // Compiled bytes, from 0x200
+    rom: Vec<u8>,
+    here: u16,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    // Where labels used before they're defined go, and the line using them
+    fixups: Vec<(u16, Fixup, String, usize)>,
+    control: Vec<(Control, usize)>,
+    symbols: SymbolMap,
+    // Whether 0x200 still holds the jump to main
+    main_jump: bool,
+}
+
+impl Compiler {
+    fn error(&self, message: impl Into<String>) -> CompileError {
+        CompileError {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self) -> Result<String, CompileError> {
+        let token = self
+            .tokens
+            .pop_front()
+            .ok_or_else(|| self.error("Unexpected end of file."))?;
+        self.line = token.line;
+        self.depth = token.depth;
+        Ok(token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), CompileError> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(self.error(format!("Expected '{}', got '{}'.", expected, token)));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, bytes: &[u8]) -> Result<(), CompileError> {
+        for &byte in bytes {
+            let offset = (self.here - PROGRAM_START) as usize;
+            if offset >= self.rom.len() {
+                self.rom.resize(offset + 1, 0);
+            }
+            self.rom[offset] = byte;
+            self.here = self
+                .here
+                .checked_add(1)
+                .ok_or_else(|| self.error("The program does not fit in 64 KiB of memory."))?;
+        }
+        Ok(())
+    }
+
+    fn instruction(&mut self, opcode: u16) -> Result<(), CompileError> {
+        self.symbols.lines.insert(self.here, self.line);
+        self.emit(&opcode.to_be_bytes())
+    }
+
+    /// `address` as the NNN of an instruction, if it fits in 12 bits
+    fn twelve_bits(&self, address: u16, what: &str) -> Result<u16, CompileError> {
+        if address > 0xFFF {
+            return Err(self.error(format!("The address of {} does not fit in 12 bits.", what)));
+        }
+        Ok(address)
+    }
+
+    /// Fill in `name`'s address at `here` once it's defined
+    fn fixup(&mut self, kind: Fixup, name: &str) {
+        self.fixups
+            .push((self.here, kind, name.to_string(), self.line));
+    }
+
+    fn patch(&mut self, address: u16, kind: Fixup, value: u16) {
+        let offset = (address - PROGRAM_START) as usize;
+        match kind {
+            Fixup::Address => {
+                self.rom[offset] = (self.rom[offset] & 0xF0) | (value >> 8) as u8;
+                self.rom[offset + 1] = value as u8;
+            }
+            Fixup::Word => self.rom[offset..offset + 2].copy_from_slice(&value.to_be_bytes()),
+            Fixup::UnpackHigh => self.rom[offset + 1] |= (value >> 8) as u8 & 0xF,
+            Fixup::UnpackLow => self.rom[offset + 1] = value as u8,
+        }
+    }
+
+    fn register(&mut self) -> Result<u8, CompileError> {
+        let token = self.next()?;
+        self.to_register(&token)
+            .ok_or_else(|| self.error(format!("Expected register, got '{}'.", token)))
+    }
+
+    fn to_register(&self, text: &str) -> Option<u8> {
+        register_number(text).or_else(|| self.aliases.get(text).copied())
+    }
+
+    fn is_register(&self, text: Option<&str>) -> bool {
+        text.is_some_and(|text| self.to_register(text).is_some())
+    }
+
+    /// A value known now: a number, constant or label defined earlier
+    fn lookup(&self, name: &str) -> Option<f64> {
+        if name == "HERE" {
+            return Some(self.here as f64);
+        }
+        self.constants
+            .get(name)
+            .copied()
+            .or_else(|| self.symbols.labels.get(name).map(|&address| address as f64))
+    }
+
+    fn constant(&mut self, bits: u32) -> Result<i64, CompileError> {
+        let token = self.next()?;
+        let value = match parse_number(&token) {
+            Some(value) => value,
+            None => match self.lookup(&token) {
+                Some(value) => value.floor() as i64,
+                None => return Err(self.error(format!("Undefined name '{}'.", token))),
+            },
+        };
+        self.check_range(value, bits)
+    }
+
+    /// Check `value` fits in `bits`. Bytes may be signed.
+    fn check_range(&self, value: i64, bits: u32) -> Result<i64, CompileError> {
+        let min = if bits == 8 { -128 } else { 0 };
+        if value < min || value >= 1 << bits {
+            return Err(self.error(format!(
+                "The value {} does not fit in {} bits.",
+                value, bits
+            )));
+        }
+        Ok(value & ((1 << bits) - 1))
+    }
+
+    /// An address that may be a label defined later, which is fixed up at `here`
+    fn address(&mut self, kind: Fixup, bits: u32) -> Result<u16, CompileError> {
+        let token = self.peek().unwrap_or_default().to_string();
+        if parse_number(&token).is_none() && self.lookup(&token).is_none() && is_name(&token) {
+            self.next()?;
+            self.fixup(kind, &token);
+            return Ok(0);
+        }
+        Ok(self.constant(bits)? as u16)
+    }
+
+    /// A `{ expression }`
+    fn expression(&mut self) -> Result<f64, CompileError> {
+        self.expect("{")?;
+        let mut tokens = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.next()?;
+            match token.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            tokens.push(token);
+        }
+        calc::evaluate(&tokens, &|name| self.lookup(name)).map_err(|message| self.error(message))
+    }
+
+    fn operand(&mut self) -> Result<Operand, CompileError> {
+        if self.is_register(self.peek()) {
+            return Ok(Operand::Register(self.register()?));
+        }
+        Ok(Operand::Byte(self.constant(8)? as u8))
+    }
+
+    fn define_label(&mut self, name: String, address: u16) -> Result<(), CompileError> {
+        if !is_name(&name) {
+            return Err(self.error(format!("'{}' is not a valid name.", name)));
+        }
+        if self.symbols.labels.contains_key(&name) || self.constants.contains_key(&name) {
+            return Err(self.error(format!("The name '{}' has already been defined.", name)));
+        }
+        self.symbols.labels.insert(name, address);
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), CompileError> {
+        let token = self.next()?;
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                if name == "main" && self.main_jump && self.here == PROGRAM_START + 2 {
+                    // Nothing comes before main, so there's no need to jump to it
+                    self.rom.clear();
+                    self.here = PROGRAM_START;
+                    self.fixups.retain(|(_, _, name, _)| name != "main");
+                }
+                self.main_jump = false;
+                self.define_label(name, self.here)?;
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.define_label(name, self.here + 1)?;
+            }
+            ":org" => {
+                let address = self.constant(16)? as u16;
+                if address < PROGRAM_START {
+                    return Err(self.error(format!(
+                        "Can't compile below {:#X}, at {:#X}.",
+                        PROGRAM_START, address
+                    )));
+                }
+                self.here = address;
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.constant(16)?;
+                self.define_constant(name, value as f64)?;
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.expression()?;
+                self.define_constant(name, value)?;
+            }
+            ":alias" => {
+                let name = self.next()?;
+                if !is_name(&name) {
+                    return Err(self.error(format!("'{}' is not a valid name.", name)));
+                }
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":byte" => {
+                let value = if self.peek() == Some("{") {
+                    let value = self.expression()?.floor() as i64;
+                    self.check_range(value, 8)?
+                } else {
+                    self.constant(8)?
+                };
+                self.emit(&[value as u8])?;
+            }
+            ":pointer" => {
+                let value = if self.peek() == Some("{") {
+                    let value = self.expression()?.floor() as i64;
+                    self.check_range(value, 16)? as u16
+                } else {
+                    self.address(Fixup::Word, 16)?
+                };
+                self.emit(&value.to_be_bytes())?;
+            }
+            ":unpack" => {
+                let nibble = self.constant(4)? as u16;
+                let address = self.address(Fixup::UnpackHigh, 12)?;
+                if let Some(fixup) = self.fixups.last_mut().filter(|fixup| fixup.0 == self.here) {
+                    // The low byte goes in the second instruction
+                    let (_, _, name, line) = fixup.clone();
+                    self.fixups
+                        .push((self.here + 2, Fixup::UnpackLow, name, line));
+                }
+                self.instruction(0x6000 | nibble << 4 | address >> 8)?;
+                self.instruction(0x6100 | (address & 0xFF))?;
+            }
+            ":breakpoint" => {
+                let name = self.next()?;
+                self.symbols.breakpoints.insert(self.here, name);
+            }
+            ":monitor" => {
+                // Octo's memory monitors don't affect the program
+                self.next()?;
+                self.next()?;
+            }
+            ":assert" => {
+                let message = match self.peek() {
+                    Some(message) if message.starts_with('"') => {
+                        let message = message.trim_matches('"').to_string();
+                        self.next()?;
+                        message
+                    }
+                    _ => "Assertion failed.".to_string(),
+                };
+                if self.expression()? == 0.0 {
+                    return Err(self.error(message));
+                }
+            }
+            ":macro" => self.define_macro()?,
+            ":call" => {
+                let address = self.address(Fixup::Address, 12)?;
+                self.instruction(0x2000 | address)?;
+            }
+            ";" | "return" => self.instruction(0x00EE)?,
+            "clear" => self.instruction(0x00E0)?,
+            "hires" => self.instruction(0x00FF)?,
+            "lores" => self.instruction(0x00FE)?,
+            "scroll-down" => {
+                let n = self.constant(4)? as u16;
+                self.instruction(0x00C0 | n)?;
+            }
+            "scroll-up" => {
+                let n = self.constant(4)? as u16;
+                self.instruction(0x00D0 | n)?;
+            }
+            "scroll-right" => self.instruction(0x00FB)?,
+            "scroll-left" => self.instruction(0x00FC)?,
+            "exit" => self.instruction(0x00FD)?,
+            "audio" => self.instruction(0xF002)?,
+            "plane" => {
+                let n = self.constant(4)? as u16;
+                self.instruction(0xF001 | n << 8)?;
+            }
+            "bcd" => self.register_instruction(0xF033)?,
+            "saveflags" => self.register_instruction(0xF075)?,
+            "loadflags" => self.register_instruction(0xF085)?,
+            "save" | "load" => {
+                let x = self.register()? as u16;
+                if self.peek() == Some("-") {
+                    self.next()?;
+                    let y = self.register()? as u16;
+                    let n = if token == "save" { 2 } else { 3 };
+                    self.instruction(0x5000 | x << 8 | y << 4 | n)?;
+                } else {
+                    let nn = if token == "save" { 0x55 } else { 0x65 };
+                    self.instruction(0xF000 | x << 8 | nn)?;
+                }
+            }
+            "sprite" => {
+                let x = self.register()? as u16;
+                let y = self.register()? as u16;
+                let n = self.constant(4)? as u16;
+                self.instruction(0xD000 | x << 8 | y << 4 | n)?;
+            }
+            "jump" => {
+                let address = self.address(Fixup::Address, 12)?;
+                self.instruction(0x1000 | address)?;
+            }
+            "jump0" => {
+                let address = self.address(Fixup::Address, 12)?;
+                self.instruction(0xB000 | address)?;
+            }
+            "native" => {
+                let address = self.address(Fixup::Address, 12)?;
+                self.instruction(address)?;
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let nn = match token.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.register_instruction(0xF000 | nn)?;
+            }
+            "i" => self.index_statement()?,
+            "if" => self.if_statement()?,
+            "else" => {
+                let Some((
+                    Control::Branch {
+                        keyword: "begin",
+                        jump,
+                    },
+                    line,
+                )) = self.control.pop()
+                else {
+                    return Err(self.error("This 'else' does not have a matching 'begin'."));
+                };
+                let else_jump = self.here;
+                self.instruction(0x1000)?;
+                let target = self.twelve_bits(self.here, "this 'else'")?;
+                self.patch(jump, Fixup::Address, target);
+                self.control.push((
+                    Control::Branch {
+                        keyword: "else",
+                        jump: else_jump,
+                    },
+                    line,
+                ));
+            }
+            "end" => {
+                let Some((Control::Branch { jump, .. }, _)) = self.control.pop() else {
+                    return Err(self.error("This 'end' does not have a matching 'begin'."));
+                };
+                let target = self.twelve_bits(self.here, "this 'end'")?;
+                self.patch(jump, Fixup::Address, target);
+            }
+            "loop" => self.control.push((
+                Control::Loop {
+                    start: self.here,
+                    exits: Vec::new(),
+                },
+                self.line,
+            )),
+            "while" => {
+                let condition = self.condition()?;
+                self.skip_when(condition)?;
+                let exit = self.here;
+                self.instruction(0x1000)?;
+                match self
+                    .control
+                    .iter_mut()
+                    .rev()
+                    .find_map(|(control, _)| match control {
+                        Control::Loop { exits, .. } => Some(exits),
+                        _ => None,
+                    }) {
+                    Some(exits) => exits.push(exit),
+                    None => return Err(self.error("This 'while' is not inside a loop.")),
+                }
+            }
+            "again" => {
+                let Some((Control::Loop { start, exits }, _)) = self.control.pop() else {
+                    return Err(self.error("This 'again' does not have a matching 'loop'."));
+                };
+                let start = self.twelve_bits(start, "this 'loop'")?;
+                self.instruction(0x1000 | start)?;
+                if !exits.is_empty() {
+                    let target = self.twelve_bits(self.here, "this 'again'")?;
+                    for exit in exits {
+                        self.patch(exit, Fixup::Address, target);
+                    }
+                }
+            }
+            _ if self.is_register(Some(&token)) => {
+                let x = self.to_register(&token).unwrap();
+                self.register_statement(x)?;
+            }
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            _ => {
+                if let Some(value) = parse_number(&token) {
+                    // Bare numbers are data, like sprites
+                    let value = self.check_range(value, 8)?;
+                    self.emit(&[value as u8])?;
+                } else if let Some(&value) = self.constants.get(&token) {
+                    let value = self.check_range(value.floor() as i64, 8)?;
+                    self.emit(&[value as u8])?;
+                } else if is_name(&token) {
+                    // Anything else is a subroutine call
+                    self.tokens.push_front(Token {
+                        text: token,
+                        line: self.line,
+                        depth: self.depth,
+                    });
+                    let address = self.address(Fixup::Address, 12)?;
+                    self.instruction(0x2000 | address)?;
+                } else {
+                    return Err(self.error(format!("Unrecognized token '{}'.", token)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn define_constant(&mut self, name: String, value: f64) -> Result<(), CompileError> {
+        if !is_name(&name) {
+            return Err(self.error(format!("'{}' is not a valid name.", name)));
+        }
+        if self.symbols.labels.contains_key(&name) {
+            return Err(self.error(format!("The name '{}' has already been defined.", name)));
+        }
+        self.symbols
+            .constants
+            .insert(name.clone(), value.floor() as i64 as u16);
+        self.constants.insert(name, value);
+        Ok(())
+    }
+
+    /// An FX__ instruction
+    fn register_instruction(&mut self, opcode: u16) -> Result<(), CompileError> {
+        let x = self.register()? as u16;
+        self.instruction(opcode | x << 8)?;
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), CompileError> {
+        let x = x as u16;
+        let operator = self.next()?;
+        let arithmetic = |n: u16| move |y: u16| 0x8000 | x << 8 | y << 4 | n;
+        match operator.as_str() {
+            ":=" => match self.peek() {
+                Some("delay") => {
+                    self.next()?;
+                    self.instruction(0xF007 | x << 8)?;
+                }
+                Some("key") => {
+                    self.next()?;
+                    self.instruction(0xF00A | x << 8)?;
+                }
+                Some("random") => {
+                    self.next()?;
+                    let nn = self.constant(8)? as u16;
+                    self.instruction(0xC000 | x << 8 | nn)?;
+                }
+                _ => match self.operand()? {
+                    Operand::Register(y) => self.instruction(arithmetic(0)(y as u16))?,
+                    Operand::Byte(nn) => self.instruction(0x6000 | x << 8 | nn as u16)?,
+                },
+            },
+            "+=" => match self.operand()? {
+                Operand::Register(y) => self.instruction(arithmetic(4)(y as u16))?,
+                Operand::Byte(nn) => self.instruction(0x7000 | x << 8 | nn as u16)?,
+            },
+            "-=" => match self.operand()? {
+                Operand::Register(y) => self.instruction(arithmetic(5)(y as u16))?,
+                Operand::Byte(nn) => {
+                    self.instruction(0x7000 | x << 8 | nn.wrapping_neg() as u16)?
+                }
+            },
+            "|=" | "&=" | "^=" | "=-" | ">>=" | "<<=" => {
+                let n = match operator.as_str() {
+                    "|=" => 1,
+                    "&=" => 2,
+                    "^=" => 3,
+                    "=-" => 7,
+                    ">>=" => 6,
+                    _ => 0xE,
+                };
+                let y = self.register()? as u16;
+                self.instruction(arithmetic(n)(y))?;
+            }
+            _ => {
+                return Err(self.error(format!("Unrecognized operator '{}'.", operator)));
+            }
+        }
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), CompileError> {
+        let operator = self.next()?;
+        match operator.as_str() {
+            ":=" => match self.peek() {
+                Some("hex") => {
+                    self.next()?;
+                    self.register_instruction(0xF029)?;
+                }
+                Some("bighex") => {
+                    self.next()?;
+                    self.register_instruction(0xF030)?;
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.instruction(0xF000)?;
+                    let address = self.address(Fixup::Word, 16)?;
+                    self.emit(&address.to_be_bytes())?;
+                }
+                _ => {
+                    let address = self.address(Fixup::Address, 12)?;
+                    self.instruction(0xA000 | address)?;
+                }
+            },
+            "+=" => self.register_instruction(0xF01E)?,
+            _ => {
+                return Err(self.error(format!("Unrecognized operator '{}'.", operator)));
+            }
+        }
+        Ok(())
+    }
+
+    fn condition(&mut self) -> Result<Condition, CompileError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        Ok(match operator.as_str() {
+            "key" => Condition::Key(x),
+            "-key" => Condition::NotKey(x),
+            "==" => Condition::Equal(x, self.operand()?),
+            "!=" => Condition::NotEqual(x, self.operand()?),
+            "<" => Condition::Less(x, self.operand()?),
+            ">" => Condition::Greater(x, self.operand()?),
+            "<=" => Condition::LessOrEqual(x, self.operand()?),
+            ">=" => Condition::GreaterOrEqual(x, self.operand()?),
+            _ => {
+                return Err(self.error(format!(
+                    "Expected a conditional operator, got '{}'.",
+                    operator
+                )))
+            }
+        })
+    }
+
+    /// Emit instructions that skip the next one when `condition` holds.
+    /// Comparisons use vf: `vf := rhs`, then a subtraction leaves its no-borrow flag in vf.
+    fn skip_when(&mut self, condition: Condition) -> Result<(), CompileError> {
+        let compare = |compiler: &mut Compiler, x: u8, rhs: Operand, subtract: u16| {
+            match rhs {
+                Operand::Register(y) => compiler.instruction(0x8F00 | (y as u16) << 4)?,
+                Operand::Byte(nn) => compiler.instruction(0x6F00 | nn as u16)?,
+            }
+            compiler.instruction(0x8F00 | (x as u16) << 4 | subtract)
+        };
+        match condition {
+            Condition::Equal(x, Operand::Byte(nn)) => {
+                self.instruction(0x3000 | (x as u16) << 8 | nn as u16)?
+            }
+            Condition::NotEqual(x, Operand::Byte(nn)) => {
+                self.instruction(0x4000 | (x as u16) << 8 | nn as u16)?
+            }
+            Condition::Equal(x, Operand::Register(y)) => {
+                self.instruction(0x5000 | (x as u16) << 8 | (y as u16) << 4)?
+            }
+            Condition::NotEqual(x, Operand::Register(y)) => {
+                self.instruction(0x9000 | (x as u16) << 8 | (y as u16) << 4)?
+            }
+            Condition::Key(x) => self.instruction(0xE09E | (x as u16) << 8)?,
+            Condition::NotKey(x) => self.instruction(0xE0A1 | (x as u16) << 8)?,
+            // vf =- vx leaves vf = 1 when vx >= rhs
+            Condition::Less(x, rhs) => {
+                compare(self, x, rhs, 7)?;
+                self.instruction(0x3F00)?;
+            }
+            Condition::GreaterOrEqual(x, rhs) => {
+                compare(self, x, rhs, 7)?;
+                self.instruction(0x3F01)?;
+            }
+            // vf -= vx leaves vf = 1 when rhs >= vx
+            Condition::Greater(x, rhs) => {
+                compare(self, x, rhs, 5)?;
+                self.instruction(0x3F00)?;
+            }
+            Condition::LessOrEqual(x, rhs) => {
+                compare(self, x, rhs, 5)?;
+                self.instruction(0x3F01)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn if_statement(&mut self) -> Result<(), CompileError> {
+        let line = self.line;
+        let condition = self.condition()?;
+        match self.next()?.as_str() {
+            // Skip the next statement unless the condition holds
+            "then" => self.skip_when(condition.negate())?,
+            // Skip the jump past the block when the condition holds
+            "begin" => {
+                self.skip_when(condition)?;
+                let jump = self.here;
+                self.instruction(0x1000)?;
+                self.control.push((
+                    Control::Branch {
+                        keyword: "begin",
+                        jump,
+                    },
+                    line,
+                ));
+            }
+            other => {
+                return Err(self.error(format!("Expected 'then' or 'begin', got '{}'.", other)))
+            }
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), CompileError> {
+        let name = self.next()?;
+        if !is_name(&name) {
+            return Err(self.error(format!("'{}' is not a valid name.", name)));
+        }
+        let mut arguments = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            arguments.push(token);
+        }
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self
+                .tokens
+                .pop_front()
+                .ok_or_else(|| self.error(format!("The macro '{}' is missing its '}}'.", name)))?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { arguments, body });
+        Ok(())
+    }
+
+    /// Replace a macro call with its body. The body's tokens take the call's line,
+    /// so errors and the symbol map point at the call.
+    fn expand_macro(&mut self, name: &str) -> Result<(), CompileError> {
+        let depth = self.depth + 1;
+        if depth > MAX_MACRO_DEPTH {
+            return Err(self.error(format!(
+                "Macro '{}' expands more than {} levels deep; does it call itself?",
+                name, MAX_MACRO_DEPTH
+            )));
+        }
+        let count = self.macros[name].arguments.len();
+        let mut values = Vec::with_capacity(count);
+        for _ in 0..count {
+            values.push(self.next()?);
+        }
+        let line = self.line;
+        let definition = &self.macros[name];
+        let expansion: Vec<Token> = definition
+            .body
+            .iter()
+            .map(|token| {
+                let text = match definition
+                    .arguments
+                    .iter()
+                    .position(|arg| *arg == token.text)
+                {
+                    Some(index) => values[index].clone(),
+                    None => token.text.clone(),
+                };
+                Token { text, line, depth }
+            })
+            .collect();
+        for token in expansion.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Program, CompileError> {
+        if let Some((control, line)) = self.control.last() {
+            self.line = *line;
+            return Err(self.error(match control {
+                Control::Branch { keyword, .. } => {
+                    format!("This '{}' does not have a matching 'end'.", keyword)
+                }
+                Control::Loop { .. } => "This 'loop' does not have a matching 'again'.".to_string(),
+            }));
+        }
+        if !self.symbols.labels.contains_key("main") {
+            self.line = 1;
+            return Err(self.error("This program is missing a 'main' label."));
+        }
+
+        for (address, kind, name, line) in std::mem::take(&mut self.fixups) {
+            self.line = line;
+            let Some(&value) = self.symbols.labels.get(&name) else {
+                return Err(self.error(format!("Undefined name '{}'.", name)));
+            };
+            if matches!(kind, Fixup::Address | Fixup::UnpackHigh) {
+                self.twelve_bits(value, &format!("'{}'", name))?;
+            }
+            self.patch(address, kind, value);
+        }
+
+        Ok(Program {
+            rom: self.rom,
+            symbols: self.symbols,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compile, CompileError};
+
+    fn error(source: &str) -> CompileError {
+        compile(source).unwrap_err()
+    }
+
+    #[test]
+    fn compiles_conditionals_and_loops() {
+        let program = compile(
+            ": main
+               loop
+                 v0 += 1
+                 while v0 != 10
+                 if v1 > 3 begin v1 := 0 else v1 += 1 end
+               again",
+        )
+        .unwrap();
+        #[rustfmt::skip]
+        assert_eq!(program.rom, [
+            0x70, 0x01, // 200: v0 += 1
+            0x40, 0x0A, // 202: skip if v0 != 10
+            0x12, 0x16, // 204: jump out of the loop
+            0x6F, 0x03, // 206: vf := 3
+            0x8F, 0x15, // 208: vf -= v1
+            0x3F, 0x00, // 20A: skip if v1 > 3
+            0x12, 0x12, // 20C: jump to else
+            0x61, 0x00, // 20E: v1 := 0
+            0x12, 0x14, // 210: jump to end
+            0x71, 0x01, // 212: v1 += 1
+            0x12, 0x00, // 214: again
+        ]);
+        assert_eq!(program.symbols.lines[&0x20E], 5);
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert_eq!(
+            error(": main\n  v0 := 1\n  jump nowhere"),
+            CompileError {
+                line: 3,
+                message: "Undefined name 'nowhere'.".to_string()
+            }
+        );
+        assert_eq!(error(": main\nloop\nv0 := 1").line, 2);
+        assert_eq!(
+            error(": main v0 := 256").message,
+            "The value 256 does not fit in 8 bits."
+        );
+        assert_eq!(
+            error(": main v0 ~= v1").message,
+            "Unrecognized operator '~='."
+        );
+        assert_eq!(
+            error(": start ;").message,
+            "This program is missing a 'main' label."
+        );
+        assert_eq!(
+            error(": main :assert \"too big\" { 3 < 2 }").message,
+            "too big"
+        );
+        assert_eq!(
+            error(": main :org 0xFFFF 1 2").message,
+            "The program does not fit in 64 KiB of memory."
+        );
+        assert_eq!(
+            error(": main :org 0xFFA loop v0 += 1 while v0 != 3 again").message,
+            "The address of this 'again' does not fit in 12 bits."
+        );
+        assert_eq!(
+            error(": main :org 0x1000 loop again").message,
+            "The address of this 'loop' does not fit in 12 bits."
+        );
+        assert_eq!(
+            error(": main :org 0xFFC if v0 == 1 begin v0 := 2 end").message,
+            "The address of this 'end' does not fit in 12 bits."
+        );
+        assert_eq!(
+            error(":macro boom { boom } : main boom").message,
+            "Macro 'boom' expands more than 256 levels deep; does it call itself?"
+        );
+        assert_eq!(
+            error(":alias clear v1 : main clear").message,
+            "'clear' is not a valid name."
+        );
+    }
+}
@@ -0,0 +1,8 @@
+//! A compiler for Octo, the chip8 assembly language: <https://github.com/JohnEarnest/Octo>
+
+mod calc;
+mod compiler;
+pub use compiler::{compile, CompileError, Program};
+pub mod symbols;
+pub use symbols::SymbolMap;
+mod token;
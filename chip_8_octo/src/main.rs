@@ -0,0 +1,47 @@
+use chip_8_octo::compile;
+use clap::Parser;
+use std::{fs, path::PathBuf, process};
+
+/// Compile Octo assembly to a chip8 ROM
+#[derive(Parser)]
+#[command(author, version, about)]
+struct Args {
+    /// Octo source file
+    source: PathBuf,
+
+    /// ROM file to write. Defaults to the source file with a .ch8 extension.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Also write a symbol map of labels and source lines, for the debugger
+    #[arg(long)]
+    symbols: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Args::parse();
+    if let Err(err) = run(&args) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let source = fs::read_to_string(&args.source)
+        .map_err(|err| format!("can't read {}: {}", args.source.display(), err))?;
+    let mut program = compile(&source)
+        .map_err(|err| format!("{}:{}: {}", args.source.display(), err.line, err.message))?;
+
+    let output = args
+        .output
+        .clone()
+        .unwrap_or_else(|| args.source.with_extension("ch8"));
+    fs::write(&output, &program.rom)
+        .map_err(|err| format!("can't write {}: {}", output.display(), err))?;
+    if let Some(path) = &args.symbols {
+        program.symbols.source = Some(args.source.display().to_string());
+        fs::write(path, program.symbols.to_text())
+            .map_err(|err| format!("can't write {}: {}", path.display(), err))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! Where a compiled program's labels and source lines ended up, for debuggers.
+//!
+//! Symbol map files have an entry per line, e.g.
+//!
+//! ```text
+//! source game.8o
+//! label 0202 main
+//! const 0040 WIDTH
+//! breakpoint 0210 after-draw
+//! line 0202 7
+//! ```
+//!
+//! Constants are written as 16 bit values. `line` entries give the source line
+//! each instruction was compiled from.
+
+use std::{collections::BTreeMap, fmt::Write};
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SymbolMap {
+    /// The source file, as given to the compiler
+    pub source: Option<String>,
+    /// Label addresses, by name
+    pub labels: BTreeMap<String, u16>,
+    /// Constant values, by name
+    pub constants: BTreeMap<String, u16>,
+    /// `:breakpoint` names, by address
+    pub breakpoints: BTreeMap<u16, String>,
+    /// Source lines, counting from 1, by instruction address
+    pub lines: BTreeMap<u16, usize>,
+}
+
+impl SymbolMap {
+    /// The names of the labels at `address`
+    pub fn labels_at(&self, address: u16) -> impl Iterator<Item = &str> {
+        self.labels
+            .iter()
+            .filter(move |&(_, &label)| label == address)
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// The label at or before `address`, and how far past it `address` is
+    pub fn locate(&self, address: u16) -> Option<(&str, u16)> {
+        self.labels
+            .iter()
+            .filter(|&(_, &label)| label <= address)
+            .max_by_key(|&(name, &label)| (label, std::cmp::Reverse(name)))
+            .map(|(name, &label)| (name.as_str(), address - label))
+    }
+
+    /// The symbol map file contents
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        if let Some(source) = &self.source {
+            let _ = writeln!(text, "source {}", source);
+        }
+        let mut labels: Vec<(&String, &u16)> = self.labels.iter().collect();
+        labels.sort_by_key(|&(name, address)| (*address, name));
+        for (name, address) in labels {
+            let _ = writeln!(text, "label {:04X} {}", address, name);
+        }
+        for (name, value) in &self.constants {
+            let _ = writeln!(text, "const {:04X} {}", value, name);
+        }
+        for (address, name) in &self.breakpoints {
+            let _ = writeln!(text, "breakpoint {:04X} {}", address, name);
+        }
+        for (address, line) in &self.lines {
+            let _ = writeln!(text, "line {:04X} {}", address, line);
+        }
+        text
+    }
+
+    /// Read a symbol map file
+    pub fn parse(text: &str) -> Result<SymbolMap, String> {
+        let mut symbols = SymbolMap::default();
+        for (index, line) in text.lines().enumerate() {
+            let invalid = || format!("line {}: invalid symbol map entry `{}`", index + 1, line);
+            if let Some(source) = line.strip_prefix("source ") {
+                symbols.source = Some(source.to_string());
+                continue;
+            }
+            let mut fields = line.splitn(3, ' ');
+            let (Some(kind), Some(address), Some(value)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                return Err(invalid());
+            };
+            let address = u16::from_str_radix(address, 16).map_err(|_| invalid())?;
+            match kind {
+                "label" => {
+                    symbols.labels.insert(value.to_string(), address);
+                }
+                "const" => {
+                    symbols.constants.insert(value.to_string(), address);
+                }
+                "breakpoint" => {
+                    symbols.breakpoints.insert(address, value.to_string());
+                }
+                "line" => {
+                    let line = value.parse().map_err(|_| invalid())?;
+                    symbols.lines.insert(address, line);
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(symbols)
+    }
+}
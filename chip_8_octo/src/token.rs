@@ -0,0 +1,62 @@
+/// A word of Octo source. Octo separates everything with whitespace,
+/// so `v0 := 1` is three tokens but `v0:=1` is one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    /// Line number, counting from 1
+    pub line: usize,
+    /// How many macro expansions this came from, 0 for source
+    pub depth: usize,
+}
+
+/// Split source into tokens, dropping `#` comments. A quoted string is one token.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (index, line) in source.lines().enumerate() {
+        let mut rest = line;
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() || rest.starts_with('#') {
+                break;
+            }
+            let len = match rest.strip_prefix('"') {
+                Some(string) => string.find('"').map_or(rest.len(), |end| end + 2),
+                None => rest.find(char::is_whitespace).unwrap_or(rest.len()),
+            };
+            tokens.push(Token {
+                text: rest[..len].to_string(),
+                line: index + 1,
+                depth: 0,
+            });
+            rest = &rest[len..];
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod test {
+    use super::tokenize;
+
+    #[test]
+    fn splits_on_whitespace() {
+        let tokens = tokenize("v0 := 1 # set v0\n:assert \"too big\" { 1 }");
+        let texts: Vec<(&str, usize)> = tokens
+            .iter()
+            .map(|token| (token.text.as_str(), token.line))
+            .collect();
+        assert_eq!(
+            texts,
+            [
+                ("v0", 1),
+                (":=", 1),
+                ("1", 1),
+                (":assert", 2),
+                ("\"too big\"", 2),
+                ("{", 2),
+                ("1", 2),
+                ("}", 2),
+            ]
+        );
+    }
+}
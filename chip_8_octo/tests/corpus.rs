@@ -0,0 +1,44 @@
+use std::{fs, path::Path};
+
+use chip_8_octo::compile;
+
+#[test]
+fn compiles_corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut compiled = 0;
+    for entry in fs::read_dir(&corpus).unwrap() {
+        let source = entry.unwrap().path();
+        if source.extension().is_none_or(|extension| extension != "8o") {
+            continue;
+        }
+        let expected = fs::read(source.with_extension("ch8")).unwrap();
+        let program = compile(&fs::read_to_string(&source).unwrap())
+            .unwrap_or_else(|err| panic!("{}: {}", source.display(), err));
+        assert_eq!(
+            program.rom,
+            expected,
+            "{} doesn't match its binary",
+            source.display()
+        );
+        compiled += 1;
+    }
+    assert_eq!(compiled, 4);
+}
+
+#[test]
+fn maps_symbols() {
+    let source =
+        fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus/macros.8o"))
+            .unwrap();
+    let symbols = compile(&source).unwrap().symbols;
+    assert_eq!(symbols.labels["patch-target"], 0x211);
+    assert_eq!(symbols.labels["table"], 0x214);
+    assert_eq!(symbols.constants["HALF"], 32);
+    assert_eq!(symbols.locate(0x216), Some(("table", 2)));
+    // Macro expansions map to the line calling the macro
+    assert_eq!(symbols.lines[&0x202], 9);
+    assert_eq!(
+        chip_8_octo::SymbolMap::parse(&symbols.to_text()),
+        Ok(symbols)
+    );
+}
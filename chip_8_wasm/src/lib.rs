@@ -1,6 +1,22 @@
-use std::f64;
+mod wasm_chip_8;
+pub use wasm_chip_8::WasmChip8;
+
+use std::{cell::RefCell, collections::BTreeSet, rc::Rc};
+
+use chip_8_core::{chip_8, debugger::Stop, globals, Chip8, Debugger, Palette};
+use js_sys::Uint8Array;
 use wasm_bindgen::prelude::*;
-use web_sys::{console, CanvasRenderingContext2d, Element, HtmlCanvasElement};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    console, CanvasRenderingContext2d, DragEvent, Event, File, HtmlButtonElement,
+    HtmlCanvasElement, HtmlInputElement, HtmlSelectElement, KeyboardEvent, Window,
+};
+
+type AnimationFrameCallback = Closure<dyn FnMut(f64)>;
+
+const MS_PER_FRAME: f64 = 1000.0 / globals::FRAMES_PER_SECOND as f64;
+// Don't try to catch up on more than a few frames, e.g. after the tab was in the background
+const MAX_LAG_MS: f64 = MS_PER_FRAME * 4.0;
 
 // println!-like macro for console.log()
 macro_rules! console_log {
@@ -17,7 +33,7 @@ impl<T> ExpectLog for Option<T> {
 
     fn expect_log(self, msg: &str) -> Self::T {
         match self {
-            Some(val) => return val,
+            Some(val) => val,
             None => {
                 console_log!("{}", msg);
                 panic!();
@@ -31,7 +47,7 @@ impl<T, E> ExpectLog for Result<T, E> {
 
     fn expect_log(self, msg: &str) -> Self::T {
         match self {
-            Ok(val) => return val,
+            Ok(val) => val,
             Err(_) => {
                 console_log!("{}", msg);
                 panic!();
@@ -40,49 +56,305 @@ impl<T, E> ExpectLog for Result<T, E> {
     }
 }
 
+/// Create a Chip8 with its random number generator seeded from `Math.random()`
+fn new_chip_8() -> Chip8 {
+    Chip8::with_seed((js_sys::Math::random() * u64::MAX as f64) as u64)
+}
+
+/// Map a `KeyboardEvent.code` to a chip8 key, using the same layout as the desktop front-ends
+fn chip_8_key(code: &str) -> Option<u8> {
+    let key = match code {
+        "Digit1" => 1,
+        "Digit2" => 2,
+        "Digit3" => 3,
+        "Digit4" => 0xC,
+        "KeyQ" => 4,
+        "KeyW" => 5,
+        "KeyE" => 6,
+        "KeyR" => 0xD,
+        "KeyA" => 7,
+        "KeyS" => 8,
+        "KeyD" => 9,
+        "KeyF" => 0xE,
+        "KeyZ" => 0xA,
+        "KeyX" => 0,
+        "KeyC" => 0xB,
+        "KeyV" => 0xF,
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Emulator state shared between the animation loop and the DOM event handlers
+struct Emulator {
+    chip_8: Chip8,
+    // Runs frames, stopping before an instruction that would crash the emulator
+    debugger: Debugger,
+    // The loaded ROM, kept around so the emulator can be reset
+    rom: Option<Vec<u8>>,
+    held_keys: BTreeSet<u8>,
+    paused: bool,
+    // Whether the ROM hit a fault, which stops it until it's reset or another is loaded
+    faulted: bool,
+    palette: Palette,
+
+    // Timestamp of the last animation frame, and the time owed to the emulator since then
+    last_timestamp: Option<f64>,
+    lag: f64,
+}
+
+impl Emulator {
+    fn new() -> Self {
+        Emulator {
+            chip_8: new_chip_8(),
+            debugger: Debugger::new(),
+            rom: None,
+            held_keys: BTreeSet::new(),
+            paused: false,
+            faulted: false,
+            palette: Palette::default(),
+            last_timestamp: None,
+            lag: 0.0,
+        }
+    }
+
+    fn load_rom(&mut self, rom: Vec<u8>) {
+        self.rom = Some(rom);
+        self.reset();
+    }
+
+    /// Restart the loaded ROM from a fresh Chip8
+    fn reset(&mut self) {
+        self.chip_8 = new_chip_8();
+        self.faulted = false;
+        self.lag = 0.0;
+        if let Some(rom) = &self.rom {
+            if let Err(err) = self.chip_8.load_rom(rom) {
+                console_log!("Couldn't load ROM: {}", err);
+                self.rom = None;
+            }
+        }
+    }
+
+    /// Run as many 60 Hz frames as are due at the given animation timestamp
+    fn update(&mut self, timestamp: f64) {
+        let Some(last_timestamp) = self.last_timestamp.replace(timestamp) else {
+            return;
+        };
+        if self.paused || self.faulted || self.rom.is_none() {
+            return;
+        }
+
+        self.lag = (self.lag + timestamp - last_timestamp).min(MAX_LAG_MS);
+        while self.lag >= MS_PER_FRAME {
+            let stop = self
+                .debugger
+                .run_frame(&mut self.chip_8, &self.held_keys, &mut ());
+            if let (Stop::Fault(pc), Some(fault)) = (stop, self.chip_8.fault()) {
+                console_log!("Stopped at {:04X}: {}", pc, fault);
+                self.faulted = true;
+                return;
+            }
+            self.lag -= MS_PER_FRAME;
+        }
+    }
+}
+
+fn draw(
+    context: &CanvasRenderingContext2d,
+    canvas: &HtmlCanvasElement,
+    display: &chip_8::Display,
+    palette: &Palette,
+) {
+    let square_width = canvas.width() as f64 / globals::DISPLAY_WIDTH as f64;
+    let square_height = canvas.height() as f64 / globals::DISPLAY_HEIGHT as f64;
+
+    context.set_fill_style_str(&palette.off().to_string());
+    context.fill_rect(0.0, 0.0, canvas.width() as f64, canvas.height() as f64);
+
+    context.set_fill_style_str(&palette.on().to_string());
+    for &(x, y) in display.iter() {
+        context.fill_rect(
+            x as f64 * square_width,
+            y as f64 * square_height,
+            square_width,
+            square_height,
+        );
+    }
+}
+
+/// Read a ROM file picked by the user, and load it into the emulator
+fn load_rom_file(emulator: Rc<RefCell<Emulator>>, file: File) {
+    wasm_bindgen_futures::spawn_local(async move {
+        match JsFuture::from(file.array_buffer()).await {
+            Ok(buffer) => {
+                console_log!("Loading ROM {}", file.name());
+                emulator
+                    .borrow_mut()
+                    .load_rom(Uint8Array::new(&buffer).to_vec());
+            }
+            Err(_) => console_log!("Couldn't read ROM file {}", file.name()),
+        }
+    });
+}
+
+fn request_animation_frame(window: &Window, f: &AnimationFrameCallback) {
+    window
+        .request_animation_frame(f.as_ref().unchecked_ref())
+        .expect_log("should be able to register `requestAnimationFrame`");
+}
+
 #[wasm_bindgen(start)]
-pub fn main() -> Result<(), JsValue> {
+pub fn start() -> Result<(), JsValue> {
     // Use `web_sys`'s global `window` function to get a handle on the global
     // window object.
     let window = web_sys::window().expect("no global `window` exists");
     let document = window.document().expect("should have a document on window");
-    let canvas = document
-        .get_element_by_id("canvas")
-        .expect_log("can't find element #canvas");
-
-    let canvas: HtmlCanvasElement = canvas.dyn_into().map_err(|_| ()).unwrap();
 
+    // Pages that embed WasmChip8 themselves don't have our #canvas; leave them alone
+    let Some(canvas) = document.get_element_by_id("canvas") else {
+        return Ok(());
+    };
+    let canvas: HtmlCanvasElement = canvas.dyn_into()?;
     let context: CanvasRenderingContext2d = canvas
-        .get_context("2d")
-        .unwrap()
-        .unwrap()
-        .dyn_into()
-        .unwrap();
-
-    context.begin_path();
-
-    // Draw the outer circle.
-    context
-        .arc(75.0, 75.0, 50.0, 0.0, f64::consts::PI * 2.0)
-        .unwrap();
-
-    // Draw the mouth.
-    context.move_to(110.0, 75.0);
-    context.arc(75.0, 75.0, 35.0, 0.0, f64::consts::PI).unwrap();
-
-    // Draw the left eye.
-    context.move_to(65.0, 65.0);
-    context
-        .arc(60.0, 65.0, 5.0, 0.0, f64::consts::PI * 2.0)
-        .unwrap();
-
-    // Draw the right eye.
-    context.move_to(95.0, 65.0);
-    context
-        .arc(90.0, 65.0, 5.0, 0.0, f64::consts::PI * 2.0)
-        .unwrap();
-
-    context.stroke();
+        .get_context("2d")?
+        .expect_log("can't get a 2d context for #canvas")
+        .dyn_into()?;
+    let rom_input: HtmlInputElement = document
+        .get_element_by_id("rom-input")
+        .expect_log("can't find element #rom-input")
+        .dyn_into()?;
+    let pause_button: HtmlButtonElement = document
+        .get_element_by_id("pause")
+        .expect_log("can't find element #pause")
+        .dyn_into()?;
+    let reset_button: HtmlButtonElement = document
+        .get_element_by_id("reset")
+        .expect_log("can't find element #reset")
+        .dyn_into()?;
+    let palette_select: HtmlSelectElement = document
+        .get_element_by_id("palette")
+        .expect_log("can't find element #palette")
+        .dyn_into()?;
+
+    let emulator = Rc::new(RefCell::new(Emulator::new()));
+
+    // Keyboard input
+    {
+        let emulator = emulator.clone();
+        let on_key_down = Closure::<dyn FnMut(_)>::new(move |event: KeyboardEvent| {
+            if let Some(key) = chip_8_key(&event.code()) {
+                event.prevent_default();
+                emulator.borrow_mut().held_keys.insert(key);
+            }
+        });
+        window.add_event_listener_with_callback("keydown", on_key_down.as_ref().unchecked_ref())?;
+        on_key_down.forget();
+    }
+    {
+        let emulator = emulator.clone();
+        let on_key_up = Closure::<dyn FnMut(_)>::new(move |event: KeyboardEvent| {
+            if let Some(key) = chip_8_key(&event.code()) {
+                emulator.borrow_mut().held_keys.remove(&key);
+            }
+        });
+        window.add_event_listener_with_callback("keyup", on_key_up.as_ref().unchecked_ref())?;
+        on_key_up.forget();
+    }
+
+    // ROM loading, through the file input or by dropping a file on the page
+    {
+        let emulator = emulator.clone();
+        let input = rom_input.clone();
+        let on_change = Closure::<dyn FnMut(_)>::new(move |_: Event| {
+            if let Some(file) = input.files().and_then(|files| files.get(0)) {
+                load_rom_file(emulator.clone(), file);
+            }
+        });
+        rom_input.add_event_listener_with_callback("change", on_change.as_ref().unchecked_ref())?;
+        on_change.forget();
+    }
+    {
+        // The page must accept dragover for drop to fire
+        let on_drag_over = Closure::<dyn FnMut(_)>::new(move |event: DragEvent| {
+            event.prevent_default();
+        });
+        document
+            .add_event_listener_with_callback("dragover", on_drag_over.as_ref().unchecked_ref())?;
+        on_drag_over.forget();
+
+        let emulator = emulator.clone();
+        let on_drop = Closure::<dyn FnMut(_)>::new(move |event: DragEvent| {
+            event.prevent_default();
+            let file = event
+                .data_transfer()
+                .and_then(|data| data.files())
+                .and_then(|files| files.get(0));
+            if let Some(file) = file {
+                load_rom_file(emulator.clone(), file);
+            }
+        });
+        document.add_event_listener_with_callback("drop", on_drop.as_ref().unchecked_ref())?;
+        on_drop.forget();
+    }
+
+    // Pause/reset controls
+    {
+        let emulator = emulator.clone();
+        let button = pause_button.clone();
+        let on_click = Closure::<dyn FnMut(_)>::new(move |_: Event| {
+            let mut emulator = emulator.borrow_mut();
+            emulator.paused = !emulator.paused;
+            button.set_text_content(Some(if emulator.paused { "Resume" } else { "Pause" }));
+        });
+        pause_button
+            .add_event_listener_with_callback("click", on_click.as_ref().unchecked_ref())?;
+        on_click.forget();
+    }
+    {
+        let emulator = emulator.clone();
+        let on_click = Closure::<dyn FnMut(_)>::new(move |_: Event| {
+            emulator.borrow_mut().reset();
+        });
+        reset_button
+            .add_event_listener_with_callback("click", on_click.as_ref().unchecked_ref())?;
+        on_click.forget();
+    }
+    {
+        let emulator = emulator.clone();
+        let select = palette_select.clone();
+        let on_change =
+            Closure::<dyn FnMut(_)>::new(move |_: Event| match select.value().parse() {
+                Ok(palette) => emulator.borrow_mut().palette = palette,
+                Err(err) => console_log!("Couldn't use palette: {}", err),
+            });
+        palette_select
+            .add_event_listener_with_callback("change", on_change.as_ref().unchecked_ref())?;
+        on_change.forget();
+    }
+
+    // Game loop, driven by requestAnimationFrame.
+    // The closure has to reschedule itself, so it holds a reference to its own cell.
+    let animation_frame: Rc<RefCell<Option<AnimationFrameCallback>>> = Rc::new(RefCell::new(None));
+    let next_animation_frame = animation_frame.clone();
+    let loop_window = window.clone();
+    *animation_frame.borrow_mut() = Some(Closure::new(move |timestamp: f64| {
+        {
+            let mut emulator = emulator.borrow_mut();
+            emulator.update(timestamp);
+            draw(
+                &context,
+                &canvas,
+                &emulator.chip_8.display,
+                &emulator.palette,
+            );
+        }
+        request_animation_frame(
+            &loop_window,
+            next_animation_frame.borrow().as_ref().unwrap(),
+        );
+    }));
+    request_animation_frame(&window, animation_frame.borrow().as_ref().unwrap());
 
     Ok(())
 }
@@ -0,0 +1,179 @@
+use std::collections::BTreeSet;
+
+use chip_8_core::{debugger::Stop, globals, Chip8, Debugger};
+use js_sys::{Array, Object, Reflect, Uint8Array};
+use wasm_bindgen::prelude::*;
+
+use crate::new_chip_8;
+
+#[wasm_bindgen(typescript_custom_section)]
+const REGISTERS_TS: &'static str = r#"
+/** Snapshot of the CPU registers, as returned by `WasmChip8.registers()` */
+export interface Registers {
+  /** General purpose registers v0 through vF */
+  v: number[];
+  /** Index register */
+  i: number;
+  /** Program counter */
+  pc: number;
+  /** Number of return addresses on the stack */
+  sp: number;
+  /** Return addresses, oldest first */
+  stack: number[];
+  /** Delay timer */
+  dt: number;
+  /** Sound timer */
+  st: number;
+}
+"#;
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "Registers")]
+    pub type Registers;
+}
+
+/// A Chip8 emulator for embedding in web tools.
+///
+/// The host drives timing: call `run_frame` 60 times a second, then read the
+/// pixels through `framebuffer_ptr`, e.g.
+/// `new Uint8Array(wasm.memory.buffer, chip8.framebuffer_ptr(), chip8.width() * chip8.height())`.
+#[wasm_bindgen]
+pub struct WasmChip8 {
+    chip_8: Chip8,
+    // Runs frames, stopping before an instruction that would crash the emulator
+    debugger: Debugger,
+    // The loaded ROM, kept around so the emulator can be reset
+    rom: Vec<u8>,
+    // One byte per pixel, row-major: 1 if the pixel is lit, 0 otherwise
+    framebuffer: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl WasmChip8 {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmChip8 {
+        let mut wasm_chip_8 = WasmChip8 {
+            chip_8: new_chip_8(),
+            debugger: Debugger::new(),
+            rom: Vec::new(),
+            framebuffer: vec![0; (globals::DISPLAY_WIDTH * globals::DISPLAY_HEIGHT) as usize],
+        };
+        wasm_chip_8.update_framebuffer();
+        wasm_chip_8
+    }
+
+    /// Load a ROM into a fresh machine
+    pub fn load_rom(&mut self, rom: Uint8Array) -> Result<(), JsError> {
+        let rom = rom.to_vec();
+        let mut chip_8 = new_chip_8();
+        chip_8
+            .load_rom(&rom)
+            .map_err(|err| JsError::new(&err.to_string()))?;
+
+        self.chip_8 = chip_8;
+        self.rom = rom;
+        self.update_framebuffer();
+        Ok(())
+    }
+
+    /// Restart the loaded ROM
+    pub fn reset(&mut self) {
+        self.chip_8 = new_chip_8();
+        // The ROM fit when it was loaded, so it still fits
+        self.chip_8.load_rom(&self.rom).unwrap();
+        self.update_framebuffer();
+    }
+
+    /// Run one 60 Hz frame. Bit N of `keys_bitmask` is set if key N is held.
+    ///
+    /// Throws, leaving the machine where it stopped, instead of running an
+    /// instruction that would crash it, like a RET with an empty stack.
+    pub fn run_frame(&mut self, keys_bitmask: u16) -> Result<(), JsError> {
+        let held_keys: BTreeSet<u8> = (0..16)
+            .filter(|key| keys_bitmask & (1 << key) != 0)
+            .collect();
+        let stop = self
+            .debugger
+            .run_frame(&mut self.chip_8, &held_keys, &mut ());
+        self.update_framebuffer();
+        match (stop, self.chip_8.fault()) {
+            (Stop::Fault(pc), Some(fault)) => {
+                Err(JsError::new(&format!("stopped at {:#05X}: {}", pc, fault)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Pointer to the framebuffer in wasm memory, `width() * height()` bytes, one per pixel
+    pub fn framebuffer_ptr(&self) -> *const u8 {
+        self.framebuffer.as_ptr()
+    }
+
+    pub fn width(&self) -> u32 {
+        globals::DISPLAY_WIDTH as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        globals::DISPLAY_HEIGHT as u32
+    }
+
+    pub fn registers(&self) -> Registers {
+        let registers = Object::new();
+        let set = |key: &str, value: JsValue| {
+            Reflect::set(&registers, &key.into(), &value).unwrap();
+        };
+
+        let v: Array = self.chip_8.v.iter().map(|&v| JsValue::from(v)).collect();
+        let stack: Array = self
+            .chip_8
+            .stack
+            .iter()
+            .map(|&a| JsValue::from(a))
+            .collect();
+        set("v", v.into());
+        set("i", self.chip_8.i.into());
+        set("pc", self.chip_8.pc.into());
+        set("sp", self.chip_8.stack.len().into());
+        set("stack", stack.into());
+        set("dt", self.chip_8.dt.into());
+        set("st", self.chip_8.st.into());
+
+        registers.unchecked_into()
+    }
+
+    /// Serialize the machine state, for `load_state`
+    pub fn save_state(&self) -> Result<Uint8Array, JsError> {
+        let state = self
+            .chip_8
+            .save_state()
+            .map_err(|err| JsError::new(&err.to_string()))?;
+        Ok(Uint8Array::from(&state[..]))
+    }
+
+    /// Restore a machine state produced by `save_state`
+    pub fn load_state(&mut self, state: Uint8Array) -> Result<(), JsError> {
+        self.chip_8 =
+            Chip8::load_state(&state.to_vec()).map_err(|err| JsError::new(&err.to_string()))?;
+        self.update_framebuffer();
+        Ok(())
+    }
+}
+
+impl Default for WasmChip8 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WasmChip8 {
+    fn update_framebuffer(&mut self) {
+        self.framebuffer.fill(0);
+        for &(x, y) in self.chip_8.display.iter() {
+            if (0..globals::DISPLAY_WIDTH).contains(&x) && (0..globals::DISPLAY_HEIGHT).contains(&y)
+            {
+                self.framebuffer[(y * globals::DISPLAY_WIDTH + x) as usize] = 1;
+            }
+        }
+    }
+}
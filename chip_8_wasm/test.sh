@@ -0,0 +1,5 @@
+#!/bin/bash
+
+set -ex
+
+wasm-pack test --headless --firefox
@@ -0,0 +1,90 @@
+//! Tests for the JavaScript-facing API, run in a headless browser with
+//! `wasm-pack test --headless --firefox` (or `--chrome`)
+
+#![cfg(target_arch = "wasm32")]
+
+use chip_8_wasm::WasmChip8;
+use js_sys::{Reflect, Uint8Array};
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::*;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+fn load(rom: &[u8]) -> WasmChip8 {
+    let mut chip_8 = WasmChip8::new();
+    chip_8.load_rom(Uint8Array::from(rom)).unwrap();
+    chip_8
+}
+
+fn register(chip_8: &WasmChip8, name: &str) -> f64 {
+    Reflect::get(&chip_8.registers(), &JsValue::from(name))
+        .unwrap()
+        .as_f64()
+        .unwrap()
+}
+
+fn framebuffer(chip_8: &WasmChip8) -> Vec<u8> {
+    let len = (chip_8.width() * chip_8.height()) as usize;
+    unsafe { std::slice::from_raw_parts(chip_8.framebuffer_ptr(), len) }.to_vec()
+}
+
+#[wasm_bindgen_test]
+fn draws_to_framebuffer() {
+    // Draw the "0" font sprite at (0, 0), then loop forever
+    let mut chip_8 = load(&[0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06]);
+    chip_8.run_frame(0).unwrap();
+
+    assert_eq!((chip_8.width(), chip_8.height()), (64, 32));
+    let pixels = framebuffer(&chip_8);
+    // Top row of "0" is 0xF0
+    assert_eq!(&pixels[0..5], &[1, 1, 1, 1, 0]);
+    // Second row is 0x90
+    assert_eq!(&pixels[64..69], &[1, 0, 0, 1, 0]);
+
+    assert_eq!(register(&chip_8, "pc"), 0x206 as f64);
+    assert_eq!(register(&chip_8, "i"), 0x50 as f64);
+}
+
+#[wasm_bindgen_test]
+fn reads_keys_bitmask() {
+    // Spin until key 5 is held, then halt at 0x206
+    let rom = [0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0x12, 0x06];
+    let mut chip_8 = load(&rom);
+
+    chip_8.run_frame(0).unwrap();
+    assert_ne!(register(&chip_8, "pc"), 0x206 as f64);
+
+    chip_8.run_frame(1 << 5).unwrap();
+    assert_eq!(register(&chip_8, "pc"), 0x206 as f64);
+}
+
+#[wasm_bindgen_test]
+fn save_and_load_state() {
+    let mut chip_8 = load(&[0x70, 0x01, 0x12, 0x00]);
+    chip_8.run_frame(0).unwrap();
+    let state = chip_8.save_state().unwrap();
+    let v0 = Reflect::get(&chip_8.registers(), &JsValue::from("v")).unwrap();
+    let v0 = Reflect::get(&v0, &JsValue::from(0)).unwrap().as_f64();
+
+    chip_8.run_frame(0).unwrap();
+    chip_8.load_state(state).unwrap();
+
+    let restored = Reflect::get(&chip_8.registers(), &JsValue::from("v")).unwrap();
+    let restored = Reflect::get(&restored, &JsValue::from(0)).unwrap().as_f64();
+    assert_eq!(restored, v0);
+
+    assert!(chip_8.load_state(Uint8Array::from(&[1, 2, 3][..])).is_err());
+}
+
+#[wasm_bindgen_test]
+fn rejects_oversized_rom() {
+    let mut chip_8 = WasmChip8::new();
+    assert!(chip_8.load_rom(Uint8Array::from(&[0u8; 4096][..])).is_err());
+}
+
+#[wasm_bindgen_test]
+fn stops_before_faults() {
+    let mut chip_8 = load(&[0x00, 0xEE]); // 200: RET with nothing on the stack
+    assert!(chip_8.run_frame(0).is_err());
+    assert_eq!(register(&chip_8, "pc"), 512.0);
+}